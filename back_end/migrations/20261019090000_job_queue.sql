@@ -0,0 +1,27 @@
+-- Background job queue
+CREATE TABLE jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    track_id UUID REFERENCES tracks(id) ON DELETE CASCADE,
+    status VARCHAR(50) NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+    last_error TEXT,
+    run_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_jobs_queued ON jobs (run_at) WHERE status = 'queued';
+CREATE INDEX idx_jobs_track_id ON jobs (track_id);
+
+CREATE TRIGGER update_jobs_updated_at
+BEFORE UPDATE ON jobs
+FOR EACH ROW EXECUTE PROCEDURE update_updated_at_column();
+
+-- Processing state of a fully uploaded track: uploaded -> processing -> ready / failed
+ALTER TABLE tracks ADD COLUMN processing_status VARCHAR(50);
+
+UPDATE tracks SET processing_status = 'ready' WHERE upload_status = 'complete';
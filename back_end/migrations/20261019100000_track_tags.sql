@@ -0,0 +1,9 @@
+-- Tags imported from the uploaded audio file
+ALTER TABLE tracks
+    ADD COLUMN album VARCHAR(255),
+    ADD COLUMN album_artist VARCHAR(255),
+    ADD COLUMN track_number INTEGER,
+    ADD COLUMN disc_number INTEGER,
+    ADD COLUMN release_year INTEGER,
+    ADD COLUMN genre VARCHAR(100),
+    ADD COLUMN isrc VARCHAR(12);
@@ -0,0 +1,15 @@
+-- Derived audio files generated from the original upload
+CREATE TABLE track_renditions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    quality VARCHAR(20) NOT NULL,
+    codec VARCHAR(20) NOT NULL,
+    mime_type VARCHAR(50) NOT NULL,
+    bitrate_kbps INTEGER,
+    sample_rate INTEGER NOT NULL,
+    channels INTEGER NOT NULL,
+    file_path VARCHAR(255) NOT NULL,
+    file_size BIGINT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (track_id, quality)
+);
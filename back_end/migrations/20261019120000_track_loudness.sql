@@ -0,0 +1,8 @@
+-- EBU R128 measurements, the basis for ReplayGain values
+ALTER TABLE tracks
+    ADD COLUMN loudness_lufs DOUBLE PRECISION,
+    ADD COLUMN loudness_range_lu DOUBLE PRECISION,
+    ADD COLUMN true_peak_dbtp DOUBLE PRECISION,
+    ADD COLUMN album_loudness_lufs DOUBLE PRECISION,
+    ADD COLUMN album_true_peak_dbtp DOUBLE PRECISION,
+    ADD COLUMN loudness_analyzed_at TIMESTAMP;
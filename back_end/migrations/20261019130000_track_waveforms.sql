@@ -0,0 +1,9 @@
+-- Peak data for drawing seek bars, stored in the audiowaveform .dat format
+CREATE TABLE track_waveforms (
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    resolution VARCHAR(20) NOT NULL,
+    samples_per_pixel INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (track_id, resolution)
+);
@@ -0,0 +1,33 @@
+-- Validated artwork, re-encoded into fixed square sizes under content-addressed names
+CREATE TABLE artworks (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    source_hash VARCHAR(64) NOT NULL UNIQUE,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE artwork_variants (
+    artwork_id UUID NOT NULL REFERENCES artworks(id) ON DELETE CASCADE,
+    size INTEGER NOT NULL,
+    format VARCHAR(10) NOT NULL,
+    file_name VARCHAR(100) NOT NULL,
+    file_size BIGINT NOT NULL,
+    PRIMARY KEY (artwork_id, size, format)
+);
+
+ALTER TABLE tracks ADD COLUMN artwork_id UUID REFERENCES artworks(id) ON DELETE SET NULL;
+ALTER TABLE playlists ADD COLUMN artwork_id UUID REFERENCES artworks(id) ON DELETE SET NULL;
+
+-- URLs of every variant of an artwork, for embedding in track and playlist listings
+CREATE FUNCTION artwork_urls(artwork UUID) RETURNS JSONB AS $$
+    SELECT COALESCE(
+        jsonb_agg(
+            jsonb_build_object('size', size, 'format', format, 'url', '/api/assets/artwork/' || file_name)
+            ORDER BY size, format
+        ),
+        '[]'::jsonb
+    )
+    FROM artwork_variants
+    WHERE artwork_id = artwork
+$$ LANGUAGE SQL STABLE;
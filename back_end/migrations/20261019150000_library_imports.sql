@@ -0,0 +1,17 @@
+-- Files brought in by the bulk import command, so an interrupted run can resume
+CREATE TABLE library_imports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    source_path TEXT NOT NULL,
+    source_size BIGINT NOT NULL,
+    track_id UUID REFERENCES tracks(id) ON DELETE SET NULL,
+    status VARCHAR(20) NOT NULL,
+    error TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, source_path)
+);
+
+CREATE TRIGGER update_library_imports_updated_at
+BEFORE UPDATE ON library_imports
+FOR EACH ROW EXECUTE PROCEDURE update_updated_at_column();
@@ -0,0 +1,15 @@
+-- Per-entry outcome of an album uploaded as a ZIP archive, the archive itself is a row in tracks
+CREATE TABLE archive_entries (
+    archive_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    entry_name TEXT NOT NULL,
+    track_id UUID REFERENCES tracks(id) ON DELETE SET NULL,
+    status VARCHAR(20) NOT NULL,
+    error TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (archive_id, entry_name)
+);
+
+CREATE TRIGGER update_archive_entries_updated_at
+BEFORE UPDATE ON archive_entries
+FOR EACH ROW EXECUTE PROCEDURE update_updated_at_column();
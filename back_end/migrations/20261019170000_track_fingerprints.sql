@@ -0,0 +1,35 @@
+-- Admins review duplicate clusters, everyone else is a regular user
+ALTER TABLE users
+    ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin'));
+
+-- Acoustic fingerprint of the first two minutes of a track
+CREATE TABLE track_fingerprints (
+    track_id UUID PRIMARY KEY REFERENCES tracks(id) ON DELETE CASCADE,
+    fingerprint INTEGER[] NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Inverted index from coarse fingerprint terms to tracks, to find candidates
+-- without comparing every fingerprint
+CREATE TABLE fingerprint_terms (
+    term INTEGER NOT NULL,
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    PRIMARY KEY (term, track_id)
+);
+
+CREATE INDEX idx_fingerprint_terms_track_id ON fingerprint_terms(track_id);
+
+-- Pairs of tracks that most likely are the same recording
+CREATE TABLE track_duplicates (
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    duplicate_of UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    similarity REAL NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (track_id, duplicate_of)
+);
+
+CREATE INDEX idx_track_duplicates_duplicate_of ON track_duplicates(duplicate_of);
+
+-- Set by the link policy, the track then refers to the copy that was there first
+ALTER TABLE tracks
+    ADD COLUMN duplicate_of UUID REFERENCES tracks(id) ON DELETE SET NULL;
@@ -0,0 +1,9 @@
+-- Audio format detected at ingest, streamed with the matching Content-Type
+ALTER TABLE tracks
+    ADD COLUMN container VARCHAR(10),
+    ADD COLUMN codec VARCHAR(20),
+    ADD COLUMN mime_type VARCHAR(50),
+    ADD COLUMN sample_rate INTEGER,
+    ADD COLUMN bit_depth INTEGER,
+    ADD COLUMN channels INTEGER,
+    ADD COLUMN bitrate_kbps INTEGER;
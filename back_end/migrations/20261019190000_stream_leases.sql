@@ -0,0 +1,15 @@
+-- Plan tier, decides how many devices may stream at once and how fast
+ALTER TABLE users
+    ADD COLUMN tier VARCHAR(20) NOT NULL DEFAULT 'free' CHECK (tier IN ('free', 'premium'));
+
+-- One row per device currently streaming, renewed while it keeps playing
+CREATE TABLE stream_leases (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    session_key VARCHAR(64) NOT NULL,
+    track_id UUID REFERENCES tracks(id) ON DELETE SET NULL,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, session_key)
+);
+
+CREATE INDEX idx_stream_leases_expires_at ON stream_leases(expires_at);
@@ -0,0 +1,19 @@
+-- Licenses a track can be published under, some let anyone download the file
+CREATE TABLE licenses (
+    code VARCHAR(20) PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    allows_download BOOLEAN NOT NULL
+);
+
+INSERT INTO licenses (code, name, allows_download) VALUES
+    ('all-rights-reserved', 'All rights reserved', false),
+    ('cc0', 'CC0 1.0 Public Domain Dedication', true),
+    ('cc-by', 'CC BY 4.0', true),
+    ('cc-by-sa', 'CC BY-SA 4.0', true),
+    ('cc-by-nd', 'CC BY-ND 4.0', true),
+    ('cc-by-nc', 'CC BY-NC 4.0', true),
+    ('cc-by-nc-sa', 'CC BY-NC-SA 4.0', true),
+    ('cc-by-nc-nd', 'CC BY-NC-ND 4.0', true);
+
+ALTER TABLE tracks
+    ADD COLUMN license VARCHAR(20) NOT NULL DEFAULT 'all-rights-reserved' REFERENCES licenses(code);
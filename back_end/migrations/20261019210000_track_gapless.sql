@@ -0,0 +1,5 @@
+-- Encoder delay and padding found at ingest, in samples per channel, for gapless playback
+ALTER TABLE tracks
+    ADD COLUMN encoder_delay INTEGER,
+    ADD COLUMN encoder_padding INTEGER,
+    ADD COLUMN total_samples BIGINT;
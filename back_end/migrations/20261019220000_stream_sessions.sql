@@ -0,0 +1,27 @@
+-- One row per playback of a track on a device, the range requests of a
+-- player are merged into it. Plays are counted from these, not from clients.
+CREATE TABLE stream_sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    session_key VARCHAR(64) NOT NULL,
+    -- Offsets below are into this file, the original, a rendition or a transcode
+    file_key VARCHAR(255) NOT NULL,
+    -- Estimated from the bitrate while a transcode is still being encoded
+    file_size BIGINT,
+    requests INTEGER NOT NULL DEFAULT 1,
+    requested_ranges INT8MULTIRANGE NOT NULL DEFAULT '{}',
+    delivered_ranges INT8MULTIRANGE NOT NULL DEFAULT '{}',
+    -- Repeated ranges count again
+    bytes_served BIGINT NOT NULL DEFAULT 0,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Set once enough of the track was delivered to count as a play
+    counted_at TIMESTAMP
+);
+
+CREATE INDEX idx_stream_sessions_device ON stream_sessions(user_id, session_key, track_id, ended_at);
+CREATE INDEX idx_stream_sessions_track_id ON stream_sessions(track_id);
+
+ALTER TABLE tracks
+    ADD COLUMN play_count BIGINT NOT NULL DEFAULT 0;
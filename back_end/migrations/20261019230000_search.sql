@@ -0,0 +1,38 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+-- unaccent() is only STABLE, generated columns and indexes need an IMMUTABLE function
+CREATE FUNCTION search_normalize(value TEXT) RETURNS TEXT AS $$
+    SELECT lower(public.unaccent('public.unaccent'::regdictionary, value))
+$$ LANGUAGE SQL IMMUTABLE PARALLEL SAFE STRICT;
+
+-- The 'simple' configuration doesn't stem, titles are in many languages.
+-- search_vector serves ranked full-text matches, search_text typo tolerant trigram ones.
+ALTER TABLE tracks
+    ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', search_normalize(COALESCE(title, ''))), 'A')
+        || setweight(to_tsvector('simple', search_normalize(COALESCE(artist, ''))), 'B')
+        || setweight(to_tsvector('simple', search_normalize(COALESCE(album, ''))), 'C')
+    ) STORED,
+    ADD COLUMN search_text TEXT GENERATED ALWAYS AS (
+        search_normalize(COALESCE(title, '') || ' ' || COALESCE(artist, ''))
+    ) STORED;
+
+ALTER TABLE playlists
+    ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+        to_tsvector('simple', search_normalize(title))
+    ) STORED,
+    ADD COLUMN search_text TEXT GENERATED ALWAYS AS (search_normalize(title)) STORED;
+
+ALTER TABLE users
+    ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+        to_tsvector('simple', search_normalize(username))
+    ) STORED,
+    ADD COLUMN search_text TEXT GENERATED ALWAYS AS (search_normalize(username)) STORED;
+
+CREATE INDEX idx_tracks_search_vector ON tracks USING GIN (search_vector);
+CREATE INDEX idx_tracks_search_text ON tracks USING GIN (search_text gin_trgm_ops);
+CREATE INDEX idx_playlists_search_vector ON playlists USING GIN (search_vector);
+CREATE INDEX idx_playlists_search_text ON playlists USING GIN (search_text gin_trgm_ops);
+CREATE INDEX idx_users_search_vector ON users USING GIN (search_vector);
+CREATE INDEX idx_users_search_text ON users USING GIN (search_text gin_trgm_ops);
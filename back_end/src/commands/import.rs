@@ -0,0 +1,218 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    commands::find_user,
+    databases::{imports::ImportExt, jobs::JobExt, upload::UploadExt},
+    jobs::upload::import_track_file,
+    media,
+    storage::WorkingFile,
+    AppState,
+};
+
+#[derive(Debug, Default, Serialize)]
+struct ImportReport {
+    directory: String,
+    user: String,
+    imported: Vec<ImportedFile>,
+    skipped: Vec<SkippedFile>,
+    failed: Vec<FailedFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportedFile {
+    path: String,
+    track_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct SkippedFile {
+    path: String,
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FailedFile {
+    path: String,
+    error: String,
+}
+
+enum Outcome {
+    Imported(Uuid),
+    Skipped(&'static str),
+}
+
+/// Imports every audio file below `directory` as tracks owned by `user`.
+///
+/// Progress is kept in `library_imports`, running the command again skips
+/// what was already imported and retries what failed or was interrupted.
+pub async fn import_library(
+    app_state: &AppState,
+    directory: &str,
+    user: &str,
+    report_path: Option<&str>,
+) -> Result<(), String> {
+    let user_id = find_user(app_state, user).await?;
+
+    let root = fs::canonicalize(directory).map_err(|e| format!("{}: {}", directory, e))?;
+    let mut files = Vec::new();
+    collect_files(&root, &mut files).map_err(|e| e.to_string())?;
+    files.sort();
+
+    let mut report = ImportReport {
+        directory: root.display().to_string(),
+        user: user.to_string(),
+        ..Default::default()
+    };
+
+    for (index, file) in files.iter().enumerate() {
+        let path = file.display().to_string();
+        println!("[{}/{}] {}", index + 1, files.len(), path);
+
+        match import_file(app_state, user_id, file).await {
+            Ok(Outcome::Imported(track_id)) => report.imported.push(ImportedFile { path, track_id }),
+            Ok(Outcome::Skipped(reason)) => report.skipped.push(SkippedFile { path, reason: reason.to_string() }),
+            Err(error) => {
+                eprintln!("Failed to import {}: {}", path, error);
+                report.failed.push(FailedFile { path, error });
+            }
+        }
+    }
+
+    let report_path = match report_path {
+        Some(report_path) => report_path.to_string(),
+        None => format!("import-report-{}.json", chrono::Utc::now().format("%Y%m%d%H%M%S")),
+    };
+    let report_json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    fs::write(&report_path, report_json).map_err(|e| e.to_string())?;
+
+    println!(
+        "✅ Imported {}, skipped {}, failed {}. Report written to {}",
+        report.imported.len(),
+        report.skipped.len(),
+        report.failed.len(),
+        report_path
+    );
+
+    Ok(())
+}
+
+/// Regular files below `dir`, hidden entries and symlinks are left out.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+async fn import_file(app_state: &AppState, user_id: Uuid, source: &Path) -> Result<Outcome, String> {
+    let source_path = source.display().to_string();
+    let source_size = fs::metadata(source).map_err(|e| e.to_string())?.len() as i64;
+
+    let Some(container) = media::sniff_file(&source_path).map_err(|e| e.to_string())? else {
+        return Ok(Outcome::Skipped("not an audio file"));
+    };
+
+    if let Some(previous) = app_state.db_client
+        .get_previous_import(user_id, &source_path)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        if previous.track_ready && previous.source_size == source_size {
+            // The run that imported it may have stopped before recording it
+            app_state.db_client
+                .finish_import(user_id, &source_path, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            return Ok(Outcome::Skipped("already imported"));
+        }
+
+        // Left over from an interrupted or failed attempt
+        if let Some(track_id) = previous.track_id {
+            app_state.db_client
+                .discard_import_track(track_id)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    let file_name = format!("{}.{}", Uuid::new_v4(), container.extension());
+    let track_id = app_state.db_client
+        .upload_file(user_id, &file_name)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Not a pending upload, the garbage collector leaves it alone
+    app_state.db_client
+        .set_processing_status(track_id, "processing")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    app_state.db_client
+        .start_import(user_id, &source_path, source_size, track_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let key = format!("uploads/{}", file_name);
+    let result = process_file(app_state, track_id, source, &key, &file_name).await;
+
+    if let Err(error) = &result {
+        let _ = app_state.storage.delete(&key).await;
+        app_state.db_client
+            .discard_import_track(track_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        app_state.db_client
+            .finish_import(user_id, &source_path, Some(error))
+            .await
+            .map_err(|e| e.to_string())?;
+        return Err(error.clone());
+    }
+
+    app_state.db_client
+        .finish_import(user_id, &source_path, None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Outcome::Imported(track_id))
+}
+
+async fn process_file(
+    app_state: &AppState,
+    track_id: Uuid,
+    source: &Path,
+    key: &str,
+    file_name: &str,
+) -> Result<(), String> {
+    let working_file = WorkingFile::for_key(app_state, key).map_err(|e| e.to_string())?;
+    tokio::fs::copy(source, working_file.path())
+        .await
+        .map_err(|e| e.to_string())?;
+    working_file
+        .persist(app_state, key)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Files without a title tag are named after themselves
+    let fallback_title = source.file_stem().map(|stem| stem.to_string_lossy().to_string());
+
+    import_track_file(app_state, track_id, file_name, &working_file, fallback_title)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,153 @@
+mod import;
+
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::{
+    databases::{fingerprints::FingerprintExt, loudness::LoudnessExt, users::UserExt},
+    jobs::{self, gc, JobPayload},
+    AppState,
+};
+
+const USAGE: &str = "Usage: backend [backfill-loudness | backfill-fingerprints | grant-admin <username or email> | set-tier <username or email> <free | premium> | gc [--dry-run] | import <directory> --user <username or email> [--report <file>]]";
+
+/// Runs a maintenance command instead of the server, returns the process exit code.
+pub async fn run(args: &[String], app_state: Arc<AppState>) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("backfill-loudness") => backfill_loudness(&app_state).await,
+        Some("backfill-fingerprints") => backfill_fingerprints(&app_state).await,
+        Some("grant-admin") => match args.get(1) {
+            Some(user) => grant_admin(&app_state, user).await,
+            None => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        Some("set-tier") => match (args.get(1), args.get(2).map(String::as_str)) {
+            (Some(user), Some(tier @ ("free" | "premium"))) => set_tier(&app_state, user, tier).await,
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        Some("gc") => collect_garbage(&app_state, args[1..].iter().any(|arg| arg == "--dry-run")).await,
+        Some("import") => match (args.get(1), option_value(args, "--user")) {
+            (Some(directory), Some(user)) if !directory.starts_with("--") => {
+                import::import_library(&app_state, directory, user, option_value(args, "--report")).await
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("🔥 {}", e);
+            1
+        }
+    }
+}
+
+/// Value following `name` on the command line, e.g. `--user alice`.
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+async fn find_user(app_state: &AppState, user: &str) -> Result<Uuid, String> {
+    let (username, email) = if user.contains('@') { (None, Some(user)) } else { (Some(user), None) };
+
+    app_state.db_client
+        .get_user(None, username, email)
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|user| user.id)
+        .ok_or_else(|| format!("User {} not found", user))
+}
+
+/// Queues a loudness analysis for every ready track that has never been measured.
+/// The jobs are picked up by the workers of a running server.
+async fn backfill_loudness(app_state: &AppState) -> Result<(), String> {
+    let tracks = app_state.db_client
+        .get_tracks_without_loudness()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for (track_id, file_name) in &tracks {
+        let payload = JobPayload::AnalyzeLoudness {
+            track_id: *track_id,
+            file_name: file_name.clone(),
+        };
+        jobs::enqueue(app_state, *track_id, payload).await?;
+    }
+
+    println!("✅ Queued loudness analysis for {} tracks", tracks.len());
+
+    Ok(())
+}
+
+/// Queues fingerprinting for every ready track uploaded before fingerprints
+/// existed, so it can be matched against new uploads.
+async fn backfill_fingerprints(app_state: &AppState) -> Result<(), String> {
+    let tracks = app_state.db_client
+        .get_tracks_without_fingerprint()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for (track_id, file_name) in &tracks {
+        let payload = JobPayload::FingerprintTrack {
+            track_id: *track_id,
+            file_name: file_name.clone(),
+        };
+        jobs::enqueue(app_state, *track_id, payload).await?;
+    }
+
+    println!("✅ Queued fingerprinting for {} tracks", tracks.len());
+
+    Ok(())
+}
+
+/// Lets a user review duplicates, there is no API for handing out roles.
+async fn grant_admin(app_state: &AppState, user: &str) -> Result<(), String> {
+    let user_id = find_user(app_state, user).await?;
+
+    let user = app_state.db_client
+        .update_user_role(user_id, "admin")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    println!("✅ {} is now an admin", user.username);
+
+    Ok(())
+}
+
+/// Moves a user to another plan tier, which sets their streaming limits.
+async fn set_tier(app_state: &AppState, user: &str, tier: &str) -> Result<(), String> {
+    let user_id = find_user(app_state, user).await?;
+
+    let user = app_state.db_client
+        .update_user_tier(user_id, tier)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    println!("✅ {} is now on the {} plan", user.username, user.tier);
+
+    Ok(())
+}
+
+async fn collect_garbage(app_state: &AppState, dry_run: bool) -> Result<(), String> {
+    let report = gc::sweep(app_state, dry_run).await?;
+    gc::log_report(&report);
+
+    Ok(())
+}
@@ -1,9 +1,80 @@
+/// Where uploads, renditions and artwork are kept, see `storage`.
+#[derive(Debug, Clone)]
+pub enum StorageConfig {
+    Local {
+        root: String,
+    },
+    S3 {
+        bucket: String,
+        region: String,
+        /// Set for S3 compatible services such as MinIO
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+    },
+}
+
+/// What happens to an upload that sounds like a track already in the library.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep the upload and tell the uploader about the matches
+    Warn,
+    /// Like warn, and the upload also points at the track it duplicates
+    Link,
+    /// Fail the upload
+    Reject,
+}
+
+/// What a plan tier may do when streaming.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamLimits {
+    /// Devices that may stream at the same time
+    pub max_streams: i64,
+    /// Cap per connection in kilobits per second, 0 for none
+    pub bandwidth_kbps: u64,
+}
+
 #[derive(Debug,Clone)]
 pub struct Config{
     pub database_url: String,
     pub jwt_secret_key: String,
     pub jwt_maxage: i64,
     pub port: u16,
+    pub worker_concurrency: usize,
+    pub job_max_attempts: i32,
+    pub min_track_seconds: u64,
+    pub max_track_seconds: u64,
+    pub max_sample_rate: u32,
+    pub max_channels: usize,
+    /// The ffmpeg binary, required for MP3 renditions, HLS and MP3 transcoding
+    pub ffmpeg_path: Option<String>,
+    pub upload_ttl_hours: u64,
+    pub gc_interval_minutes: u64,
+    pub gc_dry_run: bool,
+    pub archive_max_entries: usize,
+    pub archive_max_unpacked_mb: u64,
+    pub storage: StorageConfig,
+    /// Scratch space for stored files that have to be decoded locally
+    pub storage_work_dir: String,
+    pub duplicate_policy: DuplicatePolicy,
+    /// Share of matching fingerprint bits from which two tracks count as duplicates
+    pub duplicate_min_similarity: f64,
+    /// Key for signed stream URLs, the JWT secret unless set
+    pub url_signing_key: String,
+    /// Longest lifetime of a signed URL, also the default
+    pub signed_url_ttl_seconds: u64,
+    pub free_stream_limits: StreamLimits,
+    pub premium_stream_limits: StreamLimits,
+    /// How long a device counts as streaming after its last request
+    pub stream_lease_seconds: u64,
+    /// Disk space for transcoded tracks, the least recently played go first
+    pub transcode_cache_mb: u64,
+    /// Transcodes running at the same time, more are turned away
+    pub transcode_concurrency: usize,
+    /// Seconds of audio a stream session has to deliver to count as a play
+    pub play_min_seconds: u64,
+    /// Share of the file that counts as a play as well, for short tracks
+    pub play_min_percent: u64,
 }
 
 impl Config {
@@ -11,12 +82,96 @@ impl Config {
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not found");
         let jwt_secret_key = std::env::var("JWT_SECRET_KEY").expect("JWT_SECRET_KEY not found");
         let jwt_maxage = std::env::var("JWT_MAXAGE").expect("JWT_MAXAGE not found");
+        let worker_concurrency = std::env::var("WORKER_CONCURRENCY").unwrap_or_else(|_| "2".to_string());
+        let job_max_attempts = std::env::var("JOB_MAX_ATTEMPTS").unwrap_or_else(|_| "5".to_string());
+        let min_track_seconds = std::env::var("MIN_TRACK_SECONDS").unwrap_or_else(|_| "1".to_string());
+        let max_track_seconds = std::env::var("MAX_TRACK_SECONDS").unwrap_or_else(|_| "14400".to_string());
+        let max_sample_rate = std::env::var("MAX_SAMPLE_RATE").unwrap_or_else(|_| "192000".to_string());
+        let max_channels = std::env::var("MAX_CHANNELS").unwrap_or_else(|_| "8".to_string());
+        let ffmpeg_path = std::env::var("FFMPEG_PATH").ok();
+        let upload_ttl_hours = std::env::var("UPLOAD_TTL_HOURS").unwrap_or_else(|_| "24".to_string());
+        let gc_interval_minutes = std::env::var("GC_INTERVAL_MINUTES").unwrap_or_else(|_| "60".to_string());
+        let gc_dry_run = std::env::var("GC_DRY_RUN").unwrap_or_else(|_| "false".to_string());
+        let archive_max_entries = std::env::var("ARCHIVE_MAX_ENTRIES").unwrap_or_else(|_| "200".to_string());
+        let archive_max_unpacked_mb = std::env::var("ARCHIVE_MAX_UNPACKED_MB").unwrap_or_else(|_| "4096".to_string());
+        let storage_backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+        let storage = match storage_backend.as_str() {
+            "local" => StorageConfig::Local {
+                root: std::env::var("STORAGE_LOCAL_ROOT").unwrap_or_else(|_| ".".to_string()),
+            },
+            "s3" => StorageConfig::S3 {
+                bucket: std::env::var("S3_BUCKET").expect("S3_BUCKET not found"),
+                region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                endpoint: std::env::var("S3_ENDPOINT").ok(),
+                access_key_id: std::env::var("S3_ACCESS_KEY_ID").ok(),
+                secret_access_key: std::env::var("S3_SECRET_ACCESS_KEY").ok(),
+            },
+            other => panic!("Unknown STORAGE_BACKEND {}, expected local or s3", other),
+        };
+        let storage_work_dir = std::env::var("STORAGE_WORK_DIR")
+            .unwrap_or_else(|_| std::env::temp_dir().join("music_platform").display().to_string());
+        let duplicate_policy = match std::env::var("DUPLICATE_POLICY").unwrap_or_else(|_| "warn".to_string()).as_str() {
+            "warn" => DuplicatePolicy::Warn,
+            "link" => DuplicatePolicy::Link,
+            "reject" => DuplicatePolicy::Reject,
+            other => panic!("Unknown DUPLICATE_POLICY {}, expected warn, link or reject", other),
+        };
+        let duplicate_min_similarity = std::env::var("DUPLICATE_MIN_SIMILARITY").unwrap_or_else(|_| "0.65".to_string());
+        let url_signing_key = std::env::var("URL_SIGNING_KEY").unwrap_or_else(|_| jwt_secret_key.clone());
+        let signed_url_ttl_seconds = std::env::var("SIGNED_URL_TTL_SECONDS").unwrap_or_else(|_| "900".to_string());
+        let free_max_streams = std::env::var("FREE_MAX_STREAMS").unwrap_or_else(|_| "1".to_string());
+        let free_stream_kbps = std::env::var("FREE_STREAM_KBPS").unwrap_or_else(|_| "2048".to_string());
+        let premium_max_streams = std::env::var("PREMIUM_MAX_STREAMS").unwrap_or_else(|_| "4".to_string());
+        let premium_stream_kbps = std::env::var("PREMIUM_STREAM_KBPS").unwrap_or_else(|_| "0".to_string());
+        let stream_lease_seconds = std::env::var("STREAM_LEASE_SECONDS").unwrap_or_else(|_| "120".to_string());
+        let transcode_cache_mb = std::env::var("TRANSCODE_CACHE_MB").unwrap_or_else(|_| "2048".to_string());
+        let transcode_concurrency = std::env::var("TRANSCODE_CONCURRENCY").unwrap_or_else(|_| "2".to_string());
+        let play_min_seconds = std::env::var("PLAY_MIN_SECONDS").unwrap_or_else(|_| "30".to_string());
+        let play_min_percent = std::env::var("PLAY_MIN_PERCENT").unwrap_or_else(|_| "50".to_string());
 
         Config{
             database_url,
             jwt_secret_key,
             jwt_maxage: jwt_maxage.parse::<i64>().unwrap(),
             port: 8000,
+            worker_concurrency: worker_concurrency.parse::<usize>().unwrap(),
+            job_max_attempts: job_max_attempts.parse::<i32>().unwrap(),
+            min_track_seconds: min_track_seconds.parse::<u64>().unwrap(),
+            max_track_seconds: max_track_seconds.parse::<u64>().unwrap(),
+            max_sample_rate: max_sample_rate.parse::<u32>().unwrap(),
+            max_channels: max_channels.parse::<usize>().unwrap(),
+            ffmpeg_path,
+            upload_ttl_hours: upload_ttl_hours.parse::<u64>().unwrap(),
+            gc_interval_minutes: gc_interval_minutes.parse::<u64>().unwrap(),
+            gc_dry_run: gc_dry_run.parse::<bool>().unwrap(),
+            archive_max_entries: archive_max_entries.parse::<usize>().unwrap(),
+            archive_max_unpacked_mb: archive_max_unpacked_mb.parse::<u64>().unwrap(),
+            storage,
+            storage_work_dir,
+            duplicate_policy,
+            duplicate_min_similarity: duplicate_min_similarity.parse::<f64>().unwrap(),
+            url_signing_key,
+            signed_url_ttl_seconds: signed_url_ttl_seconds.parse::<u64>().unwrap(),
+            free_stream_limits: StreamLimits {
+                max_streams: free_max_streams.parse::<i64>().unwrap(),
+                bandwidth_kbps: free_stream_kbps.parse::<u64>().unwrap(),
+            },
+            premium_stream_limits: StreamLimits {
+                max_streams: premium_max_streams.parse::<i64>().unwrap(),
+                bandwidth_kbps: premium_stream_kbps.parse::<u64>().unwrap(),
+            },
+            stream_lease_seconds: stream_lease_seconds.parse::<u64>().unwrap(),
+            transcode_cache_mb: transcode_cache_mb.parse::<u64>().unwrap(),
+            transcode_concurrency: transcode_concurrency.parse::<usize>().unwrap(),
+            play_min_seconds: play_min_seconds.parse::<u64>().unwrap(),
+            play_min_percent: play_min_percent.parse::<u64>().unwrap(),
+        }
+    }
+
+    pub fn stream_limits(&self, tier: &str) -> StreamLimits {
+        match tier {
+            "premium" => self.premium_stream_limits,
+            _ => self.free_stream_limits,
         }
     }
 
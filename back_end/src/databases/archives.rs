@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, dtos::ArchiveEntryDto};
+
+#[async_trait]
+pub trait ArchiveExt {
+    async fn get_archive_owner(&self, archive_id: Uuid) -> Result<Option<Uuid>, sqlx::Error>;
+
+    async fn get_archive_entries(&self, archive_id: Uuid) -> Result<Vec<ArchiveEntryDto>, sqlx::Error>;
+
+    async fn save_archive_entry(
+        &self,
+        archive_id: Uuid,
+        entry_name: &str,
+        track_id: Option<Uuid>,
+        status: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl ArchiveExt for DBClients {
+    async fn get_archive_owner(&self, archive_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+        let archive = sqlx::query!(
+            r#"
+            SELECT user_id FROM tracks WHERE id = $1
+            "#,
+            archive_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(archive.and_then(|archive| archive.user_id))
+    }
+
+    async fn get_archive_entries(&self, archive_id: Uuid) -> Result<Vec<ArchiveEntryDto>, sqlx::Error> {
+        let entries = sqlx::query_as!(
+            ArchiveEntryDto,
+            r#"
+            SELECT entry_name, status, track_id, error
+            FROM archive_entries
+            WHERE archive_id = $1
+            ORDER BY entry_name
+            "#,
+            archive_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn save_archive_entry(
+        &self,
+        archive_id: Uuid,
+        entry_name: &str,
+        track_id: Option<Uuid>,
+        status: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO archive_entries (archive_id, entry_name, track_id, status, error)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (archive_id, entry_name) DO UPDATE
+            SET track_id = EXCLUDED.track_id,
+                status = EXCLUDED.status,
+                error = EXCLUDED.error
+            "#,
+            archive_id,
+            entry_name,
+            track_id,
+            status,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
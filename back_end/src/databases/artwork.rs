@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, media::artwork::ProcessedArtwork};
+
+#[async_trait]
+pub trait ArtworkExt {
+    async fn save_artwork(&self, artwork: &ProcessedArtwork) -> Result<Uuid, sqlx::Error>;
+}
+
+#[async_trait]
+impl ArtworkExt for DBClients {
+    async fn save_artwork(&self, artwork: &ProcessedArtwork) -> Result<Uuid, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Uploading the same image again reuses the existing artwork, touching it
+        // keeps the sweeper away until the caller has linked it
+        let artwork_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO artworks (source_hash, width, height)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (source_hash) DO UPDATE
+            SET created_at = Now()
+            RETURNING id
+            "#,
+            artwork.source_hash,
+            artwork.width as i32,
+            artwork.height as i32
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for variant in &artwork.variants {
+            sqlx::query!(
+                r#"
+                INSERT INTO artwork_variants (artwork_id, size, format, file_name, file_size)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (artwork_id, size, format) DO NOTHING
+                "#,
+                artwork_id,
+                variant.size as i32,
+                variant.format.as_str(),
+                variant.file_name,
+                variant.data.len() as i64
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(artwork_id)
+    }
+}
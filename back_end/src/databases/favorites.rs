@@ -1,7 +1,8 @@
 use async_trait::async_trait;
+use sqlx::types::Json;
 use uuid::Uuid;
 
-use crate::{dbs::DBClients, dtos::TrackDto};
+use crate::{dbs::DBClients, dtos::{ArtworkUrlDto, TrackDto}};
 
 #[async_trait]
 pub trait FavoriteExt {
@@ -76,10 +77,36 @@ impl FavoriteExt for DBClients {
                 t.id,
                 t.title,
                 t.artist,
+                t.album,
+                t.album_artist,
+                md5(t.user_id::text || '/' || t.album) AS album_group,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                t.loudness_lufs,
+                t.true_peak_dbtp,
+                t.album_loudness_lufs,
+                t.album_true_peak_dbtp,
+                t.container,
+                t.codec,
+                t.mime_type,
+                t.sample_rate,
+                t.bit_depth,
+                t.channels,
+                t.bitrate_kbps,
+                t.encoder_delay,
+                t.encoder_padding,
+                t.total_samples,
+                t.play_count,
+                t.license,
+                (SELECT allows_download FROM licenses WHERE code = t.license) AS "license_allows_download!",
                 t.duration,
                 t.file_name,
                 t.upload_status,
                 t.thumbnail_name,
+                artwork_urls(t.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
                 true AS is_favorite,
                 COALESCE(ph.played_at, NULL) AS played_at,
                 COALESCE(ph.duration_played, INTERVAL '0 seconds') AS duration_played,  -- Default to 0 if no playback history
@@ -93,7 +120,7 @@ impl FavoriteExt for DBClients {
             WHERE 
                 uf.user_id = $1
             AND 
-                t.upload_status = 'complete'
+                t.processing_status = 'ready'
             "#,
             user_id
         )
@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, dtos::DuplicateDto, media::fingerprint::Fingerprint};
+
+/// How many tracks sharing the most terms with a fingerprint are compared in full.
+const MAX_CANDIDATES: i64 = 20;
+
+#[async_trait]
+pub trait FingerprintExt {
+    async fn save_fingerprint(&self, track_id: Uuid, fingerprint: &Fingerprint) -> Result<(), sqlx::Error>;
+
+    /// Fingerprints of the ready tracks sharing the most terms with `terms`.
+    async fn get_fingerprint_candidates(
+        &self,
+        track_id: Uuid,
+        terms: &[i32],
+    ) -> Result<Vec<(Uuid, Vec<u32>)>, sqlx::Error>;
+
+    /// Records the likely duplicates of `track_id`, with `duplicate_of` set
+    /// it also points the track at that one.
+    async fn save_duplicates(
+        &self,
+        track_id: Uuid,
+        duplicates: &[(Uuid, f64)],
+        duplicate_of: Option<Uuid>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_track_duplicates(&self, track_id: Uuid) -> Result<Vec<DuplicateDto>, sqlx::Error>;
+
+    /// Every recorded duplicate pair, for grouping them into clusters.
+    async fn get_duplicate_pairs(&self) -> Result<Vec<(Uuid, Uuid, f64)>, sqlx::Error>;
+
+    async fn get_duplicate_tracks(&self, track_ids: &[Uuid]) -> Result<Vec<DuplicateDto>, sqlx::Error>;
+
+    async fn get_tracks_without_fingerprint(&self) -> Result<Vec<(Uuid, String)>, sqlx::Error>;
+}
+
+#[async_trait]
+impl FingerprintExt for DBClients {
+    async fn save_fingerprint(&self, track_id: Uuid, fingerprint: &Fingerprint) -> Result<(), sqlx::Error> {
+        // Postgres has no unsigned integers, the bits are stored as they are
+        let items: Vec<i32> = fingerprint.items.iter().map(|item| *item as i32).collect();
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO track_fingerprints (track_id, fingerprint)
+            VALUES ($1, $2)
+            ON CONFLICT (track_id) DO UPDATE
+            SET fingerprint = EXCLUDED.fingerprint,
+                created_at = Now()
+            "#,
+            track_id,
+            &items
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM fingerprint_terms WHERE track_id = $1
+            "#,
+            track_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO fingerprint_terms (term, track_id)
+            SELECT term, $1 FROM UNNEST($2::int[]) AS term
+            ON CONFLICT DO NOTHING
+            "#,
+            track_id,
+            &fingerprint.terms
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_fingerprint_candidates(
+        &self,
+        track_id: Uuid,
+        terms: &[i32],
+    ) -> Result<Vec<(Uuid, Vec<u32>)>, sqlx::Error> {
+        let candidates = sqlx::query!(
+            r#"
+            WITH shared AS (
+                SELECT ft.track_id, COUNT(*) AS shared_terms
+                FROM fingerprint_terms ft
+                WHERE ft.term = ANY($2) AND ft.track_id <> $1
+                GROUP BY ft.track_id
+            )
+            SELECT f.track_id, f.fingerprint
+            FROM shared s
+            JOIN track_fingerprints f ON f.track_id = s.track_id
+            JOIN tracks t ON t.id = s.track_id
+            WHERE t.processing_status = 'ready'
+            ORDER BY s.shared_terms DESC
+            LIMIT $3
+            "#,
+            track_id,
+            terms,
+            MAX_CANDIDATES
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(candidates
+            .into_iter()
+            .map(|candidate| {
+                let items = candidate.fingerprint.into_iter().map(|item| item as u32).collect();
+                (candidate.track_id, items)
+            })
+            .collect())
+    }
+
+    async fn save_duplicates(
+        &self,
+        track_id: Uuid,
+        duplicates: &[(Uuid, f64)],
+        duplicate_of: Option<Uuid>,
+    ) -> Result<(), sqlx::Error> {
+        let duplicate_ids: Vec<Uuid> = duplicates.iter().map(|(id, _)| *id).collect();
+        let similarities: Vec<f32> = duplicates.iter().map(|(_, similarity)| *similarity as f32).collect();
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO track_duplicates (track_id, duplicate_of, similarity)
+            SELECT $1, duplicate_of, similarity
+            FROM UNNEST($2::uuid[], $3::real[]) AS d(duplicate_of, similarity)
+            ON CONFLICT (track_id, duplicate_of) DO UPDATE
+            SET similarity = EXCLUDED.similarity
+            "#,
+            track_id,
+            &duplicate_ids,
+            &similarities
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if duplicate_of.is_some() {
+            sqlx::query!(
+                r#"
+                UPDATE tracks
+                SET duplicate_of = $2, updated_at = Now()
+                WHERE id = $1
+                "#,
+                track_id,
+                duplicate_of
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_track_duplicates(&self, track_id: Uuid) -> Result<Vec<DuplicateDto>, sqlx::Error> {
+        // Pairs are stored once, from the later upload to the earlier one
+        let duplicates = sqlx::query_as!(
+            DuplicateDto,
+            r#"
+            SELECT t.id AS track_id, t.title, t.artist, d.similarity AS "similarity?"
+            FROM track_duplicates d
+            JOIN tracks t ON t.id = CASE WHEN d.track_id = $1 THEN d.duplicate_of ELSE d.track_id END
+            WHERE d.track_id = $1 OR d.duplicate_of = $1
+            ORDER BY d.similarity DESC
+            "#,
+            track_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(duplicates)
+    }
+
+    async fn get_duplicate_pairs(&self) -> Result<Vec<(Uuid, Uuid, f64)>, sqlx::Error> {
+        let pairs = sqlx::query!(
+            r#"
+            SELECT track_id, duplicate_of, similarity
+            FROM track_duplicates
+            ORDER BY created_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(pairs
+            .into_iter()
+            .map(|pair| (pair.track_id, pair.duplicate_of, pair.similarity as f64))
+            .collect())
+    }
+
+    async fn get_duplicate_tracks(&self, track_ids: &[Uuid]) -> Result<Vec<DuplicateDto>, sqlx::Error> {
+        let tracks = sqlx::query_as!(
+            DuplicateDto,
+            r#"
+            SELECT id AS track_id, title, artist, NULL::real AS similarity
+            FROM tracks
+            WHERE id = ANY($1)
+            ORDER BY created_at
+            "#,
+            track_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tracks)
+    }
+
+    async fn get_tracks_without_fingerprint(&self) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+        // Tracks that already have a fingerprint job queued or running are left alone
+        let tracks = sqlx::query!(
+            r#"
+            SELECT t.id, t.file_name AS "file_name!"
+            FROM tracks t
+            WHERE t.processing_status = 'ready'
+                AND t.file_name IS NOT NULL
+                AND NOT EXISTS (SELECT 1 FROM track_fingerprints f WHERE f.track_id = t.id)
+                AND NOT EXISTS (
+                    SELECT 1 FROM jobs j
+                    WHERE j.track_id = t.id
+                        AND j.kind = 'fingerprint_track'
+                        AND j.status IN ('queued', 'running')
+                )
+            ORDER BY t.created_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tracks.into_iter().map(|track| (track.id, track.file_name)).collect())
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+use sqlx::postgres::types::PgInterval;
+
+use crate::dbs::DBClients;
+
+/// Everything on disk that rows in the database still point at.
+#[derive(Debug, Default)]
+pub struct ReferencedFiles {
+    pub track_ids: HashSet<Uuid>,
+    /// Assembled uploads under `uploads/`
+    pub track_files: HashSet<String>,
+    /// Chunk directories under `uploads/temp/` of uploads still in progress,
+    /// named after the track or, for older uploads, the file
+    pub pending_uploads: HashSet<String>,
+    pub track_thumbnails: HashSet<String>,
+    pub playlist_thumbnails: HashSet<String>,
+    /// Variants under `assets/artwork/`
+    pub artwork_files: HashSet<String>,
+}
+
+#[async_trait]
+pub trait GcExt {
+    async fn get_stale_uploads(&self, ttl_seconds: i64) -> Result<Vec<Uuid>, sqlx::Error>;
+
+    async fn delete_stale_uploads(
+        &self,
+        track_ids: &[Uuid],
+        ttl_seconds: i64,
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn get_unused_artworks(&self, ttl_seconds: i64) -> Result<Vec<Uuid>, sqlx::Error>;
+
+    async fn delete_unused_artworks(
+        &self,
+        artwork_ids: &[Uuid],
+        ttl_seconds: i64,
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn get_referenced_files(
+        &self,
+        excluded_track_ids: &[Uuid],
+        excluded_artwork_ids: &[Uuid],
+    ) -> Result<ReferencedFiles, sqlx::Error>;
+}
+
+fn interval_from_seconds(seconds: i64) -> PgInterval {
+    PgInterval {
+        months: 0,
+        days: 0,
+        microseconds: seconds * 1_000_000,
+    }
+}
+
+#[async_trait]
+impl GcExt for DBClients {
+    async fn get_stale_uploads(&self, ttl_seconds: i64) -> Result<Vec<Uuid>, sqlx::Error> {
+        // Uploads that never got their last chunk and haven't received one within the TTL
+        let tracks = sqlx::query!(
+            r#"
+            SELECT t.id
+            FROM tracks t
+            WHERE t.processing_status IS NULL
+                AND t.created_at < Now() - $1::INTERVAL
+                AND NOT EXISTS (
+                    SELECT 1 FROM audio_files a
+                    WHERE a.track_id = t.id AND a.updated_at >= Now() - $1::INTERVAL
+                )
+            "#,
+            interval_from_seconds(ttl_seconds)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tracks.into_iter().map(|track| track.id).collect())
+    }
+
+    async fn delete_stale_uploads(
+        &self,
+        track_ids: &[Uuid],
+        ttl_seconds: i64,
+    ) -> Result<u64, sqlx::Error> {
+        // The staleness check is repeated so a chunk arriving in the meantime keeps its upload
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM tracks t
+            WHERE t.id = ANY($1)
+                AND t.processing_status IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM audio_files a
+                    WHERE a.track_id = t.id AND a.updated_at >= Now() - $2::INTERVAL
+                )
+            "#,
+            track_ids,
+            interval_from_seconds(ttl_seconds)
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn get_unused_artworks(&self, ttl_seconds: i64) -> Result<Vec<Uuid>, sqlx::Error> {
+        // Fresh artworks may be about to be linked by the request that saved them
+        let artworks = sqlx::query!(
+            r#"
+            SELECT a.id
+            FROM artworks a
+            WHERE a.created_at < Now() - $1::INTERVAL
+                AND NOT EXISTS (SELECT 1 FROM tracks t WHERE t.artwork_id = a.id)
+                AND NOT EXISTS (SELECT 1 FROM playlists p WHERE p.artwork_id = a.id)
+            "#,
+            interval_from_seconds(ttl_seconds)
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(artworks.into_iter().map(|artwork| artwork.id).collect())
+    }
+
+    async fn delete_unused_artworks(
+        &self,
+        artwork_ids: &[Uuid],
+        ttl_seconds: i64,
+    ) -> Result<u64, sqlx::Error> {
+        // Checked again in case an upload reused one of them in the meantime
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM artworks a
+            WHERE a.id = ANY($1)
+                AND a.created_at < Now() - $2::INTERVAL
+                AND NOT EXISTS (SELECT 1 FROM tracks t WHERE t.artwork_id = a.id)
+                AND NOT EXISTS (SELECT 1 FROM playlists p WHERE p.artwork_id = a.id)
+            "#,
+            artwork_ids,
+            interval_from_seconds(ttl_seconds)
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn get_referenced_files(
+        &self,
+        excluded_track_ids: &[Uuid],
+        excluded_artwork_ids: &[Uuid],
+    ) -> Result<ReferencedFiles, sqlx::Error> {
+        let mut referenced = ReferencedFiles::default();
+
+        let tracks = sqlx::query!(
+            r#"
+            SELECT id, file_name, thumbnail_name, processing_status
+            FROM tracks
+            WHERE NOT (id = ANY($1))
+            "#,
+            excluded_track_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for track in tracks {
+            referenced.track_ids.insert(track.id);
+
+            // Chunks are kept until the processing job has assembled them
+            let pending = matches!(track.processing_status.as_deref(), None | Some("uploaded") | Some("processing"));
+            if pending {
+                referenced.pending_uploads.insert(track.id.to_string());
+            }
+
+            if let Some(file_name) = track.file_name {
+                if pending {
+                    referenced.pending_uploads.insert(file_name.clone());
+                }
+                referenced.track_files.insert(file_name);
+            }
+
+            if let Some(thumbnail_name) = track.thumbnail_name {
+                referenced.track_thumbnails.insert(thumbnail_name);
+            }
+        }
+
+        let playlists = sqlx::query!(
+            r#"
+            SELECT thumbnail_path FROM playlists WHERE thumbnail_path IS NOT NULL
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        referenced.playlist_thumbnails = playlists
+            .into_iter()
+            .filter_map(|playlist| playlist.thumbnail_path)
+            .collect();
+
+        let variants = sqlx::query!(
+            r#"
+            SELECT file_name FROM artwork_variants WHERE NOT (artwork_id = ANY($1))
+            "#,
+            excluded_artwork_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        referenced.artwork_files = variants
+            .into_iter()
+            .map(|variant| variant.file_name)
+            .collect();
+
+        Ok(referenced)
+    }
+}
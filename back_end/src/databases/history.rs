@@ -1,8 +1,9 @@
 use async_trait::async_trait;
 use sqlx::postgres::types::PgInterval;
+use sqlx::types::Json;
 use uuid::Uuid;
 
-use crate::{dbs::DBClients, dtos::TrackDto};
+use crate::{dbs::DBClients, dtos::{ArtworkUrlDto, TrackDto}};
 
 #[async_trait]
 pub trait HistoryExt {
@@ -84,12 +85,38 @@ impl HistoryExt for DBClients {
                 t.id,
                 t.title,
                 t.artist,
+                t.album,
+                t.album_artist,
+                md5(t.user_id::text || '/' || t.album) AS album_group,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                t.loudness_lufs,
+                t.true_peak_dbtp,
+                t.album_loudness_lufs,
+                t.album_true_peak_dbtp,
+                t.container,
+                t.codec,
+                t.mime_type,
+                t.sample_rate,
+                t.bit_depth,
+                t.channels,
+                t.bitrate_kbps,
+                t.encoder_delay,
+                t.encoder_padding,
+                t.total_samples,
+                t.play_count,
+                t.license,
+                (SELECT allows_download FROM licenses WHERE code = t.license) AS "license_allows_download!",
                 t.duration,
                 ph.duration_played,
                 ph.played_at,
                 t.file_name,
                 t.upload_status,
                 t.thumbnail_name,
+                artwork_urls(t.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
                 CASE WHEN uf.id IS NOT NULL THEN TRUE ELSE FALSE END AS is_favorite,
                 CASE WHEN t.user_id = $1 THEN true ELSE false END as is_created_by_user
             FROM
@@ -0,0 +1,132 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::dbs::DBClients;
+
+/// Outcome of an earlier import of the same source file.
+#[derive(Debug)]
+pub struct PreviousImport {
+    pub source_size: i64,
+    pub track_id: Option<Uuid>,
+    /// The track made it to `ready`
+    pub track_ready: bool,
+}
+
+#[async_trait]
+pub trait ImportExt {
+    async fn get_previous_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+    ) -> Result<Option<PreviousImport>, sqlx::Error>;
+
+    async fn start_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+        source_size: i64,
+        track_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn finish_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn discard_import_track(&self, track_id: Uuid) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl ImportExt for DBClients {
+    async fn get_previous_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+    ) -> Result<Option<PreviousImport>, sqlx::Error> {
+        let import = sqlx::query_as!(
+            PreviousImport,
+            r#"
+            SELECT
+                li.source_size,
+                li.track_id,
+                COALESCE(t.processing_status = 'ready', false) AS "track_ready!"
+            FROM library_imports li
+            LEFT JOIN tracks t ON t.id = li.track_id
+            WHERE li.user_id = $1 AND li.source_path = $2
+            "#,
+            user_id,
+            source_path
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(import)
+    }
+
+    async fn start_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+        source_size: i64,
+        track_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO library_imports (user_id, source_path, source_size, track_id, status)
+            VALUES ($1, $2, $3, $4, 'importing')
+            ON CONFLICT (user_id, source_path) DO UPDATE
+            SET source_size = EXCLUDED.source_size,
+                track_id = EXCLUDED.track_id,
+                status = 'importing',
+                error = NULL
+            "#,
+            user_id,
+            source_path,
+            source_size,
+            track_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn finish_import(
+        &self,
+        user_id: Uuid,
+        source_path: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE library_imports
+            SET status = CASE WHEN $3::TEXT IS NULL THEN 'imported' ELSE 'failed' END,
+                error = $3
+            WHERE user_id = $1 AND source_path = $2
+            "#,
+            user_id,
+            source_path,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn discard_import_track(&self, track_id: Uuid) -> Result<(), sqlx::Error> {
+        // Its file is left to the garbage collector like any other orphan
+        sqlx::query!(
+            r#"
+            DELETE FROM tracks WHERE id = $1 AND processing_status IS DISTINCT FROM 'ready'
+            "#,
+            track_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+use sqlx::postgres::types::PgInterval;
+
+use crate::{dbs::DBClients, dtos::TrackStatusDto, models::Job};
+
+#[async_trait]
+pub trait JobExt {
+    async fn complete_upload(
+        &self,
+        track_id: Uuid,
+        kind: &str,
+        payload: serde_json::Value,
+        max_attempts: i32,
+    ) -> Result<Option<Uuid>, sqlx::Error>;
+
+    async fn enqueue_job(
+        &self,
+        track_id: Uuid,
+        kind: &str,
+        payload: serde_json::Value,
+        max_attempts: i32,
+    ) -> Result<Uuid, sqlx::Error>;
+
+    async fn claim_job(&self) -> Result<Option<Job>, sqlx::Error>;
+
+    async fn finish_job(&self, job_id: Uuid) -> Result<(), sqlx::Error>;
+
+    async fn fail_job(
+        &self,
+        job_id: Uuid,
+        error: &str,
+        retry_in_seconds: Option<i64>,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Keeps a running job from being taken for stale.
+    async fn touch_job(&self, job_id: Uuid) -> Result<(), sqlx::Error>;
+
+    /// Queues running jobs nobody touched for `stale_after_seconds` again, or
+    /// fails them once they are out of attempts. Returns the jobs it changed.
+    async fn requeue_stale_jobs(&self, stale_after_seconds: i64) -> Result<Vec<Job>, sqlx::Error>;
+
+    async fn set_processing_status(
+        &self,
+        track_id: Uuid,
+        status: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_track_status(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<TrackStatusDto>, sqlx::Error>;
+}
+
+#[async_trait]
+impl JobExt for DBClients {
+    async fn complete_upload(
+        &self,
+        track_id: Uuid,
+        kind: &str,
+        payload: serde_json::Value,
+        max_attempts: i32,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Only the request that flips the track to 'uploaded' enqueues the job,
+        // so two racing final chunks can't process the same upload twice.
+        let updated = sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET upload_status = 'complete',
+                processing_status = 'uploaded',
+                updated_at = Now()
+            WHERE id = $1 AND processing_status IS NULL
+            "#,
+            track_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM audio_files WHERE track_id = $1
+            "#,
+            track_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let job = sqlx::query!(
+            r#"
+            INSERT INTO jobs (kind, payload, track_id, max_attempts)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            kind,
+            payload,
+            track_id,
+            max_attempts,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(job.id))
+    }
+
+    async fn enqueue_job(
+        &self,
+        track_id: Uuid,
+        kind: &str,
+        payload: serde_json::Value,
+        max_attempts: i32,
+    ) -> Result<Uuid, sqlx::Error> {
+        let job = sqlx::query!(
+            r#"
+            INSERT INTO jobs (kind, payload, track_id, max_attempts)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            kind,
+            payload,
+            track_id,
+            max_attempts,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(job.id)
+    }
+
+    async fn claim_job(&self) -> Result<Option<Job>, sqlx::Error> {
+        let job = sqlx::query_as!(
+            Job,
+            r#"
+            UPDATE jobs
+            SET status = 'running',
+                attempts = attempts + 1,
+                locked_at = Now()
+            WHERE id = (
+                SELECT id
+                FROM jobs
+                WHERE status = 'queued' AND run_at <= Now()
+                ORDER BY run_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, kind, payload, track_id, status, attempts, max_attempts, last_error, run_at
+            "#
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    async fn finish_job(&self, job_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = 'succeeded',
+                locked_at = NULL,
+                last_error = NULL
+            WHERE id = $1
+            "#,
+            job_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fail_job(
+        &self,
+        job_id: Uuid,
+        error: &str,
+        retry_in_seconds: Option<i64>,
+    ) -> Result<bool, sqlx::Error> {
+        // No retry delay means the failure is permanent
+        let retry_in = retry_in_seconds.map(|seconds| PgInterval {
+            months: 0,
+            days: 0,
+            microseconds: seconds * 1_000_000,
+        });
+
+        // Returns true when the job will be retried, false when it is given up on
+        let job = sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = CASE
+                    WHEN $2::INTERVAL IS NULL OR attempts >= max_attempts THEN 'failed'
+                    ELSE 'queued'
+                END,
+                run_at = Now() + COALESCE($2::INTERVAL, INTERVAL '0 seconds'),
+                locked_at = NULL,
+                last_error = $3
+            WHERE id = $1
+            RETURNING status
+            "#,
+            job_id,
+            retry_in,
+            error
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(job.status == "queued")
+    }
+
+    async fn touch_job(&self, job_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET locked_at = Now()
+            WHERE id = $1 AND status = 'running'
+            "#,
+            job_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn requeue_stale_jobs(&self, stale_after_seconds: i64) -> Result<Vec<Job>, sqlx::Error> {
+        let stale_after = PgInterval {
+            months: 0,
+            days: 0,
+            microseconds: stale_after_seconds * 1_000_000,
+        };
+
+        // A job that keeps taking its worker down would otherwise be retried forever
+        let jobs = sqlx::query_as!(
+            Job,
+            r#"
+            UPDATE jobs
+            SET status = CASE WHEN attempts >= max_attempts THEN 'failed' ELSE 'queued' END,
+                last_error = CASE
+                    WHEN attempts >= max_attempts THEN 'The worker running the job stopped'
+                    ELSE last_error
+                END,
+                locked_at = NULL
+            WHERE status = 'running' AND locked_at < Now() - $1::INTERVAL
+            RETURNING id, kind, payload, track_id, status, attempts, max_attempts, last_error, run_at
+            "#,
+            stale_after
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    async fn set_processing_status(
+        &self,
+        track_id: Uuid,
+        status: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET processing_status = $2,
+                updated_at = Now()
+            WHERE id = $1
+            "#,
+            track_id,
+            status
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_track_status(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<TrackStatusDto>, sqlx::Error> {
+        let status = sqlx::query_as!(
+            TrackStatusDto,
+            r#"
+            SELECT
+                t.id AS track_id,
+                t.upload_status,
+                t.processing_status,
+                j.status AS "job_status?",
+                j.attempts AS "attempts?",
+                j.last_error AS "last_error?"
+            FROM tracks t
+            LEFT JOIN LATERAL (
+                SELECT status, attempts, last_error
+                FROM jobs
+                WHERE jobs.track_id = t.id
+                ORDER BY created_at DESC
+                LIMIT 1
+            ) j ON true
+            WHERE t.id = $1 AND t.user_id = $2
+            "#,
+            track_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(status)
+    }
+}
@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, media::loudness::LoudnessReport};
+
+#[async_trait]
+pub trait LoudnessExt {
+    async fn save_loudness(
+        &self,
+        track_id: Uuid,
+        report: &LoudnessReport,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn update_album_loudness(&self, track_id: Uuid) -> Result<(), sqlx::Error>;
+
+    async fn get_tracks_without_loudness(&self) -> Result<Vec<(Uuid, String)>, sqlx::Error>;
+}
+
+#[async_trait]
+impl LoudnessExt for DBClients {
+    async fn save_loudness(
+        &self,
+        track_id: Uuid,
+        report: &LoudnessReport,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET loudness_lufs = $2,
+                loudness_range_lu = $3,
+                true_peak_dbtp = $4,
+                loudness_analyzed_at = Now(),
+                updated_at = Now()
+            WHERE id = $1
+            "#,
+            track_id,
+            report.integrated_lufs,
+            report.loudness_range_lu,
+            report.true_peak_dbtp
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_album_loudness(&self, track_id: Uuid) -> Result<(), sqlx::Error> {
+        // Album loudness is the duration weighted energy average of its tracks,
+        // the album peak is the loudest track peak.
+        sqlx::query!(
+            r#"
+            WITH album AS (
+                SELECT user_id, album
+                FROM tracks
+                WHERE id = $1 AND album IS NOT NULL
+            ),
+            stats AS (
+                SELECT
+                    10 * LOG(
+                        SUM(EXTRACT(EPOCH FROM t.duration)::DOUBLE PRECISION * POWER(10, t.loudness_lufs / 10))
+                        / NULLIF(SUM(EXTRACT(EPOCH FROM t.duration)::DOUBLE PRECISION), 0)
+                    ) AS loudness_lufs,
+                    MAX(t.true_peak_dbtp) AS true_peak_dbtp
+                FROM tracks t
+                JOIN album a ON t.user_id = a.user_id AND t.album = a.album
+                WHERE t.loudness_lufs IS NOT NULL AND t.processing_status = 'ready'
+            )
+            UPDATE tracks t
+            SET album_loudness_lufs = stats.loudness_lufs,
+                album_true_peak_dbtp = stats.true_peak_dbtp
+            FROM album a, stats
+            WHERE t.user_id = a.user_id AND t.album = a.album
+            "#,
+            track_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_tracks_without_loudness(&self) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+        // Tracks that already have an analysis queued or running are left alone
+        let tracks = sqlx::query!(
+            r#"
+            SELECT t.id, t.file_name AS "file_name!"
+            FROM tracks t
+            WHERE t.processing_status = 'ready'
+                AND t.loudness_analyzed_at IS NULL
+                AND t.file_name IS NOT NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM jobs j
+                    WHERE j.track_id = t.id
+                        AND j.kind = 'analyze_loudness'
+                        AND j.status IN ('queued', 'running')
+                )
+            ORDER BY t.created_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tracks.into_iter().map(|track| (track.id, track.file_name)).collect())
+    }
+}
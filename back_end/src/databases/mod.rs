@@ -1,6 +1,16 @@
+pub mod archives;
+pub mod artwork;
 pub mod favorites;
 pub mod users;
+pub mod gc;
 pub mod history;
+pub mod imports;
+pub mod jobs;
+pub mod loudness;
 pub mod playlists;
+pub mod renditions;
 pub mod track;
-pub mod upload;
\ No newline at end of file
+pub mod upload;
+pub mod waveforms;pub mod fingerprints;
+pub mod streams;
+pub mod search;
\ No newline at end of file
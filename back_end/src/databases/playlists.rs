@@ -1,7 +1,8 @@
 use async_trait::async_trait;
+use sqlx::types::Json;
 use uuid::Uuid;
 
-use crate::{dbs::DBClients, dtos::{PlayListDto, TrackDto}};
+use crate::{dbs::DBClients, dtos::{ArtworkUrlDto, PlayListDto, TrackDto}};
 
 #[async_trait]
 pub trait PlayListsExt {
@@ -9,7 +10,7 @@ pub trait PlayListsExt {
         &self,
         user_id: Uuid,
         title: String,
-        thumbnail_path: String,
+        artwork_id: Uuid,
     ) -> Result<(), sqlx::Error>;
 
     async fn get_last_track_order(
@@ -42,16 +43,16 @@ impl PlayListsExt for DBClients {
         &self,
         user_id: Uuid,
         title: String,
-        thumbnail_path: String,
+        artwork_id: Uuid,
     ) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
-            INSERT INTO playlists (user_id, title, thumbnail_path)
+            INSERT INTO playlists (user_id, title, artwork_id)
             VALUES ($1, $2, $3)
             "#,
             user_id,
             title,
-            thumbnail_path,
+            artwork_id,
         )
         .execute(&self.pool)
         .await?;
@@ -111,6 +112,7 @@ impl PlayListsExt for DBClients {
                 p.id,
                 p.title,
                 p.thumbnail_path,
+                artwork_urls(p.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
                 COALESCE(MAX(pt.track_order), 0) as max_track_order
             FROM playlists p
             LEFT JOIN playlist_tracks pt ON p.id = pt.playlist_id
@@ -138,10 +140,36 @@ impl PlayListsExt for DBClients {
                 t.id,
                 t.title,
                 t.artist,
+                t.album,
+                t.album_artist,
+                md5(t.user_id::text || '/' || t.album) AS album_group,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                t.loudness_lufs,
+                t.true_peak_dbtp,
+                t.album_loudness_lufs,
+                t.album_true_peak_dbtp,
+                t.container,
+                t.codec,
+                t.mime_type,
+                t.sample_rate,
+                t.bit_depth,
+                t.channels,
+                t.bitrate_kbps,
+                t.encoder_delay,
+                t.encoder_padding,
+                t.total_samples,
+                t.play_count,
+                t.license,
+                (SELECT allows_download FROM licenses WHERE code = t.license) AS "license_allows_download!",
                 t.duration,
                 t.file_name,
                 t.upload_status,
                 t.thumbnail_name,
+                artwork_urls(t.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
                 COALESCE(ph.played_at, NULL) AS played_at,
                 CASE WHEN uf.track_id IS NOT NULL THEN true ELSE false END as "is_favorite?",
                 COALESCE(ph.duration_played, INTERVAL '0 seconds') AS duration_played,  -- Default to 0 if no playback history
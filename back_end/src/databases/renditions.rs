@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, dtos::TrackRenditionDto, media::renditions::RenditionOutput, models::TrackRendition};
+
+#[async_trait]
+pub trait RenditionExt {
+    async fn save_rendition(
+        &self,
+        track_id: Uuid,
+        rendition: &RenditionOutput,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_track_renditions(
+        &self,
+        track_id: Uuid,
+    ) -> Result<Option<Vec<TrackRenditionDto>>, sqlx::Error>;
+
+    async fn get_rendition(
+        &self,
+        track_id: Uuid,
+        quality: &str,
+    ) -> Result<Option<TrackRendition>, sqlx::Error>;
+}
+
+#[async_trait]
+impl RenditionExt for DBClients {
+    async fn save_rendition(
+        &self,
+        track_id: Uuid,
+        rendition: &RenditionOutput,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO track_renditions (
+                track_id, quality, codec, mime_type, bitrate_kbps, sample_rate, channels, file_path, file_size
+            ) VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9
+            )
+            ON CONFLICT (track_id, quality) DO UPDATE
+            SET codec = EXCLUDED.codec,
+                mime_type = EXCLUDED.mime_type,
+                bitrate_kbps = EXCLUDED.bitrate_kbps,
+                sample_rate = EXCLUDED.sample_rate,
+                channels = EXCLUDED.channels,
+                file_path = EXCLUDED.file_path,
+                file_size = EXCLUDED.file_size,
+                created_at = Now()
+            "#,
+            track_id,
+            rendition.quality,
+            rendition.codec.name(),
+            rendition.codec.mime_type(),
+            rendition.bitrate_kbps.map(|bitrate| bitrate as i32),
+            rendition.sample_rate as i32,
+            rendition.channels as i32,
+            rendition.file_path,
+            rendition.file_size as i64,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_track_renditions(
+        &self,
+        track_id: Uuid,
+    ) -> Result<Option<Vec<TrackRenditionDto>>, sqlx::Error> {
+        // None when the track doesn't exist or isn't ready for playback yet
+        let track = sqlx::query!(
+            r#"
+            SELECT id FROM tracks WHERE id = $1 AND processing_status = 'ready'
+            "#,
+            track_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if track.is_none() {
+            return Ok(None);
+        }
+
+        let renditions = sqlx::query_as!(
+            TrackRenditionDto,
+            r#"
+            SELECT quality, codec, mime_type, bitrate_kbps, sample_rate, channels, file_size
+            FROM track_renditions
+            WHERE track_id = $1
+            ORDER BY sample_rate DESC, bitrate_kbps DESC NULLS FIRST
+            "#,
+            track_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(renditions))
+    }
+
+    async fn get_rendition(
+        &self,
+        track_id: Uuid,
+        quality: &str,
+    ) -> Result<Option<TrackRendition>, sqlx::Error> {
+        let rendition = sqlx::query_as!(
+            TrackRendition,
+            r#"
+            SELECT r.id, r.track_id, r.quality, r.codec, r.mime_type, r.bitrate_kbps,
+                   r.sample_rate, r.channels, r.file_path, r.file_size, r.created_at
+            FROM track_renditions r
+            WHERE r.track_id = $1 AND r.quality = $2
+            "#,
+            track_id,
+            quality
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(rendition)
+    }
+}
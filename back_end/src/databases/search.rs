@@ -0,0 +1,200 @@
+use async_trait::async_trait;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use crate::{dbs::DBClients, dtos::{ArtworkUrlDto, SearchPlaylistDto, SearchUserDto, TrackDto}};
+
+/// Matches are found by full-text search, or by trigram similarity to let
+/// typos and partial words through: similar to a part of the text (`<%`) or
+/// to all of it (`%`), which suits short names. Accents and case are
+/// ignored, see the `search_normalize` SQL function.
+#[async_trait]
+pub trait SearchExt {
+    /// Ready tracks by title, artist and album, with the flags of `user_id`.
+    async fn search_tracks(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TrackDto>, sqlx::Error>;
+
+    async fn search_playlists(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchPlaylistDto>, sqlx::Error>;
+
+    async fn search_users(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchUserDto>, sqlx::Error>;
+}
+
+#[async_trait]
+impl SearchExt for DBClients {
+    async fn search_tracks(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TrackDto>, sqlx::Error> {
+        // Ties go to the most played
+        let tracks = sqlx::query_as!(
+            TrackDto,
+            r#"
+            WITH query AS (
+                SELECT
+                    websearch_to_tsquery('simple', search_normalize($2)) AS tsquery,
+                    search_normalize($2) AS text
+            )
+            SELECT
+                t.id,
+                t.title,
+                t.artist,
+                t.album,
+                t.album_artist,
+                md5(t.user_id::text || '/' || t.album) AS album_group,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                t.loudness_lufs,
+                t.true_peak_dbtp,
+                t.album_loudness_lufs,
+                t.album_true_peak_dbtp,
+                t.container,
+                t.codec,
+                t.mime_type,
+                t.sample_rate,
+                t.bit_depth,
+                t.channels,
+                t.bitrate_kbps,
+                t.encoder_delay,
+                t.encoder_padding,
+                t.total_samples,
+                t.play_count,
+                t.license,
+                (SELECT allows_download FROM licenses WHERE code = t.license) AS "license_allows_download!",
+                t.duration,
+                t.file_name,
+                t.upload_status,
+                t.thumbnail_name,
+                artwork_urls(t.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
+                COALESCE(ph.played_at, NULL) AS played_at,
+                CASE WHEN uf.id IS NOT NULL THEN true ELSE false END AS is_favorite,
+                COALESCE(ph.duration_played, INTERVAL '0 seconds') AS duration_played,
+                CASE WHEN t.user_id = $1 THEN true ELSE false END AS is_created_by_user
+            FROM tracks t
+            CROSS JOIN query q
+            LEFT JOIN user_favorites uf
+                ON uf.track_id = t.id AND uf.user_id = $1
+            LEFT JOIN playback_history ph
+                ON ph.track_id = t.id AND ph.user_id = $1
+            WHERE t.processing_status = 'ready'
+                AND (t.search_vector @@ q.tsquery OR q.text <% t.search_text OR q.text % t.search_text)
+            ORDER BY
+                ts_rank(t.search_vector, q.tsquery) + word_similarity(q.text, t.search_text) DESC,
+                t.play_count DESC,
+                t.id
+            LIMIT $3 OFFSET $4
+            "#,
+            user_id,
+            query,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tracks)
+    }
+
+    async fn search_playlists(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchPlaylistDto>, sqlx::Error> {
+        let playlists = sqlx::query_as!(
+            SearchPlaylistDto,
+            r#"
+            WITH query AS (
+                SELECT
+                    websearch_to_tsquery('simple', search_normalize($2)) AS tsquery,
+                    search_normalize($2) AS text
+            )
+            SELECT
+                p.id,
+                p.title,
+                p.thumbnail_path,
+                artwork_urls(p.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
+                u.username AS "owner?",
+                (SELECT COUNT(*) FROM playlist_tracks pt WHERE pt.playlist_id = p.id) AS "track_count!",
+                COALESCE(p.user_id = $1, false) AS "is_created_by_user!"
+            FROM playlists p
+            CROSS JOIN query q
+            LEFT JOIN users u ON u.id = p.user_id
+            WHERE p.search_vector @@ q.tsquery OR q.text <% p.search_text OR q.text % p.search_text
+            ORDER BY
+                ts_rank(p.search_vector, q.tsquery) + word_similarity(q.text, p.search_text) DESC,
+                p.id
+            LIMIT $3 OFFSET $4
+            "#,
+            user_id,
+            query,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(playlists)
+    }
+
+    async fn search_users(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchUserDto>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            SearchUserDto,
+            r#"
+            WITH query AS (
+                SELECT
+                    websearch_to_tsquery('simple', search_normalize($1)) AS tsquery,
+                    search_normalize($1) AS text
+            )
+            SELECT
+                u.id,
+                u.username,
+                (
+                    SELECT COUNT(*) FROM tracks t
+                    WHERE t.user_id = u.id AND t.processing_status = 'ready'
+                ) AS "track_count!"
+            FROM users u
+            CROSS JOIN query q
+            WHERE u.search_vector @@ q.tsquery OR q.text <% u.search_text OR q.text % u.search_text
+            ORDER BY
+                ts_rank(u.search_vector, q.tsquery) + word_similarity(q.text, u.search_text) DESC,
+                u.id
+            LIMIT $2 OFFSET $3
+            "#,
+            query,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+}
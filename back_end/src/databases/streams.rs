@@ -0,0 +1,321 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::dbs::DBClients;
+
+#[async_trait]
+pub trait StreamLeaseExt {
+    /// Takes or renews the lease of one of the user's devices. Returns `false`
+    /// without a lease when `max_streams` other devices are already streaming.
+    async fn acquire_stream_lease(
+        &self,
+        user_id: Uuid,
+        session_key: &str,
+        track_id: Uuid,
+        lease_seconds: u64,
+        max_streams: i64,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn renew_stream_lease(
+        &self,
+        user_id: Uuid,
+        session_key: &str,
+        lease_seconds: u64,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl StreamLeaseExt for DBClients {
+    async fn acquire_stream_lease(
+        &self,
+        user_id: Uuid,
+        session_key: &str,
+        track_id: Uuid,
+        lease_seconds: u64,
+        max_streams: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Serializes the user's requests, two new devices can't both take the last slot
+        sqlx::query!(
+            r#"
+            SELECT id FROM users WHERE id = $1 FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM stream_leases
+            WHERE user_id = $1 AND expires_at < Now()
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let renewed = sqlx::query!(
+            r#"
+            UPDATE stream_leases
+            SET track_id = $3,
+                expires_at = Now() + make_interval(secs => $4)
+            WHERE user_id = $1 AND session_key = $2
+            "#,
+            user_id,
+            session_key,
+            track_id,
+            lease_seconds as f64
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if renewed.rows_affected() == 0 {
+            let active = sqlx::query_scalar!(
+                r#"
+                SELECT COUNT(*) AS "count!" FROM stream_leases WHERE user_id = $1
+                "#,
+                user_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if active >= max_streams {
+                tx.rollback().await?;
+                return Ok(false);
+            }
+
+            sqlx::query!(
+                r#"
+                INSERT INTO stream_leases (user_id, session_key, track_id, expires_at)
+                VALUES ($1, $2, $3, Now() + make_interval(secs => $4))
+                "#,
+                user_id,
+                session_key,
+                track_id,
+                lease_seconds as f64
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn renew_stream_lease(
+        &self,
+        user_id: Uuid,
+        session_key: &str,
+        lease_seconds: u64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE stream_leases
+            SET expires_at = GREATEST(expires_at, Now() + make_interval(secs => $3))
+            WHERE user_id = $1 AND session_key = $2
+            "#,
+            user_id,
+            session_key,
+            lease_seconds as f64
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// What one stream request sends, offsets being bytes of the served file.
+pub struct StreamRequest<'a> {
+    /// The original, a rendition or a transcode
+    pub file_key: &'a str,
+    /// Unknown while a transcode is still being encoded
+    pub file_size: Option<u64>,
+    /// Estimates the size of a transcode from the track's duration
+    pub bitrate_kbps: Option<u32>,
+    pub range_start: u64,
+    /// `None` for a transcode sent while it is encoded
+    pub range_end: Option<u64>,
+}
+
+#[async_trait]
+pub trait StreamSessionExt {
+    /// Adds the request to the device's session of the track when the previous
+    /// one ended less than `gap_seconds` ago, starts a new session otherwise.
+    async fn open_stream_session(
+        &self,
+        user_id: Uuid,
+        track_id: Uuid,
+        session_key: &str,
+        request: &StreamRequest<'_>,
+        gap_seconds: u64,
+    ) -> Result<Uuid, sqlx::Error>;
+
+    /// Records bytes `start..end` as delivered and counts the session as a play
+    /// once it delivered `min_seconds` of audio or `min_percent` of the file.
+    /// Returns whether this made it a play.
+    async fn record_stream_delivery(
+        &self,
+        session_id: Uuid,
+        start: u64,
+        end: u64,
+        min_seconds: u64,
+        min_percent: u64,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl StreamSessionExt for DBClients {
+    async fn open_stream_session(
+        &self,
+        user_id: Uuid,
+        track_id: Uuid,
+        session_key: &str,
+        request: &StreamRequest<'_>,
+        gap_seconds: u64,
+    ) -> Result<Uuid, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let file_size = request.file_size.map(|size| size as i64);
+        let range_end = request.range_end.map(|end| end as i64);
+
+        // The range requests a player sends in parallel end up in one session
+        sqlx::query!(
+            r#"
+            SELECT id FROM users WHERE id = $1 FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let resumed = sqlx::query_scalar!(
+            r#"
+            UPDATE stream_sessions
+            SET requests = requests + 1,
+                requested_ranges = requested_ranges + int8multirange(int8range($5, $6)),
+                file_size = COALESCE($7, file_size),
+                ended_at = Now()
+            WHERE id = (
+                SELECT id FROM stream_sessions
+                WHERE user_id = $1
+                    AND session_key = $2
+                    AND track_id = $3
+                    AND file_key = $4
+                    AND ended_at > Now() - make_interval(secs => $8)
+                ORDER BY ended_at DESC
+                LIMIT 1
+            )
+            RETURNING id
+            "#,
+            user_id,
+            session_key,
+            track_id,
+            request.file_key,
+            request.range_start as i64,
+            range_end,
+            file_size,
+            gap_seconds as f64
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let session_id = match resumed {
+            Some(session_id) => session_id,
+            None => {
+                sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO stream_sessions (
+                        user_id, track_id, session_key, file_key, file_size, requested_ranges
+                    )
+                    SELECT
+                        $1, t.id, $3, $4,
+                        COALESCE($5, (EXTRACT(EPOCH FROM t.duration) * $6::INTEGER * 125)::BIGINT),
+                        int8multirange(int8range($7, $8))
+                    FROM tracks t
+                    WHERE t.id = $2
+                    RETURNING id
+                    "#,
+                    user_id,
+                    track_id,
+                    session_key,
+                    request.file_key,
+                    file_size,
+                    request.bitrate_kbps.map(|kbps| kbps as i32),
+                    request.range_start as i64,
+                    range_end
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+
+        Ok(session_id)
+    }
+
+    async fn record_stream_delivery(
+        &self,
+        session_id: Uuid,
+        start: u64,
+        end: u64,
+        min_seconds: u64,
+        min_percent: u64,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE stream_sessions
+            SET delivered_ranges = delivered_ranges + int8multirange(int8range($2, $3)),
+                bytes_served = bytes_served + ($3 - $2),
+                ended_at = Now()
+            WHERE id = $1
+            "#,
+            session_id,
+            start as i64,
+            end as i64
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Seeking back and forth delivers the same bytes again, only distinct ones count.
+        // The counted_at check is repeated after the row lock, a session counts once.
+        let counted = sqlx::query!(
+            r#"
+            WITH delivered AS (
+                SELECT
+                    s.id,
+                    s.track_id,
+                    (SELECT COALESCE(SUM(upper(r) - lower(r)), 0) FROM unnest(s.delivered_ranges) r)::DOUBLE PRECISION
+                        / s.file_size AS share,
+                    EXTRACT(EPOCH FROM t.duration)::DOUBLE PRECISION AS seconds
+                FROM stream_sessions s
+                JOIN tracks t ON t.id = s.track_id
+                WHERE s.id = $1 AND s.counted_at IS NULL AND s.file_size > 0
+            ),
+            verified AS (
+                UPDATE stream_sessions s
+                SET counted_at = Now()
+                FROM delivered d
+                WHERE s.id = d.id
+                    AND s.counted_at IS NULL
+                    AND (d.share * 100 >= $2 OR d.share * d.seconds >= $3)
+                RETURNING s.track_id
+            )
+            UPDATE tracks t
+            SET play_count = t.play_count + 1
+            FROM verified v
+            WHERE t.id = v.track_id
+            "#,
+            session_id,
+            min_percent as f64,
+            min_seconds as f64
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(counted.rows_affected() > 0)
+    }
+}
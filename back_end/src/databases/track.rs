@@ -1,7 +1,8 @@
 use async_trait::async_trait;
+use sqlx::types::Json;
 use uuid::Uuid;
 
-use crate::{dbs::DBClients, dtos::TrackDto};
+use crate::{dbs::DBClients, dtos::{ArtworkUrlDto, TrackDto}, models::TrackDownload};
 
 #[async_trait]
 pub trait TrackExt {
@@ -9,6 +10,27 @@ pub trait TrackExt {
         &self,
         user_id: Uuid,
     ) -> Result<Vec<TrackDto>, sqlx::Error>;
+
+    /// File name and MIME type of a track `user_id` may stream. Ready tracks are
+    /// open to everyone, owners and admins also get theirs while processing.
+    async fn get_streamable_track(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<Option<(String, Option<String>)>, sqlx::Error>;
+
+    /// File name and duration in milliseconds of a ready track.
+    async fn get_playable_track(&self, track_id: Uuid) -> Result<Option<(String, i64)>, sqlx::Error>;
+
+    /// Tags and artwork of a track visible to `user_id`, whether they may
+    /// download it or not.
+    async fn get_track_download(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<Option<TrackDownload>, sqlx::Error>;
 }
 
 #[async_trait]
@@ -24,10 +46,36 @@ impl TrackExt for DBClients {
                 t.id,
                 t.title,
                 t.artist,
+                t.album,
+                t.album_artist,
+                md5(t.user_id::text || '/' || t.album) AS album_group,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                t.loudness_lufs,
+                t.true_peak_dbtp,
+                t.album_loudness_lufs,
+                t.album_true_peak_dbtp,
+                t.container,
+                t.codec,
+                t.mime_type,
+                t.sample_rate,
+                t.bit_depth,
+                t.channels,
+                t.bitrate_kbps,
+                t.encoder_delay,
+                t.encoder_padding,
+                t.total_samples,
+                t.play_count,
+                t.license,
+                (SELECT allows_download FROM licenses WHERE code = t.license) AS "license_allows_download!",
                 t.duration,
                 t.file_name,
                 t.upload_status,
                 t.thumbnail_name,
+                artwork_urls(t.artwork_id) AS "artwork!: Json<Vec<ArtworkUrlDto>>",
                 COALESCE(ph.played_at, NULL) AS played_at,
                 CASE WHEN uf.id IS NOT NULL THEN true ELSE false END AS is_favorite,
                 COALESCE(ph.duration_played, INTERVAL '0 seconds') AS duration_played,
@@ -37,7 +85,7 @@ impl TrackExt for DBClients {
                 ON uf.track_id = t.id AND uf.user_id = $1
             LEFT JOIN playback_history ph
                 ON ph.track_id = t.id AND ph.user_id = $1
-            WHERE t.upload_status = 'complete'
+            WHERE t.processing_status = 'ready'
             ORDER BY RANDOM()
             LIMIT 20
             "#,
@@ -48,4 +96,101 @@ impl TrackExt for DBClients {
 
         Ok(tracks)
     }
+
+    async fn get_streamable_track(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<Option<(String, Option<String>)>, sqlx::Error> {
+        let track = sqlx::query!(
+            r#"
+            SELECT file_name AS "file_name!", mime_type
+            FROM tracks
+            WHERE id = $1
+                AND file_name IS NOT NULL
+                AND (
+                    processing_status = 'ready'
+                    OR ((user_id = $2 OR $3) AND upload_status = 'complete')
+                )
+            "#,
+            track_id,
+            user_id,
+            is_admin
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(track.map(|track| (track.file_name, track.mime_type)))
+    }
+
+    async fn get_playable_track(&self, track_id: Uuid) -> Result<Option<(String, i64)>, sqlx::Error> {
+        let track = sqlx::query!(
+            r#"
+            SELECT
+                file_name AS "file_name!",
+                (EXTRACT(EPOCH FROM duration) * 1000)::BIGINT AS "duration_ms!"
+            FROM tracks
+            WHERE id = $1
+                AND processing_status = 'ready'
+                AND file_name IS NOT NULL
+                AND duration IS NOT NULL
+            "#,
+            track_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(track.map(|track| (track.file_name, track.duration_ms)))
+    }
+
+    async fn get_track_download(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<Option<TrackDownload>, sqlx::Error> {
+        let track = sqlx::query_as!(
+            TrackDownload,
+            r#"
+            SELECT
+                t.file_name AS "file_name!",
+                t.container,
+                t.title,
+                t.artist,
+                t.album,
+                t.album_artist,
+                t.track_number,
+                t.disc_number,
+                t.release_year,
+                t.genre,
+                t.isrc,
+                cover.file_name AS "artwork_file_name?",
+                cover.size AS "artwork_size?",
+                (t.user_id = $2 OR $3 OR l.allows_download) AS "can_download!"
+            FROM tracks t
+            JOIN licenses l ON l.code = t.license
+            LEFT JOIN LATERAL (
+                SELECT file_name, size
+                FROM artwork_variants
+                WHERE artwork_id = t.artwork_id AND format = 'jpeg'
+                ORDER BY size DESC
+                LIMIT 1
+            ) cover ON true
+            WHERE t.id = $1
+                AND t.file_name IS NOT NULL
+                AND (
+                    t.processing_status = 'ready'
+                    OR ((t.user_id = $2 OR $3) AND t.upload_status = 'complete')
+                )
+            "#,
+            track_id,
+            user_id,
+            is_admin
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(track)
+    }
 }
@@ -1,8 +1,15 @@
 use async_trait::async_trait;
 use uuid::Uuid;
-use sqlx::{postgres::types::PgInterval, query, query_as};
+use sqlx::{postgres::types::PgInterval, query, query_as, Postgres, Transaction};
 
-use crate::{dbs::DBClients, dtos::InCompleteTrackInfo, models::AudioFile};
+use crate::{dbs::DBClients, dtos::InCompleteTrackInfo, media::{tags::EmbeddedTags, AudioFormat}, models::AudioFile};
+
+/// Track details the uploader edits, empty ones are left as they are.
+pub struct TrackDetails<'a> {
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub license: &'a str,
+}
 
 #[async_trait]
 pub trait UploadExt {
@@ -26,21 +33,58 @@ pub trait UploadExt {
     async fn upload_thumbnail(
         &self,
         track_id: Uuid,
-        thumbnail_name: &String,
-        title: &String,
-        artist: &String,
+        artwork_id: Option<Uuid>,
+        details: &TrackDetails<'_>,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn apply_embedded_tags(
+        &self,
+        track_id: Uuid,
+        tags: &EmbeddedTags,
+        artwork_id: Option<Uuid>,
     ) -> Result<(), sqlx::Error>;
 
     async fn update_status(
         &self,
         track_id: Uuid,
-        duration: i64,
+        duration_ms: i64,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn save_audio_format(
+        &self,
+        track_id: Uuid,
+        format: &AudioFormat,
     ) -> Result<(), sqlx::Error>;
 
     async fn get_incomplete_uploads(
         &self,
         user_id: Uuid
     ) -> Result<Vec<InCompleteTrackInfo>, sqlx::Error>;
+
+    /// Locks an upload that is still receiving chunks, along with the name its
+    /// file is stored under.
+    async fn lock_pending_upload(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Transaction<'static, Postgres>, String)>, sqlx::Error>;
+
+    async fn lock_upload_for_delete(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Transaction<'static, Postgres>, Option<String>)>, sqlx::Error>;
+
+    async fn delete_upload(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        track_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Whether a track other than `track_id` keeps its file under `file_name`.
+    async fn is_file_shared(&self, track_id: Uuid, file_name: &str) -> Result<bool, sqlx::Error>;
 }
 
 #[async_trait]
@@ -170,45 +214,94 @@ impl UploadExt for DBClients {
     async fn upload_thumbnail(
         &self,
         track_id: Uuid,
-        thumbnail_name: &String,
-        title: &String,
-        artist: &String,
-    ) -> Result<(), sqlx::Error> {
+        artwork_id: Option<Uuid>,
+        details: &TrackDetails<'_>,
+        user_id: Uuid,
+        is_admin: bool,
+    ) -> Result<bool, sqlx::Error> {
+
+        // Empty fields keep what is already stored, e.g. tags read from the file
+        let result = query!(
+            r#"
+            UPDATE tracks
+            SET title = COALESCE(NULLIF($1, ''), title),
+                artist = COALESCE(NULLIF($2, ''), artist),
+                artwork_id = COALESCE($3, artwork_id),
+                license = COALESCE(NULLIF($4, ''), license),
+                updated_at = Now()
+            WHERE id = $5 AND (user_id = $6 OR $7)
+            "#,
+            details.title,
+            details.artist,
+            artwork_id,
+            details.license,
+            track_id,
+            user_id,
+            is_admin
+        ).execute(&self.pool)
+        .await?;
 
+        Ok(result.rows_affected() > 0)
+
+    }
+
+    async fn apply_embedded_tags(
+        &self,
+        track_id: Uuid,
+        tags: &EmbeddedTags,
+        artwork_id: Option<Uuid>,
+    ) -> Result<(), sqlx::Error> {
+        // A cover never replaces artwork the user uploaded, old or new style
         query!(
             r#"
             UPDATE tracks
-            SET title = $1,
-                artist = $2,
-                thumbnail_name = $3,
+            SET title = COALESCE(NULLIF(title, ''), $2),
+                artist = COALESCE(NULLIF(artist, ''), $3),
+                album = COALESCE(album, $4),
+                album_artist = COALESCE(album_artist, $5),
+                track_number = COALESCE(track_number, $6),
+                disc_number = COALESCE(disc_number, $7),
+                release_year = COALESCE(release_year, $8),
+                genre = COALESCE(genre, $9),
+                isrc = COALESCE(isrc, $10),
+                artwork_id = CASE
+                    WHEN artwork_id IS NULL AND thumbnail_name IS NULL THEN $11
+                    ELSE artwork_id
+                END,
                 updated_at = Now()
-            WHERE id = $4
+            WHERE id = $1
             "#,
-            title,
-            artist,
-            thumbnail_name,
-            track_id
+            track_id,
+            tags.title,
+            tags.artist,
+            tags.album,
+            tags.album_artist,
+            tags.track_number,
+            tags.disc_number,
+            tags.release_year,
+            tags.genre,
+            tags.isrc,
+            artwork_id,
         ).execute(&self.pool)
         .await?;
 
         Ok(())
-
     }
 
     async fn update_status(
         &self,
         track_id: Uuid,
-        duration: i64,
+        duration_ms: i64,
     ) -> Result<(), sqlx::Error> {
         let pg_duration = PgInterval {
             days: 0,
             months: 0,
-            microseconds: duration * 1_000_000
+            microseconds: duration_ms * 1_000
         };
         query!(
             r#"
             UPDATE tracks
-            SET upload_status = 'complete',
+            SET processing_status = 'ready',
                 duration = $2,
                 updated_at = Now()
             WHERE id = $1
@@ -218,11 +311,41 @@ impl UploadExt for DBClients {
         ).execute(&self.pool)
         .await?;
 
+        Ok(())
+    }
+
+    async fn save_audio_format(
+        &self,
+        track_id: Uuid,
+        format: &AudioFormat,
+    ) -> Result<(), sqlx::Error> {
         query!(
             r#"
-                DELETE FROM audio_files WHERE track_id = $1;
+            UPDATE tracks
+            SET container = $2,
+                codec = $3,
+                mime_type = $4,
+                sample_rate = $5,
+                bit_depth = $6,
+                channels = $7,
+                bitrate_kbps = $8,
+                encoder_delay = $9,
+                encoder_padding = $10,
+                total_samples = $11,
+                updated_at = Now()
+            WHERE id = $1
             "#,
-            track_id
+            track_id,
+            format.container.name(),
+            format.codec,
+            format.container.mime_type(),
+            format.sample_rate as i32,
+            format.bit_depth.map(|bits| bits as i32),
+            format.channels as i32,
+            format.bitrate_kbps as i32,
+            format.gapless.encoder_delay as i32,
+            format.gapless.encoder_padding as i32,
+            format.gapless.total_samples as i64
         ).execute(&self.pool)
         .await?;
 
@@ -260,4 +383,180 @@ impl UploadExt for DBClients {
 
         Ok(uploads)
     }
-}
\ No newline at end of file
+
+    async fn lock_pending_upload(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Transaction<'static, Postgres>, String)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // A share lock lets chunks of the same upload proceed in parallel
+        // while a cancel has to wait for them, and they for it.
+        let track = sqlx::query!(
+            r#"
+            SELECT file_name FROM tracks
+            WHERE id = $1 AND user_id = $2 AND processing_status IS NULL
+            FOR SHARE
+            "#,
+            track_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match track.and_then(|track| track.file_name) {
+            Some(file_name) => Ok(Some((tx, file_name))),
+            None => {
+                tx.rollback().await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn lock_upload_for_delete(
+        &self,
+        track_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<(Transaction<'static, Postgres>, Option<String>)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let track = sqlx::query!(
+            r#"
+            SELECT processing_status FROM tracks
+            WHERE id = $1 AND user_id = $2
+            FOR UPDATE
+            "#,
+            track_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match track {
+            Some(track) => Ok(Some((tx, track.processing_status))),
+            None => {
+                tx.rollback().await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn delete_upload(
+        &self,
+        tx: &mut Transaction<'static, Postgres>,
+        track_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM audio_files WHERE track_id = $1
+            "#,
+            track_id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM tracks WHERE id = $1
+            "#,
+            track_id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn is_file_shared(&self, track_id: Uuid, file_name: &str) -> Result<bool, sqlx::Error> {
+        let shared = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM tracks WHERE file_name = $1 AND id <> $2
+            ) AS "shared!"
+            "#,
+            file_name,
+            track_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn license(license: &str) -> TrackDetails<'_> {
+        TrackDetails { title: "", artist: "", license }
+    }
+
+    async fn create_user(db: &DBClients, name: &str) -> Uuid {
+        query!(
+            r#"
+            INSERT INTO users (username, email, password_hash)
+            VALUES ($1, $2, '')
+            RETURNING id
+            "#,
+            format!("{}-{}", name, Uuid::new_v4()),
+            format!("{}-{}@example.com", name, Uuid::new_v4())
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap()
+        .id
+    }
+
+    #[tokio::test]
+    async fn only_owner_or_admin_changes_track_details() {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPoolOptions::new().max_connections(1).connect(&database_url).await.unwrap();
+        let db = DBClients::new(pool);
+
+        let owner = create_user(&db, "owner").await;
+        let other = create_user(&db, "other").await;
+        let track_id = db.upload_file(owner, &"song.flac".to_string()).await.unwrap();
+
+        let by_other = db.upload_thumbnail(track_id, None, &license("cc0"), other, false).await.unwrap();
+        let license_after_other = query!("SELECT license FROM tracks WHERE id = $1", track_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+            .license;
+        let by_admin = db.upload_thumbnail(track_id, None, &license("cc-by"), other, true).await.unwrap();
+        let by_owner = db.upload_thumbnail(track_id, None, &license("cc0"), owner, false).await.unwrap();
+
+        query!("DELETE FROM tracks WHERE id = $1", track_id).execute(&db.pool).await.unwrap();
+        query!("DELETE FROM users WHERE id = ANY($1)", &[owner, other]).execute(&db.pool).await.unwrap();
+
+        assert!(!by_other);
+        assert_eq!(license_after_other, "all-rights-reserved");
+        assert!(by_admin);
+        assert!(by_owner);
+    }
+
+    #[tokio::test]
+    async fn file_is_shared_only_with_other_tracks() {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPoolOptions::new().max_connections(1).connect(&database_url).await.unwrap();
+        let db = DBClients::new(pool);
+
+        let owner = create_user(&db, "owner").await;
+        let file_name = format!("{}.flac", Uuid::new_v4());
+        let first = db.upload_file(owner, &file_name).await.unwrap();
+        let alone = db.is_file_shared(first, &file_name).await.unwrap();
+        let second = db.upload_file(owner, &file_name).await.unwrap();
+        let shared = db.is_file_shared(first, &file_name).await.unwrap();
+
+        query!("DELETE FROM tracks WHERE id = ANY($1)", &[first, second]).execute(&db.pool).await.unwrap();
+        query!("DELETE FROM users WHERE id = $1", owner).execute(&db.pool).await.unwrap();
+
+        assert!(!alone);
+        assert!(shared);
+    }
+}
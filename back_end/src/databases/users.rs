@@ -30,6 +30,18 @@ pub trait UserExt {
         user_id: Uuid,
         new_password_hash: String
     ) -> Result<User, sqlx::Error>;
+
+    async fn update_user_role(
+        &self,
+        user_id: Uuid,
+        role: &str
+    ) -> Result<User, sqlx::Error>;
+
+    async fn update_user_tier(
+        &self,
+        user_id: Uuid,
+        tier: &str
+    ) -> Result<User, sqlx::Error>;
 }
 
 #[async_trait]
@@ -49,6 +61,8 @@ impl UserExt for DBClients {
                 username, 
                 email, 
                 password_hash,  
+                role,
+                tier,
                 created_at, 
                 updated_at 
             FROM users 
@@ -79,7 +93,7 @@ impl UserExt for DBClients {
             r#"
             INSERT INTO users (username, email, password_hash) 
             VALUES ($1, $2, $3) 
-            RETURNING id, username, email, password_hash, created_at, updated_at
+            RETURNING id, username, email, password_hash, role, tier, created_at, updated_at
             "#,
             username.into(),
             email.into(),
@@ -101,7 +115,7 @@ impl UserExt for DBClients {
             UPDATE users
             SET username = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password_hash, created_at, updated_at
+            RETURNING id, username, email, password_hash, role, tier, created_at, updated_at
             "#,
             username.into(),
             user_id
@@ -122,7 +136,7 @@ impl UserExt for DBClients {
             UPDATE users
             SET password_hash = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password_hash, created_at, updated_at
+            RETURNING id, username, email, password_hash, role, tier, created_at, updated_at
             "#,
             new_password_hash,
             user_id
@@ -131,4 +145,46 @@ impl UserExt for DBClients {
 
         Ok(user)
     }
-}
\ No newline at end of file
+
+    async fn update_user_role(
+        &self,
+        user_id: Uuid,
+        role: &str
+    ) -> Result<User, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET role = $1, updated_at = Now()
+            WHERE id = $2
+            RETURNING id, username, email, password_hash, role, tier, created_at, updated_at
+            "#,
+            role,
+            user_id
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn update_user_tier(
+        &self,
+        user_id: Uuid,
+        tier: &str
+    ) -> Result<User, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET tier = $1, updated_at = Now()
+            WHERE id = $2
+            RETURNING id, username, email, password_hash, role, tier, created_at, updated_at
+            "#,
+            tier,
+            user_id
+        ).fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+}
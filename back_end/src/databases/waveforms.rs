@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::dbs::DBClients;
+
+#[async_trait]
+pub trait WaveformExt {
+    async fn save_waveform(
+        &self,
+        track_id: Uuid,
+        resolution: &str,
+        samples_per_pixel: i32,
+        data: &[u8],
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_waveform(
+        &self,
+        track_id: Uuid,
+        resolution: &str,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error>;
+}
+
+#[async_trait]
+impl WaveformExt for DBClients {
+    async fn save_waveform(
+        &self,
+        track_id: Uuid,
+        resolution: &str,
+        samples_per_pixel: i32,
+        data: &[u8],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO track_waveforms (track_id, resolution, samples_per_pixel, data)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (track_id, resolution) DO UPDATE
+            SET samples_per_pixel = EXCLUDED.samples_per_pixel,
+                data = EXCLUDED.data,
+                created_at = Now()
+            "#,
+            track_id,
+            resolution,
+            samples_per_pixel,
+            data
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_waveform(
+        &self,
+        track_id: Uuid,
+        resolution: &str,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        let waveform = sqlx::query!(
+            r#"
+            SELECT w.data
+            FROM track_waveforms w
+            JOIN tracks t ON t.id = w.track_id
+            WHERE w.track_id = $1 AND w.resolution = $2 AND t.processing_status = 'ready'
+            "#,
+            track_id,
+            resolution
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(waveform.map(|waveform| waveform.data))
+    }
+}
@@ -1,9 +1,13 @@
 use chrono::NaiveDateTime;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use validator::{Validate, ValidationError};
 
-use crate::models::{Duration, User};
+use crate::{
+    media::loudness::{replaygain_gain_db, replaygain_peak},
+    models::{Duration, User},
+};
 
 #[derive(Validate, Debug, Default, Clone, Deserialize, Serialize)]
 pub struct RegisterUserDto {
@@ -75,6 +79,8 @@ pub struct FilterUserDto {
     pub id: String,
     pub username: String,
     pub email: String,
+    pub role: String,
+    pub tier: String,
 
     #[serde(rename = "createAt")]
     pub created_at: NaiveDateTime,
@@ -89,21 +95,57 @@ impl FilterUserDto {
             id: user.id.to_string(),
             username: user.username.clone(),
             email: user.email.clone(),
+            role: user.role.clone(),
+            tier: user.tier.clone(),
             created_at: user.created_at.unwrap(),
             updated_at: user.updated_at.unwrap(),
         }
     }
 }
 
+/// One size and format of a track or playlist artwork.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtworkUrlDto {
+    pub size: i32,
+    pub format: String,
+    pub url: String,
+}
+
 #[derive(Debug, Serialize, Deserialize,sqlx::FromRow)]
 pub struct TrackDto {
     pub id: uuid::Uuid,
     pub title: Option<String>,
     pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub album_group: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub release_year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    pub loudness_lufs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+    pub album_loudness_lufs: Option<f64>,
+    pub album_true_peak_dbtp: Option<f64>,
+    pub container: Option<String>,
+    pub codec: Option<String>,
+    pub mime_type: Option<String>,
+    pub sample_rate: Option<i32>,
+    pub bit_depth: Option<i32>,
+    pub channels: Option<i32>,
+    pub bitrate_kbps: Option<i32>,
+    pub encoder_delay: Option<i32>,
+    pub encoder_padding: Option<i32>,
+    pub total_samples: Option<i64>,
+    pub play_count: i64,
+    pub license: String,
+    pub license_allows_download: bool,
     pub duration: Duration,
     pub file_name: Option<String>,
     pub upload_status: Option<String>,
     pub thumbnail_name: Option<String>,
+    pub artwork: Json<Vec<ArtworkUrlDto>>,
     pub is_favorite: Option<bool>,
     pub duration_played: Duration,
     pub played_at: Option<chrono::NaiveDateTime>,
@@ -178,16 +220,159 @@ pub struct InCompleteTractInfoResponse {
     pub incomplete_track_info: Vec<InCompleteTrackInfo>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackStatusDto {
+    pub track_id: uuid::Uuid,
+    pub upload_status: Option<String>,
+    pub processing_status: Option<String>,
+    pub job_status: Option<String>,
+    pub attempts: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+/// Outcome of one entry of an album uploaded as a ZIP archive.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ArchiveEntryDto {
+    pub entry_name: String,
+    pub status: String,
+    pub track_id: Option<uuid::Uuid>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackStatusResponseDto {
+    #[serde(flatten)]
+    pub status: TrackStatusDto,
+    /// Only present for archive uploads
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<ArchiveEntryDto>,
+    /// Tracks that most likely are the same recording
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<DuplicateDto>,
+}
+
+/// A track that sounds the same as another one.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DuplicateDto {
+    pub track_id: uuid::Uuid,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Share of matching fingerprint bits, from 0.5 for unrelated audio to 1.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f32>,
+}
+
+/// Tracks that were all found to be copies of each other, earliest upload first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateClusterDto {
+    pub tracks: Vec<DuplicateDto>,
+    /// Lowest similarity of the pairs that joined the cluster
+    pub min_similarity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateClustersResponseDto {
+    pub clusters: Vec<DuplicateClusterDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TrackRenditionDto {
+    pub quality: String,
+    pub codec: String,
+    pub mime_type: String,
+    pub bitrate_kbps: Option<i32>,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub file_size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackRenditionsResponseDto {
+    pub track_id: uuid::Uuid,
+    pub renditions: Vec<TrackRenditionDto>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQueryDto {
+    pub quality: Option<String>,
+    /// Transcode into this format, see `media::transcode`
+    pub format: Option<String>,
+    pub bitrate: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignedUrlQueryDto {
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedTrackUrlResponseDto {
+    pub stream_url: String,
+    pub hls_url: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaveformQueryDto {
+    pub resolution: Option<String>,
+    pub format: Option<String>,
+}
+
+/// Same layout as the JSON output of audiowaveform.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaveformJsonDto {
+    pub version: i32,
+    pub channels: i32,
+    pub sample_rate: u32,
+    pub samples_per_pixel: usize,
+    pub bits: i32,
+    pub length: usize,
+    pub data: Vec<i8>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FilterTrackDto {
     pub id: uuid::Uuid,
     pub title: Option<String>,
     pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    /// Shared by the tracks of an album, which play back to back without a gap
+    pub album_group: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub release_year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    pub replaygain_track_gain: Option<f64>,
+    pub replaygain_track_peak: Option<f64>,
+    pub replaygain_album_gain: Option<f64>,
+    pub replaygain_album_peak: Option<f64>,
+    pub container: Option<String>,
+    pub codec: Option<String>,
+    pub mime_type: Option<String>,
+    pub sample_rate: Option<i32>,
+    pub bit_depth: Option<i32>,
+    pub channels: Option<i32>,
+    pub bitrate_kbps: Option<i32>,
+    /// Samples per channel to skip at the start, missing for tracks ingested before it was recorded
+    pub encoder_delay: Option<i32>,
+    /// Samples per channel to drop at the end
+    pub encoder_padding: Option<i32>,
+    /// Samples per channel left after trimming, exact unlike the duration
+    pub total_samples: Option<i64>,
+    /// Plays verified from stream sessions, see `utils::stream_sessions`
+    pub play_count: i64,
+    pub license: String,
+    /// Owners can always download their uploads, others only under an open license
+    pub can_download: bool,
     pub duration_minutes: f64,
     pub duration_seconds: f64,
+    pub duration_ms: i64,
     pub duration_played: f64,
     pub file_name: Option<String>,
     pub thumbnail_name: Option<String>,
+    pub artwork: Vec<ArtworkUrlDto>,
     pub is_favorite: Option<bool>,
     pub played_at: Option<chrono::NaiveDateTime>,
     pub is_created_by_user: Option<bool>,
@@ -199,11 +384,38 @@ impl FilterTrackDto {
             id: track.id,
             title: track.title.clone(),
             artist: track.artist.clone(),
+            album: track.album.clone(),
+            album_artist: track.album_artist.clone(),
+            album_group: track.album_group.clone(),
+            track_number: track.track_number,
+            disc_number: track.disc_number,
+            release_year: track.release_year,
+            genre: track.genre.clone(),
+            isrc: track.isrc.clone(),
+            replaygain_track_gain: track.loudness_lufs.map(replaygain_gain_db),
+            replaygain_track_peak: track.true_peak_dbtp.map(replaygain_peak),
+            replaygain_album_gain: track.album_loudness_lufs.map(replaygain_gain_db),
+            replaygain_album_peak: track.album_true_peak_dbtp.map(replaygain_peak),
+            container: track.container.clone(),
+            codec: track.codec.clone(),
+            mime_type: track.mime_type.clone(),
+            sample_rate: track.sample_rate,
+            bit_depth: track.bit_depth,
+            channels: track.channels,
+            bitrate_kbps: track.bitrate_kbps,
+            encoder_delay: track.encoder_delay,
+            encoder_padding: track.encoder_padding,
+            total_samples: track.total_samples,
+            play_count: track.play_count,
+            license: track.license.clone(),
+            can_download: track.license_allows_download || track.is_created_by_user == Some(true),
             duration_minutes: convert_duration_to_minutes(&track.duration),
             duration_seconds: convert_duration_to_seconds(&track.duration),
+            duration_ms: convert_duration_to_milliseconds(&track.duration),
             duration_played: convert_duration_to_seconds(&track.duration_played),
             file_name: track.file_name.clone(),
             thumbnail_name: track.thumbnail_name.clone(),
+            artwork: track.artwork.0.clone(),
             is_favorite: track.is_favorite,
             played_at: track.played_at,
             is_created_by_user: track.is_created_by_user,
@@ -216,16 +428,17 @@ impl FilterTrackDto {
 }
 
 fn convert_duration_to_minutes(duration: &Duration) -> f64 {
-    let total_seconds = (duration.months * 30 * 24 * 60 * 60) as f64
-        + (duration.days * 24 * 60 * 60) as f64
-        + (duration.microseconds as f64 / 1_000_000.0);
-    total_seconds / 60.0
+    convert_duration_to_seconds(duration) / 60.0
 }
 
 fn convert_duration_to_seconds(duration: &Duration) -> f64 {
-    (duration.months * 30 * 24 * 60 * 60) as f64
-        + (duration.days * 24 * 60 * 60) as f64
-        + (duration.microseconds as f64 / 1_000_000.0)
+    convert_duration_to_milliseconds(duration) as f64 / 1_000.0
+}
+
+fn convert_duration_to_milliseconds(duration: &Duration) -> i64 {
+    // Widen before multiplying, an interval of a few months overflows i32 seconds
+    (duration.months as i64 * 30 + duration.days as i64) * 24 * 60 * 60 * 1_000
+        + duration.microseconds / 1_000
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -243,6 +456,7 @@ pub struct PlayListDto {
     pub id: uuid::Uuid,
     pub title: String,
     pub thumbnail_path: Option<String>,
+    pub artwork: Json<Vec<ArtworkUrlDto>>,
     pub max_track_order: Option<i32>,
 }
 
@@ -267,4 +481,60 @@ pub struct PlaybackMessageDto {
 pub struct NameUpdateDto {
     #[validate(length(min = 1, message = "Name is required"))]
     pub name: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchQueryDto {
+    #[validate(length(min = 1, max = 200, message = "Search query must be between 1 and 200 characters"))]
+    pub q: String,
+
+    /// Only `tracks`, `playlists` or `users`, for paging through one group
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+
+    #[validate(range(min = 1))]
+    pub page: Option<usize>,
+
+    #[validate(range(min = 1, max = 50))]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchPlaylistDto {
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub thumbnail_path: Option<String>,
+    pub artwork: Json<Vec<ArtworkUrlDto>>,
+    /// Username of the owner
+    pub owner: Option<String>,
+    pub track_count: i64,
+    pub is_created_by_user: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchUserDto {
+    pub id: uuid::Uuid,
+    pub username: String,
+    /// Tracks ready to be played
+    pub track_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResultsDto<T> {
+    pub items: Vec<T>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResponseDto {
+    pub query: String,
+    pub page: usize,
+    pub limit: usize,
+    // Groups left out with `type` are missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracks: Option<SearchResultsDto<FilterTrackDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playlists: Option<SearchResultsDto<SearchPlaylistDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users: Option<SearchResultsDto<SearchUserDto>>,
 }
\ No newline at end of file
@@ -33,6 +33,8 @@ pub enum ErrorMessage {
     UsernameExist,
     UserNoLongerExist,
     TokenNotProvided,
+    PermissionDenied,
+    InvalidSignedUrl,
 }
 
 impl ToString for ErrorMessage {
@@ -54,6 +56,8 @@ impl ErrorMessage {
             ErrorMessage::ExceededMaxPasswordLength(max_length) => format!("Password must not be more than {} characters" , max_length),
             ErrorMessage::InvalidToken => "Authentication token is invalid or expired".to_string(),
             ErrorMessage::TokenNotProvided => "You are not logged in, please provide token".to_string(),
+            ErrorMessage::PermissionDenied => "You are not allowed to perform this action".to_string(),
+            ErrorMessage::InvalidSignedUrl => "This link is invalid or has expired".to_string(),
         }
     }
 }
@@ -100,6 +104,20 @@ impl HttpError {
         }
     }
 
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::FORBIDDEN,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::NOT_FOUND,
+        }
+    }
+
     pub fn into_http_response(self) -> Response {
         let json_response = Json(ErrorResponse {
             status: self.status.to_string(),
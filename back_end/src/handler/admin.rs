@@ -0,0 +1,159 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{response::IntoResponse, routing::get, Extension, Json, Router};
+use uuid::Uuid;
+
+use crate::{
+    databases::fingerprints::FingerprintExt,
+    dtos::{DuplicateClusterDto, DuplicateClustersResponseDto},
+    errors::HttpError,
+    AppState,
+};
+
+pub fn admin_handler() -> Router {
+    Router::new()
+        .route("/duplicates", get(get_duplicate_clusters_handler))
+}
+
+/// Groups the recorded duplicate pairs into clusters of tracks that are all
+/// copies of one recording, for review.
+pub async fn get_duplicate_clusters_handler(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let pairs = app_state
+        .db_client
+        .get_duplicate_pairs()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut clusters = Clusters::default();
+    for (track_id, duplicate_of, similarity) in &pairs {
+        clusters.join(*track_id, *duplicate_of, *similarity);
+    }
+
+    let mut response = Vec::new();
+    for (track_ids, min_similarity) in clusters.into_groups() {
+        let tracks = app_state
+            .db_client
+            .get_duplicate_tracks(&track_ids)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        response.push(DuplicateClusterDto {
+            tracks,
+            min_similarity: min_similarity as f32,
+        });
+    }
+
+    // The least certain clusters need a closer look, they come first
+    response.sort_by(|a, b| a.min_similarity.total_cmp(&b.min_similarity));
+
+    Ok(Json(DuplicateClustersResponseDto { clusters: response }))
+}
+
+/// Union-find over track ids.
+#[derive(Default)]
+struct Clusters {
+    parents: HashMap<Uuid, Uuid>,
+    min_similarity: HashMap<Uuid, f64>,
+}
+
+impl Clusters {
+    fn find(&mut self, track_id: Uuid) -> Uuid {
+        let parent = *self.parents.entry(track_id).or_insert(track_id);
+        if parent == track_id {
+            return track_id;
+        }
+
+        let root = self.find(parent);
+        self.parents.insert(track_id, root);
+        root
+    }
+
+    fn join(&mut self, a: Uuid, b: Uuid, similarity: f64) {
+        let root_a = self.find(a);
+        let root_b = self.find(b);
+
+        let mut min_similarity = similarity;
+        for root in [root_a, root_b] {
+            if let Some(existing) = self.min_similarity.remove(&root) {
+                min_similarity = min_similarity.min(existing);
+            }
+        }
+
+        self.parents.insert(root_b, root_a);
+        self.min_similarity.insert(root_a, min_similarity);
+    }
+
+    /// Track ids of every cluster with its lowest pair similarity.
+    fn into_groups(mut self) -> Vec<(Vec<Uuid>, f64)> {
+        let track_ids: Vec<Uuid> = self.parents.keys().copied().collect();
+
+        let mut groups: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for track_id in track_ids {
+            let root = self.find(track_id);
+            groups.entry(root).or_default().push(track_id);
+        }
+
+        groups
+            .into_iter()
+            .map(|(root, track_ids)| {
+                let min_similarity = self.min_similarity.get(&root).copied().unwrap_or(1.0);
+                (track_ids, min_similarity)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_groups(clusters: Clusters) -> Vec<(Vec<Uuid>, f64)> {
+        let mut groups: Vec<_> = clusters
+            .into_groups()
+            .into_iter()
+            .map(|(mut track_ids, similarity)| {
+                track_ids.sort();
+                (track_ids, similarity)
+            })
+            .collect();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        groups
+    }
+
+    #[test]
+    fn joins_pairs_into_clusters() {
+        let mut ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        ids.sort();
+        let [a, b, c, d, e] = ids[..] else { unreachable!() };
+
+        let mut clusters = Clusters::default();
+        clusters.join(a, b, 0.9);
+        clusters.join(c, b, 0.8);
+        clusters.join(d, e, 0.95);
+
+        assert_eq!(sorted_groups(clusters), vec![(vec![a, b, c], 0.8), (vec![d, e], 0.95)]);
+    }
+
+    #[test]
+    fn merges_clusters_with_their_lowest_similarity() {
+        let mut ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        ids.sort();
+        let [a, b, c, d] = ids[..] else { unreachable!() };
+
+        let mut clusters = Clusters::default();
+        clusters.join(a, b, 0.7);
+        clusters.join(c, d, 0.9);
+        clusters.join(b, d, 0.85);
+        // Pairs within one cluster still lower its similarity
+        clusters.join(a, c, 0.6);
+
+        assert_eq!(sorted_groups(clusters), vec![(vec![a, b, c, d], 0.6)]);
+    }
+
+    #[test]
+    fn has_no_clusters_without_pairs() {
+        assert!(Clusters::default().into_groups().is_empty());
+    }
+}
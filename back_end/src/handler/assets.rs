@@ -0,0 +1,31 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::Path, response::{IntoResponse, Redirect}, routing::get, Extension, Router};
+use tower_http::services::ServeDir;
+
+use crate::{errors::HttpError, storage::Storage, AppState};
+
+/// How long the bucket URLs handed out for artwork stay valid.
+const ASSET_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Artwork and thumbnails, read from disk with local storage and otherwise
+/// redirected to a signed URL of the bucket.
+pub fn assets_handler(storage: &dyn Storage) -> Router {
+    match storage.local_path("assets") {
+        Some(dir) => Router::new().fallback_service(ServeDir::new(dir)),
+        None => Router::new().route("/{*path}", get(redirect_to_storage)),
+    }
+}
+
+async fn redirect_to_storage(
+    Path(path): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let url = app_state.storage
+        .presign(&format!("assets/{}", path), ASSET_URL_TTL)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Asset not found"))?;
+
+    Ok(Redirect::temporary(&url))
+}
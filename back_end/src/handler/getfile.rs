@@ -1,32 +1,52 @@
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
+use md5::Digest;
+use tokio::sync::watch;
 use uuid::Uuid;
 
 use axum::{
     body::Body,
-    extract::{Path, Request},
-    http::{header, Response}, 
+    extract::{Path, Query, Request},
+    http::{header, Method, Response, StatusCode}, 
     response::IntoResponse,
-    routing::get,
+    routing::{get, post},
     Extension,
     Json,
     Router,
 };
 
 use crate::{
-    auth::JWTAuthMiddleware,
-    databases::{track::TrackExt, upload::UploadExt},
+    auth::{JWTAuthMiddleware, StreamAccess},
+    databases::{archives::ArchiveExt, fingerprints::FingerprintExt, jobs::JobExt, renditions::RenditionExt, streams::StreamRequest, track::TrackExt, upload::UploadExt, waveforms::WaveformExt},
     errors::HttpError,
-    dtos::{FilterTrackDto, InCompleteTractInfoResponse, TrackResponseDto},
+    dtos::{
+        FilterTrackDto, InCompleteTractInfoResponse, SignedTrackUrlResponseDto,
+        SignedUrlQueryDto, StreamQueryDto, TrackRenditionsResponseDto,
+        TrackResponseDto, TrackStatusResponseDto, WaveformJsonDto, WaveformQueryDto,
+    },
+    media::{
+        renditions::{self, ORIGINAL_QUALITY},
+        transcode::{self, TranscodeTarget, TRANSCODED_QUALITY},
+        waveform::{self, Waveform},
+    },
+    storage::{cache, WorkingFile},
+    utils::{range::{self, RangeRequest}, signed_url, stream_limits, stream_sessions},
     AppState,
 };
 
-use tokio::fs;
-
 pub fn get_file_handler() -> Router {
     Router::new()
         .route("/incomplete", get(get_incomplete_uploads_handler))
         .route("/track", get(get_random_tracks_handler))
-        .route("/play/{file_name}", get(stream_audio))
+        .route("/track/{track_id}/status", get(get_track_status_handler))
+        .route("/track/{track_id}/renditions", get(get_track_renditions_handler))
+        .route("/track/{track_id}/waveform", get(get_track_waveform_handler))
+        .route("/track/{track_id}/signed-url", post(get_signed_track_url_handler))
+}
+
+/// Routes that also take a signed URL in place of a token, see `auth::stream_auth`.
+pub fn stream_handler() -> Router {
+    Router::new()
+        .route("/play/{track_id}", get(stream_audio))
 }
 
 pub async fn get_incomplete_uploads_handler(
@@ -70,58 +90,452 @@ pub async fn get_random_tracks_handler(
     Ok(Json(response))
 }
 
+pub async fn get_track_status_handler(
+    Path(track_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user_id = user.user.id;
+
+    let status = app_state
+        .db_client
+        .get_track_status(track_id, user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    let entries = app_state
+        .db_client
+        .get_archive_entries(track_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let duplicates = app_state
+        .db_client
+        .get_track_duplicates(track_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(Json(TrackStatusResponseDto { status, entries, duplicates }))
+}
+
+pub async fn get_track_renditions_handler(
+    Path(track_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    app_state
+        .db_client
+        .get_streamable_track(track_id, user.user.id, user.user.role == "admin")
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    let renditions = app_state
+        .db_client
+        .get_track_renditions(track_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    Ok(Json(TrackRenditionsResponseDto { track_id, renditions }))
+}
+
+pub async fn get_track_waveform_handler(
+    Path(track_id): Path<Uuid>,
+    Query(query): Query<WaveformQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+    req: Request<Body>,
+) -> Result<impl IntoResponse, HttpError> {
+    app_state
+        .db_client
+        .get_streamable_track(track_id, user.user.id, user.user.role == "admin")
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    let resolution = query.resolution.as_deref().unwrap_or(waveform::DEFAULT_RESOLUTION);
+    if !waveform::is_resolution(resolution) {
+        return Err(HttpError::bad_request(format!("Unknown resolution '{}'", resolution)));
+    }
+
+    let format = query.format.as_deref().unwrap_or("json");
+    if format != "json" && format != "dat" {
+        return Err(HttpError::bad_request("Format must be 'json' or 'dat'"));
+    }
+
+    let dat = app_state
+        .db_client
+        .get_waveform(track_id, resolution)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Waveform not found"))?;
+
+    // Peaks never change once generated, the ETag only differs between formats
+    let etag = format!("\"{:x}-{}\"", md5::Md5::digest(&dat), format);
+    let cache_control = "private, max-age=86400";
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false);
+    if not_modified {
+        let response: Response<Body> = Response::builder()
+            .status(304)
+            .header(header::ETAG, &etag)
+            .header(header::CACHE_CONTROL, cache_control)
+            .body(Body::empty())
+            .unwrap();
+        return Ok(response);
+    }
+
+    let (content_type, body) = if format == "dat" {
+        ("application/octet-stream", dat)
+    } else {
+        let waveform = Waveform::from_dat(&dat).map_err(HttpError::server_error)?;
+        let json = WaveformJsonDto {
+            version: 2,
+            channels: 1,
+            sample_rate: waveform.sample_rate,
+            samples_per_pixel: waveform.samples_per_pixel,
+            bits: 8,
+            length: waveform.point_count(),
+            data: waveform.data,
+        };
+        let body = serde_json::to_vec(&json).map_err(|e| HttpError::server_error(e.to_string()))?;
+        ("application/json", body)
+    };
+
+    let response: Response<Body> = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(Body::from(body))
+        .unwrap();
+
+    Ok(response)
+}
+
+/// Expiry of a signed URL asked to live `expires_in` seconds, capped by the configured TTL.
+fn signed_url_expiry(app_state: &AppState, expires_in: Option<u64>) -> chrono::DateTime<chrono::Utc> {
+    let ttl = app_state.env.signed_url_ttl_seconds;
+    let seconds = expires_in.unwrap_or(ttl).clamp(1, ttl.max(1));
+
+    chrono::Utc::now() + chrono::Duration::seconds(seconds as i64)
+}
+
+pub async fn get_signed_track_url_handler(
+    Path(track_id): Path<Uuid>,
+    Query(query): Query<SignedUrlQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    app_state
+        .db_client
+        .get_streamable_track(track_id, user.user.id, user.user.role == "admin")
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    let expires_at = signed_url_expiry(&app_state, query.expires_in);
+    let key = app_state.env.url_signing_key.as_bytes();
+
+    let stream_path = format!("/api/get/play/{}", track_id);
+    let stream_query = signed_url::signed_query(key, &stream_path, user.user.id, user.session_id, expires_at.timestamp());
+
+    // One signature for the playlists and every segment below them
+    let hls_scope = format!("/api/hls/{}/", track_id);
+    let hls_query = signed_url::signed_query(key, &hls_scope, user.user.id, user.session_id, expires_at.timestamp());
+
+    Ok(Json(SignedTrackUrlResponseDto {
+        stream_url: format!("{}?{}", stream_path, stream_query),
+        hls_url: format!("{}master.m3u8?{}", hls_scope, hls_query),
+        expires_at,
+    }))
+}
+
 async fn stream_audio(
-    Path(file_name): Path<String>,
-    Extension(_app_state): Extension<Arc<AppState>>,
-    Extension(_user): Extension<JWTAuthMiddleware>,
+    Path(track_id): Path<Uuid>,
+    Query(query): Query<StreamQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(access): Extension<StreamAccess>,
     req: Request<Body>,
 ) -> Result<impl IntoResponse, HttpError> {
-    let file_path = PathBuf::from("uploads/").join(file_name);
+    let quality = query.quality.as_deref().unwrap_or(ORIGINAL_QUALITY);
+    if quality != ORIGINAL_QUALITY && renditions::find_spec(quality).is_none() {
+        return Err(HttpError::bad_request(format!("Unknown quality '{}'", quality)));
+    }
 
-    let headers = req.headers();
+    // Tracks the user may not see look the same as tracks that don't exist
+    let (file_name, mime_type) = app_state
+        .db_client
+        .get_streamable_track(track_id, access.user_id, access.is_admin)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    // Tracks ingested before formats were recorded go by their extension
+    let original_mime_type = mime_type
+        .or_else(|| mime_guess::from_path(&file_name).first().map(|mime| mime.to_string()))
+        .unwrap_or_else(|| "audio/mpeg".to_string());
+
+    // An explicit format wins over the Accept header, which only matters for the original
+    let ffmpeg_available = app_state.env.ffmpeg_path.is_some();
+    let target = if query.format.is_some() || query.bitrate.is_some() {
+        if quality != ORIGINAL_QUALITY {
+            return Err(HttpError::bad_request("Ask for either a quality or a format, not both"));
+        }
+        Some(
+            TranscodeTarget::parse(query.format.as_deref(), query.bitrate, ffmpeg_available)
+                .map_err(HttpError::bad_request)?,
+        )
+    } else if quality == ORIGINAL_QUALITY {
+        let accept = req.headers().get(header::ACCEPT).and_then(|value| value.to_str().ok());
+        transcode::negotiate(accept, &original_mime_type, ffmpeg_available).map_err(|_| {
+            HttpError::new("None of the accepted formats can be streamed", StatusCode::NOT_ACCEPTABLE)
+        })?
+    } else {
+        None
+    };
+    // Asking for the format the file already has needs no transcoding
+    let target = target.filter(|target| {
+        target.codec.mime_type() != original_mime_type || target.bitrate_kbps.is_some()
+    });
+
+    // A rendition in the asked for format spares a transcode
+    let rendition_quality = match target {
+        Some(target) => renditions::RENDITIONS
+            .iter()
+            .find(|spec| spec.codec == target.codec && spec.bitrate_kbps == target.bitrate_kbps)
+            .map(|spec| spec.quality),
+        None => Some(quality).filter(|quality| *quality != ORIGINAL_QUALITY),
+    };
 
-    match fs::read(&file_path).await {
-        Ok(content) => {
-            // Log or process headers if needed
-            if let Some(range) = headers.get("Range") {
-                println!("Range header: {:?}", range);
-                // Implement range handling logic here if needed
-                let range_str = range.to_str().unwrap_or("");
-                let parts: Vec<&str> = range_str.split('=').collect();
-                if parts.len() == 2 && parts[0] == "bytes" {
-                    let ranges: Vec<&str> = parts[1].split('-').collect();
-                    if let Some(start) = ranges.get(0).and_then(|s| s.parse::<u64>().ok()) {
-                        let end = if let Some(end_str) = ranges.get(1) {
-                            end_str.parse::<u64>().ok().unwrap_or_else(|| content.len() as u64 - 1)
-                        } else {
-                            content.len() as u64 - 1
-                        };
-
-                        // Create a Vec<u8> for the body
-                        let body = content[start as usize..=end as usize].to_vec();
-                        let response: Response<Body> = Response::builder()
-                            .status(206) // Partial content status
-                            .header(header::CONTENT_TYPE, "audio/mpeg")
-                            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, content.len()))
-                            .body(Body::from(body))
-                            .unwrap();
-
-                        return Ok(response);
-                    }
+    // Requested renditions that don't exist (yet) fall back to the original upload
+    let rendition = match rendition_quality {
+        Some(quality) => app_state
+            .db_client
+            .get_rendition(track_id, quality)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?,
+        None => None,
+    };
+
+    let (store, key, content_type, served_quality) = match (&rendition, target) {
+        (Some(rendition), _) => (
+            app_state.storage.as_ref(),
+            rendition.file_path.clone(),
+            rendition.mime_type.clone(),
+            rendition.quality.as_str(),
+        ),
+        (None, Some(target)) => {
+            let key = format!("transcodes/{}/{}", track_id, target.file_name());
+            let cached = app_state
+                .transcodes
+                .storage()
+                .head(&key)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+            if cached.is_none() {
+                if req.method() == Method::HEAD {
+                    return transcode_response(target)
+                        .body(Body::empty())
+                        .map_err(|e| HttpError::server_error(e.to_string()));
                 }
+                return stream_transcode(&app_state, &access, track_id, &file_name, target, key).await;
             }
 
-            // If no Range header, return the whole content
-            let response: Response<Body> = Response::builder()
-                .header(header::CONTENT_TYPE, "audio/mpeg")
-                .header(header::CONTENT_DISPOSITION, "inline")
-                .header(header::ACCEPT_RANGES, "bytes") // Allow range requests
-                .body(Body::from(content)) // Take ownership of content
-                .unwrap();
-
-            Ok(response)
+            app_state.transcodes.touch(&key);
+            (app_state.transcodes.storage(), key, target.codec.mime_type().to_string(), TRANSCODED_QUALITY)
         }
-        Err(_) => Err(HttpError::server_error("File Not Found")),
+        (None, None) => (
+            app_state.storage.as_ref(),
+            format!("uploads/{}", file_name),
+            original_mime_type,
+            ORIGINAL_QUALITY,
+        ),
+    };
+
+    let object = store
+        .head(&key)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("File not found"))?;
+
+    let headers = req.headers();
+    let etag = range::etag(&object);
+    let last_modified = range::http_date(&object.last_modified);
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, &content_type)
+        .header("X-Rendition-Quality", served_quality)
+        .header(header::VARY, "Accept")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &last_modified);
+
+    if range::is_not_modified(headers, &etag, &object.last_modified) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .map_err(|e| HttpError::server_error(e.to_string()));
     }
 
-}
\ No newline at end of file
+    let range_request = match headers.get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) if range::is_range_allowed(headers, &etag, &object.last_modified) => {
+            range::parse_range(value, object.size)
+        }
+        _ => RangeRequest::Full,
+    };
+
+    let (builder, byte_range) = match range_request {
+        RangeRequest::Full => (
+            builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_DISPOSITION, "inline"),
+            0..object.size,
+        ),
+        RangeRequest::Partial(byte_range) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", byte_range.start, byte_range.end - 1, object.size),
+                ),
+            byte_range,
+        ),
+        RangeRequest::Unsatisfiable => {
+            return builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", object.size))
+                .body(Body::empty())
+                .map_err(|e| HttpError::server_error(e.to_string()));
+        }
+    };
+
+    let builder = builder.header(header::CONTENT_LENGTH, byte_range.end - byte_range.start);
+
+    // HEAD only wants the headers, empty files have nothing to read
+    let body = if req.method() == Method::HEAD || byte_range.is_empty() {
+        Body::empty()
+    } else {
+        // Only responses that send audio count as a playing device
+        let (session_key, bandwidth_kbps) =
+            stream_limits::acquire_lease(&app_state, access.user_id, access.session_id, track_id).await?;
+        let stream_request = StreamRequest {
+            file_key: &key,
+            file_size: Some(object.size),
+            bitrate_kbps: None,
+            range_start: byte_range.start,
+            range_end: Some(byte_range.end),
+        };
+        let session_id =
+            stream_sessions::open_session(&app_state, access.user_id, track_id, &session_key, &stream_request).await?;
+
+        let offset = byte_range.start;
+        let stream = store
+            .get_stream(&key, byte_range)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        let stream = stream_limits::keep_lease_alive(stream, app_state.clone(), access.user_id, session_key);
+        let stream = stream_limits::throttle(stream, bandwidth_kbps);
+        Body::from_stream(stream_sessions::record_delivery(stream, app_state.clone(), session_id, offset))
+    };
+
+    builder
+        .body(body)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+fn transcode_response(target: TranscodeTarget) -> axum::http::response::Builder {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, target.codec.mime_type())
+        .header("X-Rendition-Quality", TRANSCODED_QUALITY)
+        .header(header::VARY, "Accept")
+        // The length isn't known until the transcode is done
+        .header(header::ACCEPT_RANGES, "none")
+        .header(header::CONTENT_DISPOSITION, "inline")
+}
+
+/// Streams a transcode of the original while it is being encoded. The result
+/// is cached, later requests are served from the cache with range support.
+async fn stream_transcode(
+    app_state: &Arc<AppState>,
+    access: &StreamAccess,
+    track_id: Uuid,
+    file_name: &str,
+    target: TranscodeTarget,
+    key: String,
+) -> Result<Response<Body>, HttpError> {
+    let permit = app_state.transcode_slots.clone().try_acquire_owned().map_err(|_| {
+        HttpError::new(
+            "Too many tracks are being transcoded right now, please try again shortly",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+    })?;
+
+    let (session_key, bandwidth_kbps) =
+        stream_limits::acquire_lease(app_state, access.user_id, access.session_id, track_id).await?;
+    let stream_request = StreamRequest {
+        file_key: &key,
+        file_size: None,
+        bitrate_kbps: target.bitrate_kbps,
+        range_start: 0,
+        range_end: None,
+    };
+    let session_id =
+        stream_sessions::open_session(app_state, access.user_id, track_id, &session_key, &stream_request).await?;
+
+    let source = WorkingFile::fetch(app_state, &format!("uploads/{}", file_name))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let partial = app_state
+        .transcodes
+        .partial_path(&key)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Created here so the response can open it before the encoder starts writing
+    tokio::fs::File::create(&partial).await.map_err(|e| HttpError::server_error(e.to_string()))?;
+    let reader = tokio::fs::File::open(&partial).await.map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let (status_tx, status_rx) = watch::channel(None);
+    let task_state = app_state.clone();
+    tokio::spawn(async move {
+        let source_path = source.path_str();
+        let output_path = partial.display().to_string();
+        let ffmpeg_path = task_state.env.ffmpeg_path.clone();
+
+        // Runs to the end even if the client leaves, the next one gets it from the cache
+        let result = tokio::task::spawn_blocking(move || {
+            transcode::transcode(&source_path, target, &output_path, ffmpeg_path.as_deref())
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result);
+        drop(permit);
+        drop(source);
+
+        let result = match result {
+            Ok(()) => task_state.transcodes.insert(&key, &partial).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            eprintln!("Failed to transcode track {} to {}: {}", track_id, target.file_name(), e);
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+
+        let _ = status_tx.send(Some(result));
+    });
+
+    let stream = cache::follow_partial(reader, status_rx);
+    let stream = stream_limits::keep_lease_alive(stream, app_state.clone(), access.user_id, session_key);
+    let stream = stream_limits::throttle(stream, bandwidth_kbps);
+
+    transcode_response(target)
+        .body(Body::from_stream(stream_sessions::record_delivery(stream, app_state.clone(), session_id, 0)))
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Path,
+    http::{header, Response},
+    response::IntoResponse,
+    routing::get,
+    Extension,
+    Router,
+};
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    auth::StreamAccess,
+    databases::{streams::StreamRequest, track::TrackExt},
+    errors::HttpError,
+    media::hls,
+    storage::WorkingFile,
+    utils::{stream_limits, stream_sessions},
+    AppState,
+};
+
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+/// Size of the pieces a segment is sent in, so throttling can pace it.
+const SEGMENT_CHUNK_SIZE: usize = 16 * 1024;
+
+pub fn hls_handler() -> Router {
+    Router::new()
+        .route("/{track_id}/master.m3u8", get(get_master_playlist_handler))
+        .route("/{track_id}/{quality}/index.m3u8", get(get_media_playlist_handler))
+        .route("/{track_id}/{quality}/{segment}", get(get_segment_handler))
+}
+
+/// File name and duration of a track that can be streamed over HLS.
+async fn playable_track(app_state: &AppState, track_id: Uuid) -> Result<(String, u64), HttpError> {
+    // Segments are encoded with ffmpeg, like the lossy renditions
+    if app_state.env.ffmpeg_path.is_none() {
+        return Err(HttpError::not_found("HLS streaming is not available"));
+    }
+
+    app_state
+        .db_client
+        .get_playable_track(track_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .map(|(file_name, duration_ms)| (file_name, duration_ms.max(0) as u64))
+        .ok_or(HttpError::not_found("Track not found"))
+}
+
+/// Players resolve the URIs in a playlist on their own, so a signed playlist
+/// passes its signature on to them.
+fn signed_playlist(playlist: String, access: &StreamAccess) -> String {
+    let Some(query) = &access.signed_query else {
+        return playlist;
+    };
+
+    playlist
+        .lines()
+        .map(|line| {
+            if line.is_empty() || line.starts_with('#') {
+                line.to_string()
+            } else {
+                format!("{}?{}", line, query)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+fn playlist_response(playlist: String) -> Result<Response<Body>, HttpError> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, PLAYLIST_CONTENT_TYPE)
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        .body(Body::from(playlist))
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub async fn get_master_playlist_handler(
+    Path(track_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(access): Extension<StreamAccess>,
+) -> Result<impl IntoResponse, HttpError> {
+    playable_track(&app_state, track_id).await?;
+
+    playlist_response(signed_playlist(hls::master_playlist(), &access))
+}
+
+pub async fn get_media_playlist_handler(
+    Path((track_id, quality)): Path<(Uuid, String)>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(access): Extension<StreamAccess>,
+) -> Result<impl IntoResponse, HttpError> {
+    if hls::find_variant(&quality).is_none() {
+        return Err(HttpError::not_found(format!("Unknown quality '{}'", quality)));
+    }
+
+    let (_, duration_ms) = playable_track(&app_state, track_id).await?;
+
+    playlist_response(signed_playlist(hls::media_playlist(duration_ms), &access))
+}
+
+/// Serves a segment from the cache, encoding it first if nobody asked for it yet.
+pub async fn get_segment_handler(
+    Path((track_id, quality, segment)): Path<(Uuid, String, String)>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(access): Extension<StreamAccess>,
+) -> Result<impl IntoResponse, HttpError> {
+    let spec = hls::find_variant(&quality)
+        .ok_or_else(|| HttpError::not_found(format!("Unknown quality '{}'", quality)))?;
+    let segment_number = segment
+        .strip_suffix(".mp3")
+        .and_then(|number| number.parse::<u64>().ok())
+        .ok_or(HttpError::not_found("Segment not found"))?;
+
+    let (file_name, duration_ms) = playable_track(&app_state, track_id).await?;
+    if segment_number >= hls::segment_count(duration_ms) {
+        return Err(HttpError::not_found("Segment not found"));
+    }
+
+    let (session_key, bandwidth_kbps) =
+        stream_limits::acquire_lease(&app_state, access.user_id, access.session_id, track_id).await?;
+
+    let key = format!("uploads/hls/{}/{}/{}", track_id, quality, segment);
+
+    let data = match app_state.storage.get_range(&key, None).await {
+        Ok(data) => data,
+        Err(_) => {
+            let source = WorkingFile::fetch(&app_state, &format!("uploads/{}", file_name))
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+            let work_dir = std::path::Path::new(&app_state.env.storage_work_dir);
+            std::fs::create_dir_all(work_dir).map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            let source_path = source.path_str();
+            let output_path = work_dir
+                .join(format!("hls-{}-{}-{}.mp3", track_id, quality, Uuid::new_v4()))
+                .display()
+                .to_string();
+            let ffmpeg_path = app_state.env.ffmpeg_path.clone();
+            let data = tokio::task::spawn_blocking(move || {
+                hls::encode_segment(&source_path, spec, segment_number, &output_path, ffmpeg_path.as_deref())
+            })
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .map_err(HttpError::server_error)?;
+
+            // A failed write only costs encoding the segment again next time
+            if let Err(e) = app_state.storage.put(&key, data.clone()).await {
+                eprintln!("Failed to cache HLS segment {}: {}", key, e);
+            }
+            data
+        }
+    };
+
+    // A variant's segments are counted as ranges of one file at its nominal bitrate
+    let content_length = data.len();
+    let bitrate_kbps = spec.bitrate_kbps.unwrap_or(192);
+    let offset = segment_number * hls::SEGMENT_MS * bitrate_kbps as u64 / 8;
+    let variant_key = format!("uploads/hls/{}/{}", track_id, quality);
+    let stream_request = StreamRequest {
+        file_key: &variant_key,
+        file_size: None,
+        bitrate_kbps: Some(bitrate_kbps),
+        range_start: offset,
+        range_end: Some(offset + content_length as u64),
+    };
+    let session_id =
+        stream_sessions::open_session(&app_state, access.user_id, track_id, &session_key, &stream_request).await?;
+
+    let data = Bytes::from(data);
+    let chunks = (0..content_length)
+        .step_by(SEGMENT_CHUNK_SIZE)
+        .map(|start| Ok(data.slice(start..(start + SEGMENT_CHUNK_SIZE).min(content_length))))
+        .collect::<Vec<_>>();
+    // Variants above the plan's bandwidth are paced like any other stream
+    let stream = stream_limits::throttle(futures::stream::iter(chunks).boxed(), bandwidth_kbps);
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "audio/mpeg")
+        .header(header::CONTENT_LENGTH, content_length)
+        .header(header::CACHE_CONTROL, "private, max-age=86400")
+        .body(Body::from_stream(stream_sessions::record_delivery(stream, app_state.clone(), session_id, offset)))
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
@@ -4,4 +4,9 @@ pub mod favorites;
 pub mod getfile;
 pub mod playlists;
 pub mod upload;
-pub mod history;
\ No newline at end of file
+pub mod history;
+pub mod assets;
+pub mod admin;
+pub mod hls;
+pub mod tracks;
+pub mod search;
@@ -1,8 +1,4 @@
-use std::{
-    fs::{self, File},
-    io::Write,
-    sync::Arc,
-};
+use std::sync::Arc;
 
 use axum::{
     extract::{Multipart, Path}, response::IntoResponse, routing::{get, post}, Extension, Json, Router
@@ -13,6 +9,7 @@ use crate::{
     databases::playlists::PlayListsExt,
     dtos::{AddTrackPlayList, FilterTrackDto, PlayListResponse, Response, TrackResponseDto},
     errors::HttpError,
+    handler::upload::save_artwork,
     AppState,
 };
 
@@ -61,26 +58,11 @@ pub async fn create_playlist(
         return Err(HttpError::bad_request("Thumbnail is missing"));
     }
 
-    let thumbnail_dir = format!("assets/playlist/");
-    if let Err(_err) = fs::create_dir_all(&thumbnail_dir) {
-        return Err(HttpError::server_error("Createing failed"));
-    }
-
-    let thumbnail_file_path = format!("{}/{}", &thumbnail_dir, &thumbnail_name);
-    let mut file = match File::create(&thumbnail_file_path) {
-        Ok(f) => f,
-        Err(err) => {
-            return Err(HttpError::server_error(err.to_string()));
-        }
-    };
-
-    if let Err(_err) = file.write_all(&thumbnail_data) {
-        return Err(HttpError::server_error("Createing failed"));
-    }
+    let artwork_id = save_artwork(&app_state, thumbnail_data).await?;
 
     app_state
         .db_client
-        .create_playlist(user_id.clone(), title, thumbnail_name)
+        .create_playlist(user_id, title, artwork_id)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use axum::{extract::Query, response::IntoResponse, routing::get, Extension, Json, Router};
+use validator::Validate;
+
+use crate::{
+    auth::JWTAuthMiddleware,
+    databases::search::SearchExt,
+    dtos::{FilterTrackDto, SearchQueryDto, SearchResponseDto, SearchResultsDto},
+    errors::HttpError,
+    AppState,
+};
+
+const DEFAULT_LIMIT: usize = 20;
+
+pub fn search_handler() -> Router {
+    Router::new()
+        .route("/", get(search))
+}
+
+/// Tracks, playlists and users matching `q`, best matches first. Each group
+/// is paged on its own, `type` narrows the response down to one of them.
+pub async fn search(
+    Query(query): Query<SearchQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    query.validate().map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let text = query.q.trim();
+    if text.is_empty() {
+        return Err(HttpError::bad_request("Search query is required"));
+    }
+
+    let (tracks, playlists, users) = match query.kind.as_deref() {
+        None => (true, true, true),
+        Some("tracks") => (true, false, false),
+        Some("playlists") => (false, true, false),
+        Some("users") => (false, false, true),
+        Some(other) => {
+            return Err(HttpError::bad_request(format!(
+                "Unknown type '{}', expected tracks, playlists or users",
+                other
+            )));
+        }
+    };
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    // One more row than asked for tells whether there is a next page
+    let (fetch, offset) = (limit as i64 + 1, ((page - 1) * limit) as i64);
+    let user_id = user.user.id;
+
+    let tracks = if tracks {
+        let tracks = app_state
+            .db_client
+            .search_tracks(user_id, text, fetch, offset)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        Some(paginate(FilterTrackDto::filter_tracks(&tracks), limit))
+    } else {
+        None
+    };
+
+    let playlists = if playlists {
+        let playlists = app_state
+            .db_client
+            .search_playlists(user_id, text, fetch, offset)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        Some(paginate(playlists, limit))
+    } else {
+        None
+    };
+
+    let users = if users {
+        let users = app_state
+            .db_client
+            .search_users(text, fetch, offset)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        Some(paginate(users, limit))
+    } else {
+        None
+    };
+
+    Ok(Json(SearchResponseDto {
+        query: text.to_string(),
+        page,
+        limit,
+        tracks,
+        playlists,
+        users,
+    }))
+}
+
+fn paginate<T>(mut items: Vec<T>, limit: usize) -> SearchResultsDto<T> {
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+
+    SearchResultsDto { items, has_more }
+}
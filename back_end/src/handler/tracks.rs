@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header, HeaderMap, Method, Response, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Extension,
+    Router,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    auth::JWTAuthMiddleware,
+    databases::track::TrackExt,
+    errors::HttpError,
+    media::{
+        artwork::ARTWORK_DIR,
+        retag::{self, Cover},
+        tags::EmbeddedTags,
+        Container,
+    },
+    models::TrackDownload,
+    storage::WorkingFile,
+    utils::range::{self, RangeRequest},
+    AppState,
+};
+
+/// Longest file name stem offered for downloads, in characters.
+const MAX_FILE_NAME_LENGTH: usize = 150;
+
+pub fn tracks_handler() -> Router {
+    Router::new()
+        .route("/{track_id}/download", get(download_track))
+}
+
+/// Sends a track as an attachment, with its current tags and artwork
+/// written into the file.
+async fn download_track(
+    Path(track_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = &user.user;
+
+    // Tracks the user may not see look the same as tracks that don't exist
+    let track = app_state
+        .db_client
+        .get_track_download(track_id, user.id, user.role == "admin")
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Track not found"))?;
+
+    if !track.can_download {
+        return Err(HttpError::forbidden("The license of this track doesn't allow downloads"));
+    }
+
+    // Tracks ingested before formats were recorded, and files that can't be
+    // rewritten, are sent as uploaded
+    let container = track.container.as_deref().and_then(Container::from_name);
+    let retagged = match container {
+        Some(container) => retagged_copy(&app_state, track_id, &track, container).await?,
+        None => None,
+    };
+
+    let (store, key, content_type, extension) = match (retagged, container) {
+        (Some(key), Some(container)) => {
+            app_state.transcodes.touch(&key);
+            (app_state.transcodes.storage(), key, container.mime_type().to_string(), container.extension().to_string())
+        }
+        _ => (
+            app_state.storage.as_ref(),
+            format!("uploads/{}", track.file_name),
+            mime_guess::from_path(&track.file_name)
+                .first()
+                .map_or_else(|| "application/octet-stream".to_string(), |mime| mime.to_string()),
+            std::path::Path::new(&track.file_name)
+                .extension()
+                .map_or_else(String::new, |extension| extension.to_string_lossy().to_lowercase()),
+        ),
+    };
+
+    let object = store
+        .head(&key)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("File not found"))?;
+
+    let etag = range::etag(&object);
+    let last_modified = range::http_date(&object.last_modified);
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, &content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &last_modified)
+        .header(header::CONTENT_DISPOSITION, content_disposition(&download_file_name(&track, &extension)));
+
+    if range::is_not_modified(&headers, &etag, &object.last_modified) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .map_err(|e| HttpError::server_error(e.to_string()));
+    }
+
+    // Interrupted downloads pick up where they stopped
+    let range_request = match headers.get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) if range::is_range_allowed(&headers, &etag, &object.last_modified) => {
+            range::parse_range(value, object.size)
+        }
+        _ => RangeRequest::Full,
+    };
+
+    let (builder, byte_range) = match range_request {
+        RangeRequest::Full => (builder.status(StatusCode::OK), 0..object.size),
+        RangeRequest::Partial(byte_range) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", byte_range.start, byte_range.end - 1, object.size),
+                ),
+            byte_range,
+        ),
+        RangeRequest::Unsatisfiable => {
+            return builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", object.size))
+                .body(Body::empty())
+                .map_err(|e| HttpError::server_error(e.to_string()));
+        }
+    };
+
+    let builder = builder.header(header::CONTENT_LENGTH, byte_range.end - byte_range.start);
+
+    let body = if method == Method::HEAD || byte_range.is_empty() {
+        Body::empty()
+    } else {
+        let stream = store
+            .get_stream(&key, byte_range)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        Body::from_stream(stream)
+    };
+
+    builder
+        .body(body)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Cache key of the track's file with its current tags, written on first
+/// request. `None` when the file can't be rewritten.
+async fn retagged_copy(
+    app_state: &AppState,
+    track_id: Uuid,
+    track: &TrackDownload,
+    container: Container,
+) -> Result<Option<String>, HttpError> {
+    let tags = EmbeddedTags {
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        album: track.album.clone(),
+        album_artist: track.album_artist.clone(),
+        track_number: track.track_number,
+        disc_number: track.disc_number,
+        release_year: track.release_year,
+        genre: track.genre.clone(),
+        isrc: track.isrc.clone(),
+    };
+
+    // Editing the tags or the artwork changes the key, stale copies age out of the cache
+    let digest = Sha256::digest(format!("{:?}\n{:?}", tags, track.artwork_file_name));
+    let key = format!(
+        "transcodes/{}/download-{}.{}",
+        track_id,
+        &hex::encode(digest)[..16],
+        container.extension()
+    );
+
+    let cached = app_state
+        .transcodes
+        .storage()
+        .head(&key)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    if cached.is_some() {
+        return Ok(Some(key));
+    }
+
+    let cover = match (&track.artwork_file_name, track.artwork_size) {
+        (Some(file_name), Some(size)) => {
+            let data = app_state
+                .storage
+                .get_range(&format!("{}/{}", ARTWORK_DIR, file_name), None)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+            Some(Cover { data, size: size as u32 })
+        }
+        _ => None,
+    };
+
+    let source = WorkingFile::fetch(app_state, &format!("uploads/{}", track.file_name))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let partial = app_state
+        .transcodes
+        .partial_path(&key)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let source_path = source.path_str();
+    let output_path = partial.display().to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        retag::retag(&source_path, container, &tags, cover.as_ref(), &output_path)
+    })
+    .await
+    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if let Err(e) = result {
+        eprintln!("Sending track {} without tags, rewriting them failed: {}", track_id, e);
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Ok(None);
+    }
+
+    app_state
+        .transcodes
+        .insert(&key, &partial)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(Some(key))
+}
+
+/// `Artist - Title.ext`, without the characters file systems refuse.
+fn download_file_name(track: &TrackDownload, extension: &str) -> String {
+    let clean = |value: &str| {
+        value
+            .chars()
+            .map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { ' ' } else { c })
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let title = track.title.as_deref().map(clean).unwrap_or_default();
+    let stem = match track.artist.as_deref().map(clean).filter(|artist| !artist.is_empty()) {
+        Some(artist) if !title.is_empty() => format!("{} - {}", artist, title),
+        Some(artist) => artist,
+        None => title,
+    };
+
+    // Windows drops trailing dots and spaces
+    let stem: String = stem.chars().take(MAX_FILE_NAME_LENGTH).collect();
+    let stem = stem.trim_end_matches(['.', ' ']);
+    let stem = if stem.is_empty() { "Untitled" } else { stem };
+
+    if extension.is_empty() {
+        stem.to_string()
+    } else {
+        format!("{}.{}", stem, extension)
+    }
+}
+
+/// Plain ASCII for old clients, the exact name for everyone else (RFC 6266).
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii() && c != '%' { c } else { '_' })
+        .collect();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(file_name, NON_ALPHANUMERIC)
+    )
+}
@@ -1,107 +1,26 @@
-use std::{
-    fs::{self, File, OpenOptions}, 
-    io::Write,
-    sync::Arc
-};
+use std::sync::Arc;
 
-use axum::{extract::Multipart, response::IntoResponse, routing::post, Extension, Json, Router};
-use chrono::Duration;
-use symphonia::core::{formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+use axum::{extract::{Multipart, Path}, http::StatusCode, response::IntoResponse, routing::{delete, post}, Extension, Json, Router};
 
-use crate::{auth::JWTAuthMiddleware, databases::upload::UploadExt, dtos::{Response, UploadResponse}, errors::HttpError, AppState};
+use crate::{auth::JWTAuthMiddleware, databases::{artwork::ArtworkExt, jobs::JobExt, upload::{TrackDetails, UploadExt}}, dtos::{Response, UploadResponse}, errors::HttpError, jobs::JobPayload, media::{self, archive, artwork::{self, ArtworkError}, validate::ValidationError}, storage::{self, Storage}, AppState};
 
 fn sanitize_filename(filename: &str) -> String {
     filename.replace(&['/', '\\'][..], "").replace("..", "")
 }
 
-fn is_upload_complete(temp_dir: &str, total_chunks: usize) -> bool {
-    match fs::read_dir(temp_dir) {
-        Ok(entries) => entries.count() == total_chunks,
+async fn is_upload_complete(storage: &dyn Storage, temp_prefix: &str, total_chunks: usize) -> bool {
+    match storage.list(temp_prefix).await {
+        // Chunks still being written don't count yet
+        Ok(objects) => objects.iter().filter(|object| !object.key.ends_with(".tmp")).count() == total_chunks,
         Err(_) => false,
     }
 }
 
-fn get_audio_duration(file_path: &str) -> Result<Duration, Box<dyn std::error::Error>> {
-      // Open the audio file
-      let file = File::open(file_path)?;
-      let mss = MediaSourceStream::new(Box::new(file), Default::default());
-  
-      // Create a hint to help the format registry guess the format
-      let mut hint = Hint::new();
-      
-      // Provide the file extension as a format hint
-      if let Some(extension) = file_path.split('.').last() {
-          hint.with_extension(extension);
-      }
-  
-      // Probe the media file
-      let probed = symphonia::default::get_probe().format(
-          &hint,
-          mss,
-          &FormatOptions::default(),
-          &MetadataOptions::default(),
-      )?;
-      
-      // Get the default track
-      let track = probed.format.default_track().ok_or("No track found")?;
-      
-      // Get the time base for the track
-      let time_base = track.codec_params.time_base.ok_or("No time base")?;
-      
-      // Get the frame count, handling the `Result<u64, &str>`
-      let n_frames = track.codec_params.n_frames.ok_or("No frame count")?;
-      
-      // Calculate the duration safely after handling the result
-      let duration_seconds = (n_frames as f64 * time_base.numer as f64) / time_base.denom as f64;
-  
-      // Return the duration as a `Duration` object
-      Ok(Duration::seconds(duration_seconds as i64))
-}
-
-async fn assemble_file(
-    temp_dir: &str, 
-    file_name: &str, 
-    total_chunks: usize, 
-    track_id: uuid::Uuid,
-    app_state: Arc<AppState>,
-) -> std::io::Result<()> {
-    let output_path = format!("uploads/{}", file_name);
-    let mut output_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&output_path)?;
-
-    for chunk_number in 0..total_chunks {
-        let chunk_path = format!("{}/chunk_{}", temp_dir, chunk_number);
-        let chunk_data = fs::read(&chunk_path)?;
-        output_file.write_all(&chunk_data)?;
-    }
-
-    // Clean up the temporary chunks
-    fs::remove_dir_all(temp_dir)?;
-
-    let duration = match get_audio_duration(&output_path) {
-        Ok(duration) => duration,
-        Err(e) => {
-            // Convert the error to std::io::Error and return
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-        }
-    };
-
-    let duration_seconds = duration.num_seconds(); // Assuming `duration` is of type `Duration`
-
-    app_state.db_client
-        .update_status(track_id.clone(), duration_seconds)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-
-    Ok(())
-}
-
 pub fn upload_handler() -> Router {
     Router::new()
         .route("/", post(upload_chunks))
         .route("/thumbnail", post(upload_thumbnail))
+        .route("/{track_id}", delete(cancel_upload))
 }
 
 pub async fn upload_chunks(
@@ -163,6 +82,20 @@ pub async fn upload_chunks(
         return Err(HttpError::bad_request("File name and chunk data are missing"));
     }
 
+    // Held until this chunk is stored, so a concurrent cancel can't leave it half written
+    let upload_lock = match (chunk_number, track_id) {
+        (0, _) | (_, None) => None,
+        (_, Some(track_id)) => {
+            let (upload_lock, stored_name) = app_state.db_client
+                .lock_pending_upload(track_id, user_id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                .ok_or(HttpError::not_found("Upload not found"))?;
+            file_name = stored_name;
+            Some(upload_lock)
+        }
+    };
+
     if let Some(track_id) = track_id {
         if let Some(existing_file) = app_state.db_client.get_audio_file(track_id).await.map_err(|e| HttpError::server_error(e.to_string()))? {
             uploaded_chunks = existing_file.upload_chunks; // Get the uploaded_chunks value
@@ -173,6 +106,15 @@ pub async fn upload_chunks(
 
 
     if chunk_number == 0 {
+        // Turn away anything but audio files and album archives before any bytes are stored
+        let extension = match media::sniff_container(&chunk_data) {
+            Some(container) => container.extension(),
+            None if archive::is_zip(&chunk_data) => "zip",
+            None => return Err(HttpError::bad_request(ValidationError::UnrecognizedContainer.to_string())),
+        };
+        // Client file names repeat across uploads, each track gets a key of its own
+        file_name = format!("{}.{}", uuid::Uuid::new_v4(), extension);
+
         track_id = Some(
             app_state.db_client.upload_file(user_id.clone(), &file_name)
             .await
@@ -181,18 +123,8 @@ pub async fn upload_chunks(
     }
     let track_id = track_id.ok_or(HttpError::bad_request("track id missing"))?;
 
-    let temp_dir = format!("uploads/temp/{}", &file_name);
-    if let Err(_err) = fs::create_dir_all(&temp_dir) {
-        return Err(HttpError::server_error("Failed to create temp directory".to_string()));
-    }
-
-    let chuck_path = format!("{}/chunk_{}",temp_dir,chunk_number);
-    let mut file = match File::create(&chuck_path) {
-        Ok(f) => f,
-        Err(_err) => {
-            return Err(HttpError::server_error("failed to create chuck file"));
-        }
-    };
+    let temp_prefix = format!("uploads/temp/{}", track_id);
+    let chuck_path = format!("{}/chunk_{}",temp_prefix,chunk_number);
 
     app_state.db_client.upload_chuck(
         track_id.clone(), 
@@ -204,32 +136,87 @@ pub async fn upload_chunks(
     .await
     .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    if let Err(_err) = file.write_all(&chunk_data) {
+    if let Err(err) = app_state.storage.put(&chuck_path, chunk_data).await {
+        eprintln!("Failed to store {}: {}", chuck_path, err);
         return Err(HttpError::server_error("failed to create chuck file"));
     }
 
-    if is_upload_complete(&temp_dir, total_chunks as usize) {
-        if let Err(_err) = assemble_file(&temp_dir, &file_name, total_chunks as usize, track_id.clone(), app_state).await {
-            return Err(HttpError::server_error("Failed to complite file".to_string()));
-        }
+    if let Some(upload_lock) = upload_lock {
+        upload_lock.commit().await.map_err(|e| HttpError::server_error(e.to_string()))?;
+    }
+
+    if is_upload_complete(app_state.storage.as_ref(), &temp_prefix, total_chunks as usize).await {
+        // Assembling and probing happen in a background job, clients poll the track status
+        let payload = JobPayload::ProcessUpload {
+            track_id,
+            file_name: file_name.clone(),
+            total_chunks: total_chunks as usize,
+        };
+        let payload_json = serde_json::to_value(&payload)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        app_state.db_client
+            .complete_upload(track_id, payload.kind(), payload_json, app_state.env.job_max_attempts)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
     }
 
     Ok(Json(UploadResponse{ track_id }))
 }
 
 
+/// Discards an upload that hasn't received all of its chunks yet.
+pub async fn cancel_upload(
+    Path(track_id): Path<uuid::Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user_id = user.user.id;
+
+    // Waits for chunk requests of this upload that are still being stored
+    let (mut tx, processing_status) = app_state.db_client
+        .lock_upload_for_delete(track_id, user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::not_found("Upload not found"))?;
+
+    if processing_status.is_some() {
+        return Err(HttpError::new("Upload is already complete", StatusCode::CONFLICT));
+    }
+
+    app_state.db_client
+        .delete_upload(&mut tx, track_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // The rows only go away if the chunks do, dropping `tx` rolls back
+    let temp_prefix = format!("uploads/temp/{}", track_id);
+    storage::delete_prefix(app_state.storage.as_ref(), &temp_prefix)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    tx.commit().await.map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = Response {
+        status: "success",
+        message: "Upload cancelled".to_string(),
+    };
+
+    Ok(Json(response))
+}
+
 pub async fn upload_thumbnail(
     Extension(app_state): Extension<Arc<AppState>>,
-    Extension(_user): Extension<JWTAuthMiddleware>,
+    Extension(user): Extension<JWTAuthMiddleware>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, HttpError> {
 
-    // let user = &user.user;
-    // let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
+    let user = &user.user;
 
     let mut track_id: Option<uuid::Uuid> = None;
     let mut title = String::new();
     let mut artist = String::new();
+    let mut license = String::new();
     let mut thumbnail_name = String::new();
     let mut thumbnail_data = Vec::new();
 
@@ -247,6 +234,9 @@ pub async fn upload_thumbnail(
             "artist" => {
                 artist = field.text().await.unwrap();
             }
+            "license" => {
+                license = field.text().await.unwrap();
+            }
             "thumbnail" => {
                 thumbnail_name = field.file_name().unwrap_or_default().to_string();
                 match field.bytes().await {
@@ -265,31 +255,36 @@ pub async fn upload_thumbnail(
 
     let track_id = track_id.ok_or(HttpError::bad_request("track id missing"))?;
 
-    if thumbnail_name.is_empty() || thumbnail_data.is_empty() {
-        return Err(HttpError::bad_request("Thumbnail is missing"));
-    }    
-
-    let thumbnail_dir = format!("assets/images/");
-    if let Err(_err) = fs::create_dir_all(&thumbnail_dir) {
-        return Err(HttpError::server_error("Createing failed"));
-    }
-
-    let thumbnail_file_path = format!("{}/{}", &thumbnail_dir, &thumbnail_name);
-    let mut file = match File::create(&thumbnail_file_path) {
-        Ok(f) => f,
-        Err(err) => {
-            return Err(HttpError::server_error(err.to_string()));
+    // The thumbnail is optional when the upload carried embedded cover art
+    let artwork_id = if thumbnail_data.is_empty() {
+        if !thumbnail_name.is_empty() {
+            return Err(HttpError::bad_request("Thumbnail is missing"));
         }
+        None
+    } else {
+        Some(save_artwork(&app_state, thumbnail_data).await?)
     };
 
-    if let Err(_err) = file.write_all(&thumbnail_data) {
-        return Err(HttpError::server_error("Createing failed"));
-    }
-
-    app_state.db_client
-        .upload_thumbnail(track_id.clone(), &thumbnail_name, &title, &artist)
+    let updated = app_state.db_client
+        .upload_thumbnail(
+            track_id,
+            artwork_id,
+            &TrackDetails { title: &title, artist: &artist, license: &license },
+            user.id,
+            user.role == "admin",
+        )
         .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;    
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                HttpError::bad_request(format!("Unknown license '{}'", license))
+            }
+            e => HttpError::server_error(e.to_string()),
+        })?;
+
+    // Tracks of other users are as good as missing
+    if !updated {
+        return Err(HttpError::not_found("Track not found"));
+    }
 
     let response = Response {
         status: "success",
@@ -297,4 +292,24 @@ pub async fn upload_thumbnail(
     };
 
     Ok(Json(response))
-}
\ No newline at end of file
+}
+
+/// Validates an uploaded image and stores it as a set of artwork variants.
+pub async fn save_artwork(app_state: &AppState, data: Vec<u8>) -> Result<uuid::Uuid, HttpError> {
+    let artwork = tokio::task::spawn_blocking(move || artwork::process_artwork(&data))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .map_err(|e| match e {
+            ArtworkError::Invalid(_) => HttpError::bad_request(e.to_string()),
+            ArtworkError::Io(_) => HttpError::server_error(e.to_string()),
+        })?;
+
+    artwork::store_artwork(app_state.storage.as_ref(), &artwork)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.db_client
+        .save_artwork(&artwork)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{
+    databases::{archives::ArchiveExt, artwork::ArtworkExt, imports::ImportExt, jobs::JobExt, upload::{TrackDetails, UploadExt}},
+    dtos::ArchiveEntryDto,
+    jobs::{upload::import_track_file, JobError},
+    media::{
+        archive::{self, ArchiveEntry, ArchiveError, ArchiveLimits, EntryKind},
+        artwork::{self, ArtworkError},
+        Container,
+    },
+    storage::WorkingFile,
+    AppState,
+};
+
+fn archive_error(err: ArchiveError) -> JobError {
+    match err {
+        ArchiveError::Invalid(_) => JobError::Permanent(err.to_string()),
+        ArchiveError::Io(_) => JobError::Transient(err.to_string()),
+    }
+}
+
+/// Unpacks an album uploaded as a ZIP archive into one track per audio entry,
+/// all sharing the archive's `cover.*` as artwork.
+///
+/// Entries imported by an earlier attempt are kept, a retried job picks up
+/// where the previous one stopped. The archive row ends up `unpacked` and the
+/// archive stored under `archive_key` is removed.
+pub async fn process_archive(
+    app_state: &AppState,
+    archive_id: Uuid,
+    archive_key: &str,
+    archive_file: &WorkingFile,
+) -> Result<(), JobError> {
+    let user_id = app_state.db_client
+        .get_archive_owner(archive_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| JobError::Permanent("Archive not found".to_string()))?;
+
+    let archive_path = archive_file.path_str();
+    let limits = ArchiveLimits::from_config(&app_state.env);
+    let path = archive_path.clone();
+    let entries = tokio::task::spawn_blocking(move || archive::list_entries(&path, &limits))
+        .await
+        .map_err(|e| e.to_string())?
+        .and_then(|entries| match entries.iter().any(|entry| matches!(entry.kind, EntryKind::Audio(_))) {
+            true => Ok(entries),
+            false => Err(ArchiveError::Invalid("it contains no audio files".to_string())),
+        });
+
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(err) => {
+            // Like rejected audio, rejected archives don't stay in storage
+            if let ArchiveError::Invalid(_) = err {
+                let _ = app_state.storage.delete(archive_key).await;
+            }
+            return Err(archive_error(err));
+        }
+    };
+
+    let previous: HashMap<String, ArchiveEntryDto> = app_state.db_client
+        .get_archive_entries(archive_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|entry| (entry.entry_name.clone(), entry))
+        .collect();
+
+    let mut artwork_id = None;
+    for entry in entries.iter().filter(|entry| entry.kind == EntryKind::Cover) {
+        if artwork_id.is_some() {
+            save_entry(app_state, archive_id, &entry.name, None, "skipped", Some("another cover was used")).await?;
+            continue;
+        }
+        artwork_id = save_cover(app_state, archive_id, &archive_path, entry).await?;
+    }
+
+    let album = Album { archive_id, user_id, archive_path: &archive_path, artwork_id };
+    for entry in &entries {
+        match entry.kind {
+            EntryKind::Audio(container) => {
+                import_entry(app_state, &album, entry, container, previous.get(&entry.name)).await?
+            }
+            EntryKind::Skipped(reason) => {
+                save_entry(app_state, archive_id, &entry.name, None, "skipped", Some(reason)).await?
+            }
+            EntryKind::Cover => {}
+        }
+    }
+
+    let _ = app_state.storage.delete(archive_key).await;
+
+    app_state.db_client
+        .set_processing_status(archive_id, "unpacked")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+async fn save_entry(
+    app_state: &AppState,
+    archive_id: Uuid,
+    entry_name: &str,
+    track_id: Option<Uuid>,
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), JobError> {
+    app_state.db_client
+        .save_archive_entry(archive_id, entry_name, track_id, status, error)
+        .await
+        .map_err(|e| JobError::Transient(e.to_string()))
+}
+
+/// Turns the cover entry into artwork. A broken cover is recorded and the
+/// album is imported without it.
+async fn save_cover(
+    app_state: &AppState,
+    archive_id: Uuid,
+    archive_path: &str,
+    entry: &ArchiveEntry,
+) -> Result<Option<Uuid>, JobError> {
+    let path = archive_path.to_string();
+    let index = entry.index;
+    let processed = tokio::task::spawn_blocking(move || {
+        let data = archive::read_cover(&path, index).map_err(|e| match e {
+            ArchiveError::Invalid(reason) => ArtworkError::Invalid(reason),
+            ArchiveError::Io(err) => ArtworkError::Io(err),
+        })?;
+        artwork::process_artwork(&data)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let processed = match processed {
+        Ok(processed) => processed,
+        Err(ArtworkError::Invalid(reason)) => {
+            save_entry(app_state, archive_id, &entry.name, None, "failed", Some(&reason)).await?;
+            return Ok(None);
+        }
+        Err(err) => return Err(JobError::Transient(err.to_string())),
+    };
+
+    artwork::store_artwork(app_state.storage.as_ref(), &processed).await?;
+
+    let artwork_id = app_state.db_client
+        .save_artwork(&processed)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    save_entry(app_state, archive_id, &entry.name, None, "cover", None).await?;
+
+    Ok(Some(artwork_id))
+}
+
+/// What every entry of one archive shares.
+struct Album<'a> {
+    archive_id: Uuid,
+    user_id: Uuid,
+    archive_path: &'a str,
+    artwork_id: Option<Uuid>,
+}
+
+async fn import_entry(
+    app_state: &AppState,
+    album: &Album<'_>,
+    entry: &ArchiveEntry,
+    container: Container,
+    previous: Option<&ArchiveEntryDto>,
+) -> Result<(), JobError> {
+    let Album { archive_id, user_id, archive_path, artwork_id } = *album;
+
+    if let Some(previous) = previous {
+        if previous.status == "imported" {
+            return Ok(());
+        }
+
+        if let Some(track_id) = previous.track_id {
+            // A ready track means the previous attempt stopped right before recording it
+            let ready = app_state.db_client
+                .get_track_status(track_id, user_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .is_some_and(|track| track.processing_status.as_deref() == Some("ready"));
+            if ready {
+                return save_entry(app_state, archive_id, &entry.name, Some(track_id), "imported", None).await;
+            }
+
+            app_state.db_client
+                .discard_import_track(track_id)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    let file_name = format!("{}.{}", Uuid::new_v4(), container.extension());
+    let track_id = app_state.db_client
+        .upload_file(user_id, &file_name)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    app_state.db_client
+        .set_processing_status(track_id, "processing")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    save_entry(app_state, archive_id, &entry.name, Some(track_id), "processing", None).await?;
+
+    // Counts as artwork the user picked, embedded covers don't replace it
+    if artwork_id.is_some() {
+        app_state.db_client
+            .upload_thumbnail(
+                track_id,
+                artwork_id,
+                &TrackDetails { title: "", artist: "", license: "" },
+                user_id,
+                false,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let key = format!("uploads/{}", file_name);
+    let entry_file = WorkingFile::for_key(app_state, &key)?;
+    let path = archive_path.to_string();
+    let index = entry.index;
+    let extract_path = entry_file.path_str();
+    let result = match tokio::task::spawn_blocking(move || archive::extract_entry(&path, index, &extract_path))
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        Ok(()) => match entry_file.persist(app_state, &key).await {
+            Ok(()) => import_track_file(app_state, track_id, &file_name, &entry_file, entry.stem()).await,
+            Err(err) => Err(err.into()),
+        },
+        Err(err) => Err(archive_error(err)),
+    };
+
+    match result {
+        Ok(()) => save_entry(app_state, archive_id, &entry.name, Some(track_id), "imported", None).await,
+        // One bad file doesn't hold up the rest of the album
+        Err(JobError::Permanent(error)) => {
+            let _ = app_state.storage.delete(&key).await;
+            app_state.db_client
+                .discard_import_track(track_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            save_entry(app_state, archive_id, &entry.name, None, "failed", Some(&error)).await
+        }
+        // Left as `processing`, the retried job discards the track and starts over
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::{
+    databases::fingerprints::FingerprintExt,
+    jobs::JobError,
+    media::fingerprint::{self, Fingerprint},
+    storage::WorkingFile,
+    AppState,
+};
+
+/// Tracks that sound like `fingerprint` with their similarity, best match first.
+pub async fn find_duplicates(
+    app_state: &AppState,
+    track_id: Uuid,
+    fingerprint: &Fingerprint,
+) -> Result<Vec<(Uuid, f64)>, JobError> {
+    let candidates = app_state.db_client
+        .get_fingerprint_candidates(track_id, &fingerprint.terms)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let items = fingerprint.items.clone();
+    let min_similarity = app_state.env.duplicate_min_similarity;
+
+    let mut duplicates = tokio::task::spawn_blocking(move || {
+        candidates
+            .into_iter()
+            .map(|(candidate_id, candidate)| (candidate_id, fingerprint::similarity(&items, &candidate)))
+            .filter(|(_, similarity)| *similarity >= min_similarity)
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    duplicates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    Ok(duplicates)
+}
+
+/// Fingerprints a track uploaded before fingerprints existed and records its
+/// duplicates. The duplicate policy only applies to new uploads.
+pub async fn fingerprint_track(
+    track_id: Uuid,
+    file_name: &str,
+    app_state: Arc<AppState>,
+) -> Result<(), JobError> {
+    let source = WorkingFile::fetch(&app_state, &format!("uploads/{}", file_name)).await?;
+    let file_path = source.path_str();
+
+    let fingerprint = tokio::task::spawn_blocking(move || fingerprint::fingerprint_file(&file_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let duplicates = find_duplicates(&app_state, track_id, &fingerprint).await?;
+    if !duplicates.is_empty() {
+        println!("Track {} has {} likely duplicates", track_id, duplicates.len());
+    }
+
+    app_state.db_client
+        .save_fingerprint(track_id, &fingerprint)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    app_state.db_client
+        .save_duplicates(track_id, &duplicates, None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    databases::gc::{GcExt, ReferencedFiles},
+    media::artwork,
+    storage::{Storage, StoredObject},
+    AppState,
+};
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub stale_uploads: u64,
+    pub unused_artworks: u64,
+    pub orphaned_paths: Vec<String>,
+    pub reclaimed_bytes: u64,
+}
+
+pub fn spawn_sweeper(app_state: Arc<AppState>) {
+    let interval = Duration::from_secs(app_state.env.gc_interval_minutes * 60);
+    let dry_run = app_state.env.gc_dry_run;
+
+    tokio::spawn(async move {
+        loop {
+            match sweep(&app_state, dry_run).await {
+                Ok(report) => log_report(&report),
+                Err(e) => eprintln!("Garbage collection failed: {}", e),
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+pub fn log_report(report: &GcReport) {
+    let prefix = if report.dry_run { "[dry run] would have removed" } else { "Removed" };
+
+    for path in &report.orphaned_paths {
+        println!("{} orphaned {}", prefix, path);
+    }
+    println!(
+        "{} {} abandoned uploads, {} unused artworks and {} orphaned files, {} bytes reclaimed",
+        prefix,
+        report.stale_uploads,
+        report.unused_artworks,
+        report.orphaned_paths.len(),
+        report.reclaimed_bytes
+    );
+}
+
+/// Removes uploads abandoned for longer than the TTL and artwork nothing uses
+/// anymore, then every stored file that no row refers to. With `dry_run`
+/// nothing is deleted.
+pub async fn sweep(app_state: &AppState, dry_run: bool) -> Result<GcReport, String> {
+    let ttl_seconds = app_state.env.upload_ttl_hours as i64 * 60 * 60;
+
+    let stale_uploads = app_state.db_client
+        .get_stale_uploads(ttl_seconds)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Rows go first so their chunk directories show up as orphans below
+    let stale_count = if dry_run || stale_uploads.is_empty() {
+        stale_uploads.len() as u64
+    } else {
+        app_state.db_client
+            .delete_stale_uploads(&stale_uploads, ttl_seconds)
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    let unused_artworks = app_state.db_client
+        .get_unused_artworks(ttl_seconds)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let unused_count = if dry_run || unused_artworks.is_empty() {
+        unused_artworks.len() as u64
+    } else {
+        app_state.db_client
+            .delete_unused_artworks(&unused_artworks, ttl_seconds)
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    let referenced = app_state.db_client
+        .get_referenced_files(&stale_uploads, &unused_artworks)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let min_age = Duration::from_secs(ttl_seconds as u64);
+    let (orphaned_paths, reclaimed_bytes) = remove_orphans(app_state.storage.as_ref(), &referenced, min_age, dry_run).await?;
+
+    Ok(GcReport {
+        dry_run,
+        stale_uploads: stale_count,
+        unused_artworks: unused_count,
+        orphaned_paths,
+        reclaimed_bytes,
+    })
+}
+
+async fn remove_orphans(
+    storage: &dyn Storage,
+    referenced: &ReferencedFiles,
+    min_age: Duration,
+    dry_run: bool,
+) -> Result<(Vec<String>, u64), String> {
+    let track_ids: HashSet<String> = referenced.track_ids.iter().map(Uuid::to_string).collect();
+
+    // Prefix, names it may contain, whether those names are directories
+    let locations: [(&str, &HashSet<String>, bool); 7] = [
+        ("uploads", &referenced.track_files, false),
+        ("uploads/temp", &referenced.pending_uploads, true),
+        ("uploads/renditions", &track_ids, true),
+        ("uploads/hls", &track_ids, true),
+        ("assets/images", &referenced.track_thumbnails, false),
+        ("assets/playlist", &referenced.playlist_thumbnails, false),
+        (artwork::ARTWORK_DIR, &referenced.artwork_files, false),
+    ];
+
+    let mut orphaned_paths = Vec::new();
+    let mut reclaimed_bytes = 0;
+
+    for (prefix, names, expect_dirs) in locations {
+        let objects = storage.list(prefix).await.map_err(|e| e.to_string())?;
+
+        // Objects grouped by the name right below `prefix`
+        let mut orphans: BTreeMap<String, Vec<StoredObject>> = BTreeMap::new();
+        for object in objects {
+            let Some(rest) = object.key.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('/')) else {
+                continue;
+            };
+            let (name, nested) = match rest.split_once('/') {
+                Some((name, _)) => (name, true),
+                None => (rest, false),
+            };
+            if nested != expect_dirs || names.contains(name) {
+                continue;
+            }
+
+            orphans.entry(name.to_string()).or_default().push(object);
+        }
+
+        for (name, objects) in orphans {
+            // Young files may belong to a request that hasn't written its row yet
+            if !is_older_than(&objects, min_age) {
+                continue;
+            }
+
+            let mut removed = true;
+            if !dry_run {
+                for object in &objects {
+                    if let Err(e) = storage.delete(&object.key).await {
+                        eprintln!("Failed to remove {}: {}", object.key, e);
+                        removed = false;
+                    }
+                }
+            }
+
+            if removed {
+                orphaned_paths.push(format!("{}/{}", prefix, name));
+                reclaimed_bytes += objects.iter().map(|object| object.size).sum::<u64>();
+            }
+        }
+    }
+
+    Ok((orphaned_paths, reclaimed_bytes))
+}
+
+/// Whether even the newest of `objects` is older than `min_age`.
+fn is_older_than(objects: &[StoredObject], min_age: Duration) -> bool {
+    let Some(newest) = objects.iter().map(|object| object.last_modified).max() else {
+        return false;
+    };
+
+    (Utc::now() - newest)
+        .to_std()
+        .map(|age| age >= min_age)
+        .unwrap_or(false)
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::{
+    databases::loudness::LoudnessExt,
+    jobs::JobError,
+    media::loudness,
+    storage::WorkingFile,
+    AppState,
+};
+
+/// Measures the loudness of an uploaded track and refreshes its album's ReplayGain values.
+pub async fn analyze_loudness(
+    track_id: Uuid,
+    file_name: &str,
+    app_state: Arc<AppState>,
+) -> Result<(), JobError> {
+    let source = WorkingFile::fetch(&app_state, &format!("uploads/{}", file_name)).await?;
+    let file_path = source.path_str();
+
+    let report = tokio::task::spawn_blocking(move || loudness::analyze_file(&file_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    println!(
+        "Loudness of track {}: {:?} LUFS, {:.1} LU range, {:?} dBTP",
+        track_id, report.integrated_lufs, report.loudness_range_lu, report.true_peak_dbtp
+    );
+
+    app_state.db_client
+        .save_loudness(track_id, &report)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    app_state.db_client
+        .update_album_loudness(track_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,241 @@
+pub mod archive;
+pub mod fingerprint;
+pub mod gc;
+pub mod loudness;
+pub mod renditions;
+pub mod upload;
+pub mod waveform;
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{databases::jobs::JobExt, models::Job, storage::StorageError, AppState};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often running jobs are touched and stale ones looked for.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const STALE_JOB_SECONDS: i64 = 15 * 60;
+const BACKOFF_BASE_SECONDS: i64 = 10;
+const BACKOFF_MAX_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobPayload {
+    ProcessUpload {
+        track_id: Uuid,
+        file_name: String,
+        total_chunks: usize,
+    },
+    GenerateRenditions {
+        track_id: Uuid,
+        file_name: String,
+    },
+    AnalyzeLoudness {
+        track_id: Uuid,
+        file_name: String,
+    },
+    GenerateWaveform {
+        track_id: Uuid,
+        file_name: String,
+    },
+    FingerprintTrack {
+        track_id: Uuid,
+        file_name: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    /// Worth retrying later, e.g. the database or disk was unavailable
+    Transient(String),
+    /// Retrying can't help, e.g. the uploaded file was rejected
+    Permanent(String),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Transient(message) | JobError::Permanent(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for JobError {
+    fn from(message: String) -> Self {
+        JobError::Transient(message)
+    }
+}
+
+impl From<StorageError> for JobError {
+    fn from(err: StorageError) -> Self {
+        JobError::Transient(err.to_string())
+    }
+}
+
+impl JobPayload {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobPayload::ProcessUpload { .. } => "process_upload",
+            JobPayload::GenerateRenditions { .. } => "generate_renditions",
+            JobPayload::AnalyzeLoudness { .. } => "analyze_loudness",
+            JobPayload::GenerateWaveform { .. } => "generate_waveform",
+            JobPayload::FingerprintTrack { .. } => "fingerprint_track",
+        }
+    }
+}
+
+/// Queues a follow-up job for a track that has already been uploaded.
+pub async fn enqueue(app_state: &AppState, track_id: Uuid, payload: JobPayload) -> Result<Uuid, String> {
+    let payload_json = serde_json::to_value(&payload).map_err(|e| e.to_string())?;
+
+    app_state.db_client
+        .enqueue_job(track_id, payload.kind(), payload_json, app_state.env.job_max_attempts)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub fn spawn_workers(app_state: Arc<AppState>) {
+    let app_state_clone = app_state.clone();
+    tokio::spawn(async move { requeue_stale_jobs(app_state_clone).await });
+
+    for worker_id in 0..app_state.env.worker_concurrency {
+        let app_state = app_state.clone();
+        tokio::spawn(async move { run_worker(worker_id, app_state).await });
+    }
+}
+
+// Jobs left 'running' by a crashed worker or instance are picked up again
+async fn requeue_stale_jobs(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let jobs = match app_state.db_client.requeue_stale_jobs(STALE_JOB_SECONDS).await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                eprintln!("Failed to requeue stale jobs: {}", e);
+                continue;
+            }
+        };
+
+        for job in jobs {
+            if job.status == "queued" {
+                println!("Requeued stale job {} (attempt {})", job.id, job.attempts);
+                continue;
+            }
+
+            eprintln!("Stale job {} is out of attempts and failed", job.id);
+            if let (Some(track_id), "process_upload") = (job.track_id, job.kind.as_str()) {
+                mark_track_failed(track_id, app_state.clone()).await;
+            }
+        }
+    }
+}
+
+async fn run_worker(worker_id: usize, app_state: Arc<AppState>) {
+    println!("Job worker {} started", worker_id);
+
+    loop {
+        let job = match app_state.db_client.claim_job().await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Worker {} failed to claim job: {}", worker_id, e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let job_id = job.id;
+        let track_id = job.track_id;
+        // Only a failed upload leaves the track unplayable, derived jobs can fail on their own
+        let fails_track = job.kind == "process_upload";
+        let attempts = job.attempts;
+
+        let result = {
+            let job_run = run_job(job, app_state.clone());
+            tokio::pin!(job_run);
+
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+            heartbeat.tick().await;
+
+            loop {
+                tokio::select! {
+                    result = &mut job_run => break result,
+                    _ = heartbeat.tick() => {
+                        if let Err(e) = app_state.db_client.touch_job(job_id).await {
+                            eprintln!("Failed to touch job {}: {}", job_id, e);
+                        }
+                    }
+                }
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                if let Err(e) = app_state.db_client.finish_job(job_id).await {
+                    eprintln!("Failed to mark job {} as succeeded: {}", job_id, e);
+                }
+            }
+            Err(err) => {
+                eprintln!("Job {} failed (attempt {}): {}", job_id, attempts, err);
+
+                let retry_in = match err {
+                    JobError::Transient(_) => Some(backoff_seconds(attempts)),
+                    JobError::Permanent(_) => None,
+                };
+
+                match app_state.db_client.fail_job(job_id, &err.to_string(), retry_in).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if let (Some(track_id), true) = (track_id, fails_track) {
+                            mark_track_failed(track_id, app_state.clone()).await;
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to record failure of job {}: {}", job_id, e),
+                }
+            }
+        }
+    }
+}
+
+async fn run_job(job: Job, app_state: Arc<AppState>) -> Result<(), JobError> {
+    let payload: JobPayload = serde_json::from_value(job.payload)
+        .map_err(|e| JobError::Permanent(format!("Invalid payload for job kind '{}': {}", job.kind, e)))?;
+
+    match payload {
+        JobPayload::ProcessUpload { track_id, file_name, total_chunks } => {
+            upload::process_upload(track_id, &file_name, total_chunks, app_state).await
+        }
+        JobPayload::GenerateRenditions { track_id, file_name } => {
+            renditions::generate_renditions(track_id, &file_name, app_state).await
+        }
+        JobPayload::AnalyzeLoudness { track_id, file_name } => {
+            loudness::analyze_loudness(track_id, &file_name, app_state).await
+        }
+        JobPayload::GenerateWaveform { track_id, file_name } => {
+            waveform::generate_waveform(track_id, &file_name, app_state).await
+        }
+        JobPayload::FingerprintTrack { track_id, file_name } => {
+            fingerprint::fingerprint_track(track_id, &file_name, app_state).await
+        }
+    }
+}
+
+async fn mark_track_failed(track_id: Uuid, app_state: Arc<AppState>) {
+    if let Err(e) = app_state.db_client.set_processing_status(track_id, "failed").await {
+        eprintln!("Failed to mark track {} as failed: {}", track_id, e);
+    }
+}
+
+/// Exponential backoff: 10s, 20s, 40s, ... capped at one hour.
+fn backoff_seconds(attempts: i32) -> i64 {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    (BACKOFF_BASE_SECONDS * 2_i64.pow(exponent)).min(BACKOFF_MAX_SECONDS)
+}
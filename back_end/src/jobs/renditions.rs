@@ -0,0 +1,56 @@
+use std::{path::Path, sync::Arc};
+
+use uuid::Uuid;
+
+use crate::{
+    databases::renditions::RenditionExt,
+    jobs::JobError,
+    media::renditions,
+    storage::WorkingFile,
+    AppState,
+};
+
+/// Encodes the quality tiers of an uploaded track into `uploads/renditions/{track_id}/`.
+pub async fn generate_renditions(
+    track_id: Uuid,
+    file_name: &str,
+    app_state: Arc<AppState>,
+) -> Result<(), JobError> {
+    let source = WorkingFile::fetch(&app_state, &format!("uploads/{}", file_name)).await?;
+    let output_prefix = format!("uploads/renditions/{}", track_id);
+    let output = WorkingFile::for_key(&app_state, &output_prefix)?;
+
+    let source_path = source.path_str();
+    let output_dir = output.path_str();
+    let ffmpeg_path = app_state.env.ffmpeg_path.clone();
+
+    if ffmpeg_path.is_none() {
+        println!("FFMPEG_PATH is not set, only the lossless rendition of {} is generated", track_id);
+    }
+
+    let mut outputs = tokio::task::spawn_blocking(move || {
+        renditions::generate_renditions(&source_path, &output_dir, ffmpeg_path.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    for rendition in &mut outputs {
+        // The database keeps the storage key rather than the local path
+        let file_name = Path::new(&rendition.file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let key = format!("{}/{}", output_prefix, file_name);
+        app_state.storage.put_file(&key, Path::new(&rendition.file_path)).await?;
+        rendition.file_path = key;
+
+        app_state.db_client
+            .save_rendition(track_id, rendition)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    println!("Generated {} renditions for track {}", outputs.len(), track_id);
+
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use symphonia::core::probe::ProbeResult;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::{
+    config::DuplicatePolicy,
+    databases::{artwork::ArtworkExt, fingerprints::FingerprintExt, jobs::JobExt, upload::UploadExt},
+    jobs::{self, JobError, JobPayload},
+    media::{
+        self,
+        archive,
+        AudioFormat,
+        artwork::{self, ProcessedArtwork},
+        fingerprint::{self, Fingerprint},
+        gapless::{self, GaplessInfo},
+        tags::{self, EmbeddedTags},
+        validate::{self, ValidationLimits, ValidationReport},
+    },
+    storage::{self, StorageError, WorkingFile},
+    AppState,
+};
+
+/// Number of samples per channel in the file.
+///
+/// The frame count announced by the container is preferred. VBR MP3 without a
+/// Xing header and some OGG files don't have one, and headers can be wrong,
+/// so the frames counted while decoding the file during validation are used
+/// when the header is missing or clearly too short.
+fn get_audio_frames(probed: &ProbeResult, report: &ValidationReport) -> Result<u64, String> {
+    if report.sample_rate == 0 {
+        return Err("Unknown sample rate".to_string());
+    }
+
+    let decoded_frames = report.frames;
+    let header_frames = probed
+        .format
+        .default_track()
+        .and_then(|track| track.codec_params.n_frames);
+
+    let tolerance = report.sample_rate as u64 / 10;
+    let frames = match header_frames {
+        Some(header_frames) if decoded_frames <= header_frames + tolerance => header_frames,
+        _ => decoded_frames,
+    };
+
+    Ok(frames)
+}
+
+/// Concatenates the uploaded chunks stored below `temp_prefix` into `output`.
+async fn assemble_file(
+    app_state: &AppState,
+    temp_prefix: &str,
+    output: &WorkingFile,
+    total_chunks: usize,
+) -> Result<(), StorageError> {
+    let backend = |e: std::io::Error| StorageError::Backend(format!("{}: {}", output.path().display(), e));
+
+    let mut output_file = tokio::fs::File::create(output.path()).await.map_err(backend)?;
+    for chunk_number in 0..total_chunks {
+        let chunk_key = format!("{}/chunk_{}", temp_prefix, chunk_number);
+        let chunk_data = app_state.storage.get_range(&chunk_key, None).await?;
+        output_file.write_all(&chunk_data).await.map_err(backend)?;
+    }
+    output_file.sync_all().await.map_err(backend)?;
+
+    Ok(())
+}
+
+fn audio_format(
+    file_path: &str,
+    report: &ValidationReport,
+    duration_ms: i64,
+    gapless: GaplessInfo,
+) -> Result<AudioFormat, String> {
+    let file_size = std::fs::metadata(file_path).map_err(|e| e.to_string())?.len();
+    // Bits per millisecond are kilobits per second
+    let bitrate_kbps = (file_size * 8).checked_div(duration_ms.max(0) as u64).unwrap_or(0);
+
+    Ok(AudioFormat {
+        container: report.container,
+        codec: validate::codec_name(report.codec),
+        sample_rate: report.sample_rate,
+        bit_depth: report.bits_per_sample,
+        channels: report.channels,
+        bitrate_kbps: bitrate_kbps as u32,
+        gapless,
+    })
+}
+
+/// What a validated upload told us about itself.
+pub struct AnalyzedFile {
+    pub duration_ms: i64,
+    pub format: AudioFormat,
+    pub tags: EmbeddedTags,
+    /// Embedded cover art, stored along with the track
+    pub cover: Option<ProcessedArtwork>,
+    /// Missing when the audio couldn't be fingerprinted, the upload goes on without
+    pub fingerprint: Option<Fingerprint>,
+    /// Tracks this one sounds like, found by `import_track_file`
+    pub duplicates: Vec<(Uuid, f64)>,
+}
+
+/// Validates, probes and reads the tags of a local file. Blocking.
+pub fn analyze_file(file_path: &str, limits: &ValidationLimits) -> Result<AnalyzedFile, JobError> {
+    let report = match validate::validate_file(file_path, limits) {
+        Ok(report) => report,
+        Err(err) if err.is_permanent() => return Err(JobError::Permanent(err.to_string())),
+        Err(err) => return Err(JobError::Transient(err.to_string())),
+    };
+    println!(
+        "Validated {}: {:?} / {}, {} Hz, {} channels, {} frames",
+        file_path, report.container, report.codec, report.sample_rate, report.channels, report.frames
+    );
+
+    let mut probed = media::probe_file(file_path)?;
+    let frames = get_audio_frames(&probed, &report)?;
+    let duration_ms = (frames as u128 * 1_000 / report.sample_rate as u128) as i64;
+    let (tags, cover) = tags::read_tags(&mut probed);
+    let gapless = gapless::read_gapless(&mut probed, frames);
+    let format = audio_format(file_path, &report, duration_ms, gapless)?;
+
+    // A broken cover isn't worth failing the upload over
+    let cover = cover.and_then(|cover| {
+        artwork::process_artwork(&cover.data)
+            .map_err(|e| eprintln!("Ignoring embedded cover of {}: {}", file_path, e))
+            .ok()
+    });
+
+    let fingerprint = fingerprint::fingerprint_file(file_path)
+        .map_err(|e| eprintln!("Failed to fingerprint {}: {}", file_path, e))
+        .ok();
+
+    Ok(AnalyzedFile { duration_ms, format, tags, cover, fingerprint, duplicates: Vec::new() })
+}
+
+impl AnalyzedFile {
+    /// Fills in the track from the file, marks it ready and queues the derived data.
+    pub async fn apply(self, app_state: &AppState, track_id: Uuid, file_name: &str) -> Result<(), JobError> {
+        let artwork_id = match &self.cover {
+            Some(cover) => {
+                artwork::store_artwork(app_state.storage.as_ref(), cover).await?;
+                Some(app_state.db_client.save_artwork(cover).await.map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+
+        // Embedded tags only fill in what the user hasn't entered already
+        app_state.db_client
+            .apply_embedded_tags(track_id, &self.tags, artwork_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(fingerprint) = &self.fingerprint {
+            app_state.db_client
+                .save_fingerprint(track_id, fingerprint)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        if !self.duplicates.is_empty() {
+            // Linked to the closest match, which is where its own duplicates point as well
+            let duplicate_of = match app_state.env.duplicate_policy {
+                DuplicatePolicy::Link => self.duplicates.first().map(|(id, _)| *id),
+                _ => None,
+            };
+            app_state.db_client
+                .save_duplicates(track_id, &self.duplicates, duplicate_of)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        app_state.db_client
+            .save_audio_format(track_id, &self.format)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        app_state.db_client
+            .update_status(track_id, self.duration_ms)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // The original is playable from here on, derived data is produced separately
+        let follow_ups = [
+            JobPayload::GenerateRenditions { track_id, file_name: file_name.to_string() },
+            JobPayload::AnalyzeLoudness { track_id, file_name: file_name.to_string() },
+            JobPayload::GenerateWaveform { track_id, file_name: file_name.to_string() },
+        ];
+        for payload in follow_ups {
+            jobs::enqueue(app_state, track_id, payload).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deletes the file of a rejected track, unless another track still uses it.
+async fn discard_file(app_state: &AppState, track_id: Uuid, file_name: &str) -> Result<(), JobError> {
+    let shared = app_state.db_client
+        .is_file_shared(track_id, file_name)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !shared {
+        let _ = app_state.storage.delete(&format!("uploads/{}", file_name)).await;
+    }
+
+    Ok(())
+}
+
+/// Turns the file stored under `uploads/{file_name}` into a ready track,
+/// `working_file` being its local copy. Files without a title tag are named
+/// `fallback_title`.
+///
+/// A file that is rejected for good is deleted, its bytes never stay in storage.
+/// That includes likely duplicates when `DUPLICATE_POLICY` is `reject`. Files
+/// that another track row still points at are left alone.
+pub async fn import_track_file(
+    app_state: &AppState,
+    track_id: Uuid,
+    file_name: &str,
+    working_file: &WorkingFile,
+    fallback_title: Option<String>,
+) -> Result<(), JobError> {
+    let limits = ValidationLimits::from_config(&app_state.env);
+    let file_path = working_file.path_str();
+
+    let analyzed = tokio::task::spawn_blocking(move || analyze_file(&file_path, &limits))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut analyzed = match analyzed {
+        Ok(analyzed) => analyzed,
+        Err(JobError::Permanent(error)) => {
+            discard_file(app_state, track_id, file_name).await?;
+            return Err(JobError::Permanent(error));
+        }
+        Err(err) => return Err(err),
+    };
+
+    if analyzed.tags.title.is_none() {
+        analyzed.tags.title = fallback_title;
+    }
+
+    if let Some(fingerprint) = &analyzed.fingerprint {
+        analyzed.duplicates = jobs::fingerprint::find_duplicates(app_state, track_id, fingerprint).await?;
+    }
+
+    if let (Some((duplicate_of, similarity)), DuplicatePolicy::Reject) =
+        (analyzed.duplicates.first(), app_state.env.duplicate_policy)
+    {
+        discard_file(app_state, track_id, file_name).await?;
+        return Err(JobError::Permanent(format!(
+            "Likely duplicate of track {} ({:.0}% similar)",
+            duplicate_of,
+            similarity * 100.0
+        )));
+    }
+
+    analyzed.apply(app_state, track_id, file_name).await
+}
+
+pub async fn process_upload(
+    track_id: Uuid,
+    file_name: &str,
+    total_chunks: usize,
+    app_state: Arc<AppState>,
+) -> Result<(), JobError> {
+    app_state.db_client
+        .set_processing_status(track_id, "processing")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut temp_prefix = format!("uploads/temp/{}", track_id);
+    let mut chunks = app_state.storage.list(&temp_prefix).await?;
+    // Uploads started before chunks were kept per track
+    if chunks.is_empty() {
+        temp_prefix = format!("uploads/temp/{}", file_name);
+        chunks = app_state.storage.list(&temp_prefix).await?;
+    }
+    let key = format!("uploads/{}", file_name);
+
+    // Safe to run again after a failed attempt: once the chunks have been
+    // assembled, stored and removed, the stored file is reused.
+    let working_file = if chunks.is_empty() {
+        WorkingFile::fetch(&app_state, &key).await?
+    } else {
+        let working_file = WorkingFile::for_key(&app_state, &key)?;
+        assemble_file(&app_state, &temp_prefix, &working_file, total_chunks).await?;
+        working_file.persist(&app_state, &key).await?;
+
+        // Clean up the temporary chunks
+        storage::delete_prefix(app_state.storage.as_ref(), &temp_prefix).await?;
+        working_file
+    };
+
+    let assembled_path = working_file.path_str();
+    let is_archive = tokio::task::spawn_blocking(move || archive::is_zip_file(&assembled_path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    if is_archive {
+        return jobs::archive::process_archive(&app_state, track_id, &key, &working_file).await;
+    }
+
+    import_track_file(&app_state, track_id, file_name, &working_file, None).await
+}
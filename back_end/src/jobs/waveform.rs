@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::{
+    databases::waveforms::WaveformExt,
+    jobs::JobError,
+    media::waveform,
+    storage::WorkingFile,
+    AppState,
+};
+
+/// Computes the seek-bar peaks of an uploaded track at every resolution.
+pub async fn generate_waveform(
+    track_id: Uuid,
+    file_name: &str,
+    app_state: Arc<AppState>,
+) -> Result<(), JobError> {
+    let source = WorkingFile::fetch(&app_state, &format!("uploads/{}", file_name)).await?;
+    let file_path = source.path_str();
+
+    let waveforms = tokio::task::spawn_blocking(move || waveform::generate_waveforms(&file_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    for (resolution, waveform) in &waveforms {
+        app_state.db_client
+            .save_waveform(track_id, resolution, waveform.samples_per_pixel as i32, &waveform.to_dat())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
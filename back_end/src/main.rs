@@ -1,18 +1,22 @@
 mod auth;
+mod commands;
 mod config;
 mod databases;
 mod dbs;
 mod dtos;
 mod errors;
 mod handler;
+mod jobs;
+mod media;
 mod models;
 mod routes;
+mod storage;
 mod utils;
 
 use std::{net::SocketAddr, sync::Arc};
 
 use axum::http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    header::{ACCEPT, ACCEPT_RANGES, AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE, RANGE},
     HeaderValue, Method,
 };
 use config::Config;
@@ -20,6 +24,8 @@ use dbs::DBClients;
 use dotenv::dotenv;
 use routes::create_router;
 use sqlx::postgres::PgPoolOptions;
+use storage::{cache::DiskCache, Storage};
+use tokio::sync::Semaphore;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::filter::LevelFilter;
 
@@ -27,6 +33,10 @@ use tracing_subscriber::filter::LevelFilter;
 pub struct AppState {
     pub env: Config,
     pub db_client: DBClients,
+    pub storage: Arc<dyn Storage>,
+    pub transcodes: Arc<DiskCache>,
+    /// One permit per transcode allowed to run
+    pub transcode_slots: Arc<Semaphore>,
 }
 
 #[tokio::main]
@@ -39,6 +49,10 @@ async fn main() {
 
     let config = Config::init();
 
+    if config.ffmpeg_path.is_none() {
+        println!("⚠️ FFMPEG_PATH is not set, MP3 renditions, HLS and MP3 transcoding are unavailable");
+    }
+
     let pool = match PgPoolOptions::new()
         .max_connections(10)
         .connect(&config.database_url)
@@ -56,17 +70,53 @@ async fn main() {
 
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:8000".parse::<HeaderValue>().unwrap())
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
+        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, RANGE, IF_NONE_MATCH, IF_RANGE])
+        // Players read these to seek and revalidate
+        .expose_headers([ETAG, CONTENT_RANGE, ACCEPT_RANGES])
         .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST, Method::PUT]);
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::HEAD]);
+
+    let storage = match storage::from_config(&config.storage) {
+        Ok(storage) => storage,
+        Err(err) => {
+            println!("🔥 Failed to set up storage: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let transcodes = match DiskCache::open(
+        &config.storage_work_dir,
+        "transcodes",
+        config.transcode_cache_mb * 1024 * 1024,
+    ).await {
+        Ok(cache) => cache,
+        Err(err) => {
+            println!("🔥 Failed to open the transcode cache: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     let db_client = DBClients::new(pool);
     let app_state = AppState {
         env: config.clone(),
         db_client,
+        storage,
+        transcodes: Arc::new(transcodes),
+        transcode_slots: Arc::new(Semaphore::new(config.transcode_concurrency)),
     };
 
-    let app = create_router(Arc::new(app_state.clone())).layer(cors.clone());
+    let app_state = Arc::new(app_state);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        let code = commands::run(&args, app_state).await;
+        std::process::exit(code);
+    }
+
+    jobs::spawn_workers(app_state.clone());
+    jobs::gc::spawn_sweeper(app_state.clone());
+
+    let app = create_router(app_state).layer(cors.clone());
 
     println!(
         "{}",
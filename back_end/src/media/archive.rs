@@ -0,0 +1,205 @@
+//! Albums uploaded as a single ZIP archive.
+//!
+//! Entry names are only used to recognise the cover and to name tracks,
+//! entries are never written to a path derived from them.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    config::Config,
+    media::{self, Container, SNIFF_LEN},
+};
+
+/// Covers are small, anything bigger is not worth decoding.
+const MAX_COVER_BYTES: u64 = 20 * 1024 * 1024;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(ZIP_MAGIC)
+}
+
+pub fn is_zip_file(file_path: &str) -> io::Result<bool> {
+    let mut head = [0; 4];
+    let read = File::open(file_path)?.read(&mut head)?;
+
+    Ok(is_zip(&head[..read]))
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveLimits {
+    pub max_entries: usize,
+    /// Sum of the uncompressed sizes of all entries
+    pub max_unpacked_bytes: u64,
+}
+
+impl ArchiveLimits {
+    pub fn from_config(config: &Config) -> Self {
+        ArchiveLimits {
+            max_entries: config.archive_max_entries,
+            max_unpacked_bytes: config.archive_max_unpacked_mb * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// The archive itself is unusable, retrying won't help
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Invalid(reason) => write!(f, "Invalid archive: {}", reason),
+            ArchiveError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(err: ZipError) -> Self {
+        match err {
+            ZipError::Io(err) => ArchiveError::Io(err),
+            err => ArchiveError::Invalid(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryKind {
+    Audio(Container),
+    Cover,
+    Skipped(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub index: usize,
+    /// Path inside the archive
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+impl ArchiveEntry {
+    /// File name without directories and extension, used as a fallback title.
+    pub fn stem(&self) -> Option<String> {
+        Path::new(&self.name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+    }
+}
+
+/// Lists the entries worth reporting, in name order, after checking the
+/// archive against the limits. Directories and hidden files are left out.
+pub fn list_entries(archive_path: &str, limits: &ArchiveLimits) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let mut archive = ZipArchive::new(File::open(archive_path).map_err(ArchiveError::Io)?)?;
+
+    if archive.len() > limits.max_entries {
+        return Err(ArchiveError::Invalid(format!(
+            "{} entries, at most {} are allowed",
+            archive.len(),
+            limits.max_entries
+        )));
+    }
+
+    // Declared sizes can lie, extraction enforces them again
+    let mut unpacked_bytes: u64 = 0;
+    let mut entries = Vec::new();
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        unpacked_bytes = unpacked_bytes.saturating_add(entry.size());
+        if unpacked_bytes > limits.max_unpacked_bytes {
+            return Err(ArchiveError::Invalid(format!(
+                "unpacks to more than {} MB",
+                limits.max_unpacked_bytes / 1024 / 1024
+            )));
+        }
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        // Absolute paths, `..` and the like
+        let Some(name) = entry.enclosed_name().map(|path| path.display().to_string()) else {
+            entries.push(ArchiveEntry { index, name: entry.name().to_string(), kind: EntryKind::Skipped("unsafe path") });
+            continue;
+        };
+
+        let hidden = name
+            .split(['/', '\\'])
+            .any(|part| part.starts_with('.') || part == "__MACOSX");
+        if hidden {
+            continue;
+        }
+
+        let is_cover = Path::new(&name)
+            .file_stem()
+            .is_some_and(|stem| stem.eq_ignore_ascii_case("cover"));
+
+        let kind = if entry.encrypted() {
+            EntryKind::Skipped("encrypted")
+        } else if is_cover {
+            EntryKind::Cover
+        } else {
+            let mut head = Vec::with_capacity(SNIFF_LEN);
+            match (&mut entry).take(SNIFF_LEN as u64).read_to_end(&mut head) {
+                Ok(_) => match media::sniff_container(&head) {
+                    Some(container) => EntryKind::Audio(container),
+                    None => EntryKind::Skipped("not an audio file"),
+                },
+                Err(_) => EntryKind::Skipped("unreadable"),
+            }
+        };
+
+        entries.push(ArchiveEntry { index, name, kind });
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(entries)
+}
+
+/// Writes an entry to `output_path`, failing if it holds more than it declared.
+pub fn extract_entry(archive_path: &str, index: usize, output_path: &str) -> Result<(), ArchiveError> {
+    let mut archive = ZipArchive::new(File::open(archive_path).map_err(ArchiveError::Io)?)?;
+    let entry = archive.by_index(index)?;
+    let declared = entry.size();
+
+    let mut output = File::create(output_path).map_err(ArchiveError::Io)?;
+    let written = io::copy(&mut entry.take(declared + 1), &mut output).map_err(ArchiveError::Io)?;
+    if written > declared {
+        drop(output);
+        let _ = fs::remove_file(output_path);
+        return Err(ArchiveError::Invalid("entry is larger than declared".to_string()));
+    }
+    output.flush().map_err(ArchiveError::Io)?;
+
+    Ok(())
+}
+
+/// Reads a cover entry into memory.
+pub fn read_cover(archive_path: &str, index: usize) -> Result<Vec<u8>, ArchiveError> {
+    let mut archive = ZipArchive::new(File::open(archive_path).map_err(ArchiveError::Io)?)?;
+    let entry = archive.by_index(index)?;
+    if entry.size() > MAX_COVER_BYTES {
+        return Err(ArchiveError::Invalid("cover is too large".to_string()));
+    }
+
+    let mut data = Vec::new();
+    entry.take(MAX_COVER_BYTES + 1).read_to_end(&mut data).map_err(ArchiveError::Io)?;
+    if data.len() as u64 > MAX_COVER_BYTES {
+        return Err(ArchiveError::Invalid("cover is too large".to_string()));
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,192 @@
+//! Cover art validation and re-encoding into fixed square sizes.
+//!
+//! Every variant is decoded and encoded again, so nothing from the upload
+//! (EXIF, ICC profiles, trailing bytes) survives apart from the pixels.
+
+use std::{fmt, io::Cursor};
+
+use image::{
+    codecs::jpeg::JpegEncoder,
+    imageops::{self, FilterType},
+    DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits, RgbImage,
+};
+use sha2::{Digest, Sha256};
+
+use crate::storage::{Storage, StorageError};
+
+pub const ARTWORK_DIR: &str = "assets/artwork";
+
+/// Square edge lengths produced for every artwork, smallest first.
+pub const SIZES: [u32; 5] = [64, 128, 256, 512, 1024];
+
+const MAX_DIMENSION: u32 = 8192;
+const JPEG_QUALITY: u8 = 85;
+const WEBP_QUALITY: f32 = 80.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtworkFormat {
+    Webp,
+    Jpeg,
+}
+
+impl ArtworkFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtworkFormat::Webp => "webp",
+            ArtworkFormat::Jpeg => "jpeg",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArtworkFormat::Webp => "webp",
+            ArtworkFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArtworkError {
+    /// The upload isn't an image we accept
+    Invalid(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ArtworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtworkError::Invalid(reason) => write!(f, "Invalid image: {}", reason),
+            ArtworkError::Io(err) => write!(f, "Failed to process artwork: {}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ArtworkVariant {
+    pub size: u32,
+    pub format: ArtworkFormat,
+    /// Hash of the encoded bytes plus extension
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct ProcessedArtwork {
+    /// Hash of the uploaded bytes, identical uploads share one artwork
+    pub source_hash: String,
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ArtworkVariant>,
+}
+
+/// Decodes an uploaded image and encodes every size in WebP and JPEG.
+pub fn process_artwork(bytes: &[u8]) -> Result<ProcessedArtwork, ArtworkError> {
+    let image = decode(bytes)?;
+    let (width, height) = (image.width(), image.height());
+
+    let smallest = SIZES[0];
+    if width < smallest || height < smallest {
+        return Err(ArtworkError::Invalid(format!(
+            "Image must be at least {}x{} pixels",
+            smallest, smallest
+        )));
+    }
+
+    // Center crop to a square, transparency is flattened onto white
+    let side = width.min(height);
+    let square = flatten(&image.crop_imm((width - side) / 2, (height - side) / 2, side, side));
+
+    let mut variants = Vec::new();
+    // Sizes larger than the source would only be upscaled
+    for size in SIZES.into_iter().filter(|size| *size <= side) {
+        let resized = imageops::resize(&square, size, size, FilterType::Lanczos3);
+
+        for format in [ArtworkFormat::Webp, ArtworkFormat::Jpeg] {
+            let data = encode(&resized, format)?;
+            variants.push(ArtworkVariant {
+                size,
+                format,
+                file_name: format!("{}.{}", hex_digest(&data), format.extension()),
+                data,
+            });
+        }
+    }
+
+    Ok(ProcessedArtwork {
+        source_hash: hex_digest(bytes),
+        width,
+        height,
+        variants,
+    })
+}
+
+/// Stores the variants under `ARTWORK_DIR`, variants that are already
+/// stored are identical by construction and are left alone.
+pub async fn store_artwork(storage: &dyn Storage, artwork: &ProcessedArtwork) -> Result<(), StorageError> {
+    for variant in &artwork.variants {
+        let key = format!("{}/{}", ARTWORK_DIR, variant.file_name);
+        if storage.head(&key).await?.is_some() {
+            continue;
+        }
+
+        storage.put(&key, variant.data.clone()).await?;
+    }
+
+    Ok(())
+}
+
+fn decode(bytes: &[u8]) -> Result<DynamicImage, ArtworkError> {
+    let invalid = |e: image::ImageError| ArtworkError::Invalid(e.to_string());
+
+    // The format comes from the magic bytes, never from the client supplied name
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(ArtworkError::Io)?;
+    match reader.format() {
+        Some(ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif) => {}
+        _ => return Err(ArtworkError::Invalid("Unsupported image format".to_string())),
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    // EXIF orientation is applied to the pixels since the tag itself is dropped
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+
+    Ok(image)
+}
+
+fn flatten(image: &DynamicImage) -> RgbImage {
+    let rgba = image.to_rgba8();
+
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |channel: u8| ((channel as u32 * a as u32 + 255 * (255 - a as u32) + 127) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+fn encode(image: &RgbImage, format: ArtworkFormat) -> Result<Vec<u8>, ArtworkError> {
+    match format {
+        ArtworkFormat::Webp => {
+            let encoded = webp::Encoder::from_rgb(image.as_raw(), image.width(), image.height()).encode(WEBP_QUALITY);
+            Ok(encoded.to_vec())
+        }
+        ArtworkFormat::Jpeg => {
+            let mut data = Vec::new();
+            JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY)
+                .encode_image(image)
+                .map_err(|e| ArtworkError::Io(std::io::Error::other(e)))?;
+            Ok(data)
+        }
+    }
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -0,0 +1,151 @@
+use std::ops::Range;
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error,
+    formats::{SeekMode, SeekTo},
+    units::Time,
+};
+
+use crate::media;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmSpec {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Bit depth of lossless sources, lossy ones have none
+    pub bits_per_sample: Option<u32>,
+}
+
+/// Decodes the default audio track of a file and hands interleaved `f32`
+/// samples to `on_samples`, one packet at a time.
+///
+/// Packets that fail to decode are skipped, the upload has already been
+/// validated so these are isolated glitches rather than a broken stream.
+pub fn decode_file<F>(file_path: &str, on_samples: F) -> Result<PcmSpec, String>
+where
+    F: FnMut(&PcmSpec, &[f32]) -> Result<(), String>,
+{
+    decode(file_path, None, on_samples)
+}
+
+/// Like `decode_file`, but only hands over the samples between `range_ms`,
+/// seeking to its start instead of decoding everything before it.
+pub fn decode_range<F>(file_path: &str, range_ms: Range<u64>, on_samples: F) -> Result<PcmSpec, String>
+where
+    F: FnMut(&PcmSpec, &[f32]) -> Result<(), String>,
+{
+    decode(file_path, Some(range_ms), on_samples)
+}
+
+fn decode<F>(file_path: &str, range_ms: Option<Range<u64>>, mut on_samples: F) -> Result<PcmSpec, String>
+where
+    F: FnMut(&PcmSpec, &[f32]) -> Result<(), String>,
+{
+    let mut probed = media::probe_file(file_path)?;
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base;
+    let sample_rate = track.codec_params.sample_rate.unwrap_or(0) as u64;
+    let bits_per_sample = track.codec_params.bits_per_sample;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| e.to_string())?;
+
+    // Wanted frames, without timing information everything is decoded
+    let frame_range = match (&range_ms, time_base, sample_rate) {
+        (Some(range_ms), Some(_), 1..) => Some(range_ms.start * sample_rate / 1000..range_ms.end * sample_rate / 1000),
+        _ => None,
+    };
+
+    if let Some(range_ms) = range_ms.as_ref().filter(|range_ms| range_ms.start > 0 && frame_range.is_some()) {
+        let seek_to = SeekTo::Time {
+            time: Time::from(range_ms.start as f64 / 1000.0),
+            track_id: Some(track_id),
+        };
+        // Streams that can't seek are decoded from the start, the frames before are dropped
+        match probed.format.seek(SeekMode::Accurate, seek_to) {
+            Ok(_) => decoder.reset(),
+            Err(e) => eprintln!("Decoding {} from the start, seeking failed: {}", file_path, e),
+        }
+    }
+
+    let mut spec: Option<PcmSpec> = None;
+    let mut sample_buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // First frame of the packet, from its timestamp
+        let packet_frame = match (time_base, &frame_range) {
+            (Some(time_base), Some(frame_range)) => {
+                let frame = (packet.ts() as u128 * time_base.numer as u128 * sample_rate as u128
+                    / time_base.denom as u128) as u64;
+                if frame >= frame_range.end {
+                    break;
+                }
+                Some(frame)
+            }
+            _ => None,
+        };
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if decoded.frames() == 0 {
+            continue;
+        }
+
+        let signal_spec = *decoded.spec();
+        let pcm_spec = *spec.get_or_insert(PcmSpec {
+            sample_rate: signal_spec.rate,
+            channels: signal_spec.channels.count(),
+            bits_per_sample,
+        });
+
+        let needs_buffer = sample_buffer
+            .as_ref()
+            .map(|buffer| buffer.capacity() < decoded.capacity() * pcm_spec.channels)
+            .unwrap_or(true);
+        if needs_buffer {
+            sample_buffer = Some(SampleBuffer::<f32>::new(decoded.capacity() as u64, signal_spec));
+        }
+
+        let buffer = sample_buffer.as_mut().unwrap();
+        buffer.copy_interleaved_ref(decoded);
+
+        let mut samples = buffer.samples();
+        if let (Some(packet_frame), Some(frame_range)) = (packet_frame, &frame_range) {
+            let frames = (samples.len() / pcm_spec.channels) as u64;
+            let skip = frame_range.start.saturating_sub(packet_frame).min(frames);
+            let keep = frame_range.end.saturating_sub(packet_frame).min(frames);
+            if skip >= keep {
+                continue;
+            }
+            samples = &samples[skip as usize * pcm_spec.channels..keep as usize * pcm_spec.channels];
+        }
+
+        on_samples(&pcm_spec, samples)?;
+    }
+
+    spec.ok_or_else(|| "No audio could be decoded".to_string())
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::Write,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::media::{decode::PcmSpec, flac::FlacWriter, transcode::DEFAULT_BITRATE_KBPS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Flac,
+    Mp3,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Flac => "flac",
+            Codec::Mp3 => "mp3",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Flac => "flac",
+            Codec::Mp3 => "mp3",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Codec::Flac => "audio/flac",
+            Codec::Mp3 => "audio/mpeg",
+        }
+    }
+
+    /// Lossy codecs have no pure Rust encoder and go through ffmpeg.
+    pub fn needs_ffmpeg(&self) -> bool {
+        !matches!(self, Codec::Flac)
+    }
+}
+
+/// Receives decoded interleaved samples and writes an encoded file.
+pub trait PcmEncoder: Send {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    fn finish(self: Box<Self>) -> Result<(), String>;
+}
+
+/// FLAC keeps every channel of the source, lossy renditions are downmixed to stereo at most.
+pub fn output_channels(codec: Codec, input_channels: usize) -> usize {
+    match codec {
+        Codec::Flac => input_channels,
+        Codec::Mp3 => input_channels.clamp(1, 2),
+    }
+}
+
+/// Bit depth of the FLAC rendition: 16-bit for sources of up to 16 bits and
+/// lossy or unknown ones, 24-bit for anything deeper. 8 and 20-bit sources are
+/// padded without loss, 32-bit and float ones are cut down to 24.
+pub fn flac_bits_per_sample(spec: &PcmSpec) -> u32 {
+    match spec.bits_per_sample {
+        Some(17..) => 24,
+        _ => 16,
+    }
+}
+
+pub fn create_encoder(
+    codec: Codec,
+    bitrate_kbps: Option<u32>,
+    spec: PcmSpec,
+    output_path: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<Box<dyn PcmEncoder>, String> {
+    match codec {
+        Codec::Flac => {
+            let bits_per_sample = flac_bits_per_sample(&spec);
+            let writer = FlacWriter::create(
+                output_path,
+                spec.sample_rate,
+                output_channels(Codec::Flac, spec.channels),
+                bits_per_sample,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(Box::new(FlacEncoder { writer, bits_per_sample }))
+        }
+        Codec::Mp3 => {
+            let ffmpeg_path = ffmpeg_path.ok_or("ffmpeg is not configured")?;
+            let bitrate = format!("{}k", bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS));
+            let encoder = FfmpegEncoder::spawn(
+                ffmpeg_path,
+                spec,
+                &["-c:a", "libmp3lame", "-b:a", &bitrate, "-f", "mp3"],
+                output_path,
+            )?;
+            Ok(Box::new(encoder))
+        }
+    }
+}
+
+/// MP3 for HLS segments: bare frames, without the ID3 tag and Xing header
+/// that would be played as a short silence at every segment boundary.
+pub fn create_segment_encoder(
+    bitrate_kbps: Option<u32>,
+    spec: PcmSpec,
+    output_path: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<Box<dyn PcmEncoder>, String> {
+    let ffmpeg_path = ffmpeg_path.ok_or("ffmpeg is not configured")?;
+    let bitrate = format!("{}k", bitrate_kbps.unwrap_or(DEFAULT_BITRATE_KBPS));
+    let encoder = FfmpegEncoder::spawn(
+        ffmpeg_path,
+        spec,
+        &["-c:a", "libmp3lame", "-b:a", &bitrate, "-id3v2_version", "0", "-write_xing", "0", "-f", "mp3"],
+        output_path,
+    )?;
+
+    Ok(Box::new(encoder))
+}
+
+/// Converts `f32` samples to integers of `bits_per_sample` bits.
+pub fn to_pcm(samples: &[f32], bits_per_sample: u32) -> Vec<i32> {
+    // Symphonia scales integer PCM by 2^(bits - 1), the same factor keeps sources of that depth bit-exact
+    let scale = (1i64 << (bits_per_sample - 1)) as f32;
+
+    samples
+        .iter()
+        .map(|sample| (sample * scale).round().clamp(-scale, scale - 1.0) as i32)
+        .collect()
+}
+
+struct FlacEncoder {
+    writer: FlacWriter,
+    bits_per_sample: u32,
+}
+
+impl PcmEncoder for FlacEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.writer
+            .write_interleaved(&to_pcm(samples, self.bits_per_sample))
+            .map_err(|e| e.to_string())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        self.writer.finish().map_err(|e| e.to_string())
+    }
+}
+
+/// Pipes raw 16-bit PCM into an ffmpeg process that writes the output file.
+struct FfmpegEncoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl FfmpegEncoder {
+    fn spawn(ffmpeg_path: &str, spec: PcmSpec, codec_args: &[&str], output_path: &str) -> Result<Self, String> {
+        let mut child = Command::new(ffmpeg_path)
+            .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y"])
+            .args(["-f", "s16le"])
+            .args(["-ar", &spec.sample_rate.to_string()])
+            .args(["-ac", &spec.channels.to_string()])
+            .args(["-i", "pipe:0", "-vn"])
+            // ffmpeg downmixes surround sources
+            .args(["-ac", &output_channels(Codec::Mp3, spec.channels).to_string()])
+            .args(codec_args)
+            .arg(output_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+
+        let stdin = child.stdin.take();
+
+        Ok(FfmpegEncoder { child, stdin })
+    }
+}
+
+impl PcmEncoder for FfmpegEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes: Vec<u8> = to_pcm(samples, 16)
+            .iter()
+            .flat_map(|sample| (*sample as i16).to_le_bytes())
+            .collect();
+
+        self.stdin
+            .as_mut()
+            .ok_or("ffmpeg input already closed")?
+            .write_all(&bytes)
+            .map_err(|e| format!("Failed to write to ffmpeg: {}", e))
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let FfmpegEncoder { child, stdin, .. } = *self;
+
+        // Closing stdin tells ffmpeg the stream has ended
+        drop(stdin);
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("ffmpeg did not finish: {}", e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "ffmpeg exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::decode;
+
+    #[test]
+    fn flac_keeps_bit_depth_and_channels() {
+        let spec = PcmSpec { sample_rate: 48000, channels: 6, bits_per_sample: Some(24) };
+        let samples: Vec<f32> = (0..6 * 10000)
+            .map(|i| ((i * 7919) % (1 << 24) - (1 << 23)) as f32 / (1 << 23) as f32)
+            .collect();
+
+        let path = std::env::temp_dir().join(format!("encode-test-{}.flac", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let mut encoder = create_encoder(Codec::Flac, None, spec, path, None).unwrap();
+        encoder.write(&samples).unwrap();
+        encoder.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        let decoded_spec = decode::decode_file(path, |_, chunk| {
+            decoded.extend_from_slice(chunk);
+            Ok(())
+        });
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(decoded_spec.unwrap(), spec);
+        assert_eq!(decoded, samples);
+    }
+}
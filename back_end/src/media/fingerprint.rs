@@ -0,0 +1,205 @@
+//! Acoustic fingerprints for recognising the same recording under another
+//! file name, container or set of tags.
+//!
+//! Like Chromaprint, the audio is reduced to 11025 Hz mono and cut into
+//! overlapping frames that each become a 32-bit sub-fingerprint. Bit `m`
+//! tells whether the energy difference between bands `m` and `m + 1` grew
+//! since the previous frame, which survives re-encoding, resampling and
+//! volume changes. Two fingerprints are compared by the share of bits they
+//! agree on once aligned.
+
+use realfft::RealFftPlanner;
+
+use crate::media::decode;
+
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_STEP: usize = FRAME_SIZE / 3;
+
+/// 33 bands give the 32 differences of one sub-fingerprint.
+const BANDS: usize = 33;
+const MIN_FREQUENCY: f32 = 300.0;
+const MAX_FREQUENCY: f32 = 2000.0;
+
+/// Only the start of a track is fingerprinted, that is enough to tell it apart.
+const MAX_SECONDS: usize = 120;
+
+/// Alignments with less overlap than this (about 10 seconds) are ignored.
+const MIN_OVERLAP: usize = 80;
+
+/// Frames between the three loudest bands that make up a lookup term.
+const TERM_SPAN: usize = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Fingerprint {
+    /// Sub-fingerprints, about 8 per second of audio
+    pub items: Vec<u32>,
+    /// Coarse keys for finding candidates in the index: the loudest band of
+    /// three frames in a row. They survive far more noise than the items.
+    pub terms: Vec<i32>,
+}
+
+pub fn fingerprint_file(file_path: &str) -> Result<Fingerprint, String> {
+    let max_samples = SAMPLE_RATE as usize * MAX_SECONDS;
+    let mut resampler: Option<Resampler> = None;
+    let mut samples = Vec::new();
+
+    decode::decode_file(file_path, |spec, interleaved| {
+        if samples.len() >= max_samples {
+            return Ok(());
+        }
+
+        let resampler = resampler.get_or_insert_with(|| Resampler::new(spec.sample_rate));
+        for frame in interleaved.chunks(spec.channels) {
+            let mono = frame.iter().sum::<f32>() / spec.channels as f32;
+            resampler.push(mono, &mut samples);
+        }
+
+        Ok(())
+    })?;
+
+    samples.truncate(max_samples);
+
+    Ok(fingerprint_samples(&samples))
+}
+
+/// Averages the input over every output period, a crude low-pass that is
+/// good enough for bands below 2 kHz.
+struct Resampler {
+    /// Input samples per output sample
+    step: f64,
+    position: f64,
+    sum: f32,
+    count: u32,
+}
+
+impl Resampler {
+    fn new(sample_rate: u32) -> Self {
+        Resampler {
+            step: sample_rate as f64 / SAMPLE_RATE as f64,
+            position: 0.0,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32, output: &mut Vec<f32>) {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1.0;
+
+        // Input below 11025 Hz repeats samples
+        while self.position >= self.step {
+            self.position -= self.step;
+            let value = if self.count > 0 { self.sum / self.count as f32 } else { sample };
+            output.push(value);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+fn fingerprint_samples(samples: &[f32]) -> Fingerprint {
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME_SIZE);
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FRAME_SIZE as f32).cos())
+        .collect();
+
+    // FFT bin where every band starts, spaced logarithmically
+    let edges: Vec<usize> = (0..=BANDS)
+        .map(|band| {
+            let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(band as f32 / BANDS as f32);
+            (frequency * FRAME_SIZE as f32 / SAMPLE_RATE as f32).round() as usize
+        })
+        .collect();
+
+    let mut items = Vec::new();
+    let mut loudest_bands = Vec::new();
+    let mut previous: Option<[f32; BANDS]> = None;
+
+    for start in (0..samples.len().saturating_sub(FRAME_SIZE - 1)).step_by(FRAME_STEP) {
+        for ((value, sample), weight) in input.iter_mut().zip(&samples[start..start + FRAME_SIZE]).zip(&window) {
+            *value = sample * weight;
+        }
+        if fft.process(&mut input, &mut spectrum).is_err() {
+            break;
+        }
+
+        let mut energies = [0.0; BANDS];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            *energy = spectrum[edges[band]..edges[band + 1].max(edges[band] + 1)]
+                .iter()
+                .map(|bin| bin.norm_sqr())
+                .sum();
+        }
+
+        let loudest = energies
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .filter(|(_, energy)| **energy > 0.0)
+            .map(|(band, _)| band);
+        loudest_bands.push(loudest);
+
+        if let Some(previous) = previous {
+            let mut item = 0u32;
+            for band in 0..BANDS - 1 {
+                let difference = (energies[band] - energies[band + 1]) - (previous[band] - previous[band + 1]);
+                if difference > 0.0 {
+                    item |= 1 << band;
+                }
+            }
+            items.push(item);
+        }
+        previous = Some(energies);
+    }
+
+    Fingerprint { items, terms: terms(&loudest_bands) }
+}
+
+/// Distinct lookup terms, silent frames left out.
+fn terms(loudest_bands: &[Option<usize>]) -> Vec<i32> {
+    let mut terms: Vec<i32> = (0..loudest_bands.len().saturating_sub(2 * TERM_SPAN))
+        .filter_map(|frame| {
+            let first = loudest_bands[frame]?;
+            let second = loudest_bands[frame + TERM_SPAN]?;
+            let third = loudest_bands[frame + 2 * TERM_SPAN]?;
+            Some(((first * BANDS + second) * BANDS + third) as i32)
+        })
+        .collect();
+    terms.sort_unstable();
+    terms.dedup();
+    terms
+}
+
+/// Share of matching bits of the best alignment of `a` and `b`, from 0.0
+/// to 1.0. Unrelated audio agrees on about half of the bits.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let min_offset = MIN_OVERLAP as isize - a.len() as isize;
+    let max_offset = b.len() as isize - MIN_OVERLAP as isize;
+
+    (min_offset..=max_offset)
+        .filter_map(|offset| aligned_similarity(a, b, offset))
+        .fold(0.0, f64::max)
+}
+
+/// Compares `a[i]` with `b[i + offset]` over the overlap.
+fn aligned_similarity(a: &[u32], b: &[u32], offset: isize) -> Option<f64> {
+    let (a, b) = if offset >= 0 {
+        (a, b.get(offset as usize..)?)
+    } else {
+        (a.get(offset.unsigned_abs()..)?, b)
+    };
+
+    let overlap = a.len().min(b.len());
+    if overlap < MIN_OVERLAP {
+        return None;
+    }
+
+    let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+
+    Some(1.0 - differing as f64 / (overlap * 32) as f64)
+}
@@ -0,0 +1,511 @@
+//! A small FLAC encoder for 16 and 24-bit PCM.
+//!
+//! It uses fixed linear predictors (orders 0-4) with partitioned Rice coding
+//! and stereo decorrelation, which gets within a few percent of `flac -5`
+//! on typical music while staying simple.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+};
+
+use md5::{Digest, Md5};
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+/// The STREAMINFO block (with its header) follows the "fLaC" marker.
+const STREAMINFO_OFFSET: u64 = 4;
+
+pub struct FlacWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    pending: Vec<Vec<i32>>,
+    md5: Md5,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl FlacWriter {
+    pub fn create(output_path: &str, sample_rate: u32, channels: usize, bits_per_sample: u32) -> std::io::Result<Self> {
+        if !(1..=8).contains(&channels) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "FLAC supports 1 to 8 channels",
+            ));
+        }
+        if !matches!(bits_per_sample, 16 | 24) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Only 16 and 24-bit samples are supported",
+            ));
+        }
+
+        let mut writer = BufWriter::new(File::create(output_path)?);
+        writer.write_all(b"fLaC")?;
+
+        let mut flac = FlacWriter {
+            writer,
+            sample_rate,
+            channels,
+            bits_per_sample,
+            pending: vec![Vec::with_capacity(BLOCK_SIZE); channels],
+            md5: Md5::new(),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+        };
+
+        // Placeholder, rewritten with the final sizes and sample count in `finish`
+        let streaminfo = flac.streaminfo(&[0; 16]);
+        flac.writer.write_all(&streaminfo)?;
+
+        Ok(flac)
+    }
+
+    /// Appends interleaved samples of the bit depth the writer was created with.
+    pub fn write_interleaved(&mut self, samples: &[i32]) -> std::io::Result<()> {
+        // STREAMINFO carries the MD5 of the interleaved little-endian samples
+        let sample_bytes = self.bits_per_sample as usize / 8;
+        for sample in samples {
+            self.md5.update(&sample.to_le_bytes()[..sample_bytes]);
+        }
+
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.pending[channel].push(*sample);
+            }
+
+            if self.pending[0].len() == BLOCK_SIZE {
+                self.flush_block()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if !self.pending[0].is_empty() {
+            self.flush_block()?;
+        }
+
+        let md5: [u8; 16] = self.md5.finalize_reset().into();
+        let streaminfo = self.streaminfo(&md5);
+        self.writer.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()
+    }
+
+    fn streaminfo(&self, md5: &[u8; 16]) -> Vec<u8> {
+        let mut bits = BitWriter::new();
+        // Metadata block header: last block, type 0 (STREAMINFO), 34 bytes
+        bits.write(1, 1);
+        bits.write(0, 7);
+        bits.write(34, 24);
+
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(self.min_frame_size as u64, 24);
+        bits.write(self.max_frame_size as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        bits.write(self.total_samples, 36);
+        for byte in md5 {
+            bits.write(*byte as u64, 8);
+        }
+
+        bits.into_bytes()
+    }
+
+    fn flush_block(&mut self) -> std::io::Result<()> {
+        let block_size = self.pending[0].len();
+        let frame = self.encode_frame(block_size);
+
+        let frame_size = frame.len() as u32;
+        self.min_frame_size = if self.frame_number == 0 { frame_size } else { self.min_frame_size.min(frame_size) };
+        self.max_frame_size = self.max_frame_size.max(frame_size);
+
+        self.writer.write_all(&frame)?;
+        self.frame_number += 1;
+        self.total_samples += block_size as u64;
+
+        for channel in self.pending.iter_mut() {
+            channel.clear();
+        }
+
+        Ok(())
+    }
+
+    fn encode_frame(&self, block_size: usize) -> Vec<u8> {
+        let (side, mid): (Vec<i32>, Vec<i32>) = if self.channels == 2 {
+            self.pending[0]
+                .iter()
+                .zip(&self.pending[1])
+                .map(|(l, r)| (l - r, (l + r) >> 1))
+                .unzip()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        // Pick the cheapest channel layout for stereo, independent channels otherwise
+        let (assignment, subframes) = if self.channels == 2 {
+            let left = &self.pending[0];
+            let right = &self.pending[1];
+
+            let left_sub = encode_subframe(left, self.bits_per_sample);
+            let right_sub = encode_subframe(right, self.bits_per_sample);
+            let side_sub = encode_subframe(&side, self.bits_per_sample + 1);
+            let mid_sub = encode_subframe(&mid, self.bits_per_sample);
+
+            let candidates = [
+                (left_sub.bits + right_sub.bits, 1u64),
+                (left_sub.bits + side_sub.bits, 8),
+                (side_sub.bits + right_sub.bits, 9),
+                (mid_sub.bits + side_sub.bits, 10),
+            ];
+            let best = candidates.iter().min_by_key(|(bits, _)| *bits).unwrap().1;
+
+            match best {
+                8 => (8, vec![left_sub, side_sub]),
+                9 => (9, vec![side_sub, right_sub]),
+                10 => (10, vec![mid_sub, side_sub]),
+                _ => (1, vec![left_sub, right_sub]),
+            }
+        } else {
+            let subframes = self
+                .pending
+                .iter()
+                .map(|channel| encode_subframe(channel, self.bits_per_sample))
+                .collect();
+            (self.channels as u64 - 1, subframes)
+        };
+
+        let mut bits = BitWriter::new();
+
+        // Frame header
+        bits.write(0b11111111111110, 14);
+        bits.write(0, 1); // reserved
+        bits.write(0, 1); // fixed block size
+        let block_size_code = if block_size == BLOCK_SIZE { 0b1100 } else { 0b0111 };
+        bits.write(block_size_code, 4);
+        bits.write(0, 4); // sample rate from STREAMINFO
+        bits.write(assignment, 4);
+        let sample_size_code = if self.bits_per_sample == 24 { 0b110 } else { 0b100 };
+        bits.write(sample_size_code, 3);
+        bits.write(0, 1); // reserved
+        write_utf8_number(&mut bits, self.frame_number);
+        if block_size_code == 0b0111 {
+            bits.write(block_size as u64 - 1, 16);
+        }
+        let header_crc = crc8(bits.bytes());
+        bits.write(header_crc as u64, 8);
+
+        for subframe in subframes {
+            subframe.write_to(&mut bits);
+        }
+
+        bits.align();
+        let frame_crc = crc16(bits.bytes());
+        bits.write(frame_crc as u64, 16);
+
+        bits.into_bytes()
+    }
+}
+
+enum SubframeKind {
+    Constant(i32),
+    Verbatim,
+    Fixed { order: usize, residual: Residual },
+}
+
+struct Subframe<'a> {
+    samples: &'a [i32],
+    bits_per_sample: u32,
+    kind: SubframeKind,
+    bits: u64,
+}
+
+impl Subframe<'_> {
+    fn write_to(&self, bits: &mut BitWriter) {
+        match &self.kind {
+            SubframeKind::Constant(value) => {
+                bits.write(0b0000000, 8);
+                bits.write_signed(*value, self.bits_per_sample);
+            }
+            SubframeKind::Verbatim => {
+                bits.write(0b00000010, 8);
+                for sample in self.samples {
+                    bits.write_signed(*sample, self.bits_per_sample);
+                }
+            }
+            SubframeKind::Fixed { order, residual } => {
+                bits.write(0b00010000 | ((*order as u64) << 1), 8);
+                for sample in &self.samples[..*order] {
+                    bits.write_signed(*sample, self.bits_per_sample);
+                }
+                residual.write_to(bits);
+            }
+        }
+    }
+}
+
+fn encode_subframe(samples: &[i32], bits_per_sample: u32) -> Subframe<'_> {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        return Subframe {
+            samples,
+            bits_per_sample,
+            kind: SubframeKind::Constant(samples[0]),
+            bits: 8 + bits_per_sample as u64,
+        };
+    }
+
+    let verbatim_bits = 8 + bits_per_sample as u64 * samples.len() as u64;
+    let mut best = Subframe {
+        samples,
+        bits_per_sample,
+        kind: SubframeKind::Verbatim,
+        bits: verbatim_bits,
+    };
+
+    let max_order = MAX_FIXED_ORDER.min(samples.len().saturating_sub(1));
+    for order in 0..=max_order {
+        let residual = fixed_residual(samples, order);
+        let encoded = Residual::encode(&residual, samples.len(), order);
+        let bits = 8 + bits_per_sample as u64 * order as u64 + encoded.bits;
+
+        if bits < best.bits {
+            best = Subframe {
+                samples,
+                bits_per_sample,
+                kind: SubframeKind::Fixed { order, residual: encoded },
+                bits,
+            };
+        }
+    }
+
+    best
+}
+
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i32> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back] as i64;
+            let residual = match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            };
+            residual as i32
+        })
+        .collect()
+}
+
+struct Residual {
+    values: Vec<u32>,
+    predictor_order: usize,
+    partition_order: u32,
+    parameters: Vec<u32>,
+    parameter_bits: u32,
+    bits: u64,
+}
+
+impl Residual {
+    /// Chooses the partition order and per-partition Rice parameters with the fewest bits.
+    fn encode(residual: &[i32], block_size: usize, predictor_order: usize) -> Residual {
+        let values: Vec<u32> = residual.iter().map(|r| ((r << 1) ^ (r >> 31)) as u32).collect();
+
+        let mut best: Option<(u32, Vec<u32>, u64)> = None;
+        for partition_order in 0..=MAX_PARTITION_ORDER {
+            let partitions = 1usize << partition_order;
+            if !block_size.is_multiple_of(partitions) || block_size / partitions <= predictor_order {
+                break;
+            }
+
+            let partition_size = block_size / partitions;
+            let mut parameters = Vec::with_capacity(partitions);
+            let mut bits = 0u64;
+            let mut start = 0;
+
+            for partition in 0..partitions {
+                let len = if partition == 0 { partition_size - predictor_order } else { partition_size };
+                let (parameter, partition_bits) = best_rice_parameter(&values[start..start + len]);
+                parameters.push(parameter);
+                bits += partition_bits;
+                start += len;
+            }
+
+            if best.as_ref().map(|(_, _, best_bits)| bits < *best_bits).unwrap_or(true) {
+                best = Some((partition_order, parameters, bits));
+            }
+        }
+
+        let (partition_order, parameters, bits) = best.unwrap();
+        let parameter_bits = if parameters.iter().any(|p| *p > 14) { 5 } else { 4 };
+        let total_bits = 2 + 4 + bits + parameter_bits as u64 * parameters.len() as u64;
+
+        Residual {
+            values,
+            predictor_order,
+            partition_order,
+            parameters,
+            parameter_bits,
+            bits: total_bits,
+        }
+    }
+
+    fn write_to(&self, bits: &mut BitWriter) {
+        // Coding method 0 has 4-bit Rice parameters, method 1 has 5-bit ones
+        bits.write(if self.parameter_bits == 5 { 1 } else { 0 }, 2);
+        bits.write(self.partition_order as u64, 4);
+
+        let block_size = self.values.len() + self.predictor_order;
+        let partition_size = block_size >> self.partition_order;
+        let mut start = 0;
+
+        for (partition, parameter) in self.parameters.iter().enumerate() {
+            let len = if partition == 0 { partition_size - self.predictor_order } else { partition_size };
+            bits.write(*parameter as u64, self.parameter_bits);
+            for value in &self.values[start..start + len] {
+                bits.write_rice(*value, *parameter);
+            }
+            start += len;
+        }
+    }
+}
+
+fn best_rice_parameter(values: &[u32]) -> (u32, u64) {
+    if values.is_empty() {
+        return (0, 0);
+    }
+
+    let sum: u64 = values.iter().map(|v| *v as u64).sum();
+    let mean = sum / values.len() as u64;
+    let estimate = if mean == 0 { 0 } else { 63 - mean.leading_zeros() };
+
+    // Check the neighbours of the estimate, the exact cost is cheap to compute
+    let mut best = (0u32, u64::MAX);
+    for parameter in estimate.saturating_sub(1)..=(estimate + 1).min(30) {
+        let bits: u64 = values
+            .iter()
+            .map(|v| (*v >> parameter) as u64 + 1 + parameter as u64)
+            .sum();
+        if bits < best.1 {
+            best = (parameter, bits);
+        }
+    }
+
+    best
+}
+
+fn write_utf8_number(bits: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bits.write(value, 8);
+        return;
+    }
+
+    let mut byte_count = 2;
+    while value >= 1u64 << (5 * byte_count + 1) {
+        byte_count += 1;
+    }
+
+    let lead_mask = (0xff00u64 >> byte_count) & 0xff;
+    let lead = lead_mask | (value >> (6 * (byte_count - 1)));
+    bits.write(lead, 8);
+    for i in (0..byte_count - 1).rev() {
+        bits.write(0x80 | ((value >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            accumulator: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Writes the low `bits` bits of `value`, at most 48 at a time.
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+
+        self.accumulator = (self.accumulator << bits) | (value & ((1u64 << bits) - 1));
+        self.bit_count += bits;
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            self.bytes.push((self.accumulator >> self.bit_count) as u8);
+        }
+        self.accumulator &= (1u64 << self.bit_count) - 1;
+    }
+
+    fn write_signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u64 & ((1u64 << bits) - 1), bits);
+    }
+
+    fn write_rice(&mut self, value: u32, parameter: u32) {
+        let mut quotient = value >> parameter;
+        while quotient > 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        self.write(1, quotient + 1);
+        if parameter > 0 {
+            self.write(value as u64 & ((1u64 << parameter) - 1), parameter);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.write(0, 8 - self.bit_count);
+        }
+    }
+
+    /// The bytes completed so far, only meaningful on a byte boundary.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
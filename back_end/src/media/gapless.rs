@@ -0,0 +1,122 @@
+use symphonia::core::{
+    codecs::CODEC_TYPE_NULL,
+    meta::{MetadataRevision, Value},
+    probe::ProbeResult,
+};
+
+/// What a player has to trim to join tracks without a gap, in samples per channel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GaplessInfo {
+    /// Silence the encoder put before the audio
+    pub encoder_delay: u32,
+    /// Silence the encoder put after the audio
+    pub encoder_padding: u32,
+    /// Samples left once delay and padding are trimmed
+    pub total_samples: u64,
+}
+
+/// Reads the encoder delay and padding of a probed file, `frames` being all
+/// the samples it decodes to.
+///
+/// An iTunSMPB tag (iTunes AAC and some MP3 files) is preferred, otherwise
+/// symphonia's reading of the LAME header of MP3 files and the pre-skip of
+/// Opus streams is used. Other files have neither delay nor padding.
+pub fn read_gapless(probed: &mut ProbeResult, frames: u64) -> GaplessInfo {
+    let mut smpb = None;
+
+    let format_metadata = probed.format.metadata();
+    if let Some(revision) = format_metadata.current() {
+        smpb = find_itunsmpb(revision);
+    }
+
+    if smpb.is_none() {
+        if let Some(metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.current() {
+                smpb = find_itunsmpb(revision);
+            }
+        }
+    }
+
+    if let Some(info) = smpb {
+        return info;
+    }
+
+    let (encoder_delay, encoder_padding) = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .map_or((0, 0), |track| {
+            (track.codec_params.delay.unwrap_or(0), track.codec_params.padding.unwrap_or(0))
+        });
+
+    GaplessInfo {
+        encoder_delay,
+        encoder_padding,
+        total_samples: frames.saturating_sub(encoder_delay as u64 + encoder_padding as u64),
+    }
+}
+
+fn find_itunsmpb(revision: &MetadataRevision) -> Option<GaplessInfo> {
+    revision
+        .tags()
+        .iter()
+        .filter(|tag| tag.key.ends_with("iTunSMPB"))
+        .find_map(|tag| match &tag.value {
+            Value::String(value) => parse_itunsmpb(value),
+            _ => None,
+        })
+}
+
+/// Parses " 00000000 00000840 000001CA 00000000003F31F6 ...", hexadecimal
+/// fields of which the second to fourth are delay, padding and sample count.
+fn parse_itunsmpb(value: &str) -> Option<GaplessInfo> {
+    let mut fields = value
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .split_whitespace()
+        .skip(1);
+
+    let encoder_delay = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let encoder_padding = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let total_samples = u64::from_str_radix(fields.next()?, 16).ok()?;
+
+    if total_samples == 0 {
+        return None;
+    }
+
+    Some(GaplessInfo { encoder_delay, encoder_padding, total_samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_itunsmpb() {
+        let info = parse_itunsmpb(
+            " 00000000 00000840 000001CA 00000000003F31F6 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000",
+        )
+        .unwrap();
+
+        assert_eq!(info.encoder_delay, 0x840);
+        assert_eq!(info.encoder_padding, 0x1ca);
+        assert_eq!(info.total_samples, 0x3f31f6);
+    }
+
+    #[test]
+    fn ignores_nul_padding_and_case() {
+        let info = parse_itunsmpb("\0 00000000 00000210 0000034f 0000000000ac4400\0").unwrap();
+
+        assert_eq!(info.encoder_delay, 0x210);
+        assert_eq!(info.encoder_padding, 0x34f);
+        assert_eq!(info.total_samples, 0xac4400);
+    }
+
+    #[test]
+    fn rejects_incomplete_values() {
+        assert!(parse_itunsmpb("").is_none());
+        assert!(parse_itunsmpb(" 00000000 00000840 000001CA").is_none());
+        assert!(parse_itunsmpb(" 00000000 00000840 000001CA 0000000000000000").is_none());
+        assert!(parse_itunsmpb(" 00000000 xyz 000001CA 00000000003F31F6").is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! HTTP Live Streaming of the lossy renditions.
+//!
+//! Segments are MPEG audio ("packed audio" in the HLS spec) cut from the
+//! decoded upload, each starting with the ID3 timestamp tag players use to
+//! line them up. They are encoded when first requested.
+
+use std::{fmt::Write as _, fs};
+
+use crate::media::{
+    decode,
+    encode::{self, Codec},
+    renditions::{RenditionSpec, RENDITIONS},
+};
+
+pub const SEGMENT_MS: u64 = 6_000;
+
+/// The ID3 PRIV owner HLS uses for the timestamp of packed audio segments.
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+
+/// Renditions that can be streamed over HLS, best quality first.
+pub fn variants() -> impl Iterator<Item = &'static RenditionSpec> {
+    RENDITIONS.iter().filter(|spec| spec.codec == Codec::Mp3)
+}
+
+pub fn find_variant(quality: &str) -> Option<&'static RenditionSpec> {
+    variants().find(|spec| spec.quality == quality)
+}
+
+pub fn segment_count(duration_ms: u64) -> u64 {
+    duration_ms.div_ceil(SEGMENT_MS)
+}
+
+/// Lists every variant, relative to the master playlist.
+pub fn master_playlist() -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+
+    for spec in variants() {
+        // Some headroom over the nominal bitrate for the ID3 tags and frame padding
+        let bandwidth = spec.bitrate_kbps.unwrap_or(192) * 1_100;
+        let _ = writeln!(playlist, "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"mp4a.40.34\"", bandwidth);
+        let _ = writeln!(playlist, "{}/index.m3u8", spec.quality);
+    }
+
+    playlist
+}
+
+/// The segments of a track of `duration_ms`, relative to the media playlist.
+pub fn media_playlist(duration_ms: u64) -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:VOD\n");
+    let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", SEGMENT_MS / 1000);
+    playlist.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
+
+    for segment in 0..segment_count(duration_ms) {
+        let start = segment * SEGMENT_MS;
+        let length = (start + SEGMENT_MS).min(duration_ms) - start;
+        let _ = writeln!(playlist, "#EXTINF:{:.3},", length as f64 / 1000.0);
+        let _ = writeln!(playlist, "{}.mp3", segment);
+    }
+
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
+/// Decodes segment `segment` of `source_path` and encodes it into `output_path`.
+/// Blocking.
+pub fn encode_segment(
+    source_path: &str,
+    spec: &RenditionSpec,
+    segment: u64,
+    output_path: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let start_ms = segment * SEGMENT_MS;
+
+    let audio = encode_audio(source_path, spec, start_ms, output_path, ffmpeg_path);
+    // The encoded file is only a step on the way, whether it worked or not
+    let _ = fs::remove_file(output_path);
+
+    let mut data = timestamp_tag(start_ms);
+    data.extend_from_slice(&audio?);
+
+    Ok(data)
+}
+
+fn encode_audio(
+    source_path: &str,
+    spec: &RenditionSpec,
+    start_ms: u64,
+    output_path: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let mut encoder = None;
+
+    decode::decode_range(source_path, start_ms..start_ms + SEGMENT_MS, |pcm_spec, samples| {
+        if encoder.is_none() {
+            encoder = Some(encode::create_segment_encoder(spec.bitrate_kbps, *pcm_spec, output_path, ffmpeg_path)?);
+        }
+        encoder.as_mut().unwrap().write(samples)
+    })?;
+
+    encoder.ok_or("The segment is past the end of the track")?.finish()?;
+
+    fs::read(output_path).map_err(|e| e.to_string())
+}
+
+/// ID3v2.4 tag with the start time of a segment on the 90 kHz MPEG clock.
+fn timestamp_tag(start_ms: u64) -> Vec<u8> {
+    let timestamp = (start_ms * 90) & ((1 << 33) - 1);
+
+    let mut frame = TIMESTAMP_OWNER.to_vec();
+    frame.extend_from_slice(&timestamp.to_be_bytes());
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(10 + frame.len() as u32));
+    tag.extend_from_slice(b"PRIV");
+    tag.extend_from_slice(&syncsafe(frame.len() as u32));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend_from_slice(&frame);
+
+    tag
+}
+
+/// ID3 sizes use 7 bits per byte.
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7f,
+        (size >> 14) as u8 & 0x7f,
+        (size >> 7) as u8 & 0x7f,
+        size as u8 & 0x7f,
+    ]
+}
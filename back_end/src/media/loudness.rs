@@ -0,0 +1,302 @@
+//! EBU R128 loudness measurement (ITU-R BS.1770-4 and EBU Tech 3342).
+
+use std::f64::consts::PI;
+
+use crate::media::decode::{self, PcmSpec};
+
+/// ReplayGain 2.0 reference level.
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE_LU: f64 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+
+/// Measurements are built from 100 ms sub-blocks.
+const SUB_BLOCKS_PER_SECOND: u32 = 10;
+/// Momentary blocks are 400 ms, short-term blocks 3 s.
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+/// Taps per phase of the true-peak interpolation filter.
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoudnessReport {
+    /// `None` for digital silence
+    pub integrated_lufs: Option<f64>,
+    pub loudness_range_lu: f64,
+    /// `None` for digital silence
+    pub true_peak_dbtp: Option<f64>,
+}
+
+/// Gain that brings a track measured at `lufs` to the ReplayGain reference.
+pub fn replaygain_gain_db(lufs: f64) -> f64 {
+    ((REPLAYGAIN_REFERENCE_LUFS - lufs) * 100.0).round() / 100.0
+}
+
+/// ReplayGain peaks are linear sample values, 1.0 being full scale.
+pub fn replaygain_peak(true_peak_dbtp: f64) -> f64 {
+    (10_f64.powf(true_peak_dbtp / 20.0) * 1_000_000.0).round() / 1_000_000.0
+}
+
+pub fn analyze_file(file_path: &str) -> Result<LoudnessReport, String> {
+    let mut meter: Option<LoudnessMeter> = None;
+
+    decode::decode_file(file_path, |spec, samples| {
+        meter.get_or_insert_with(|| LoudnessMeter::new(*spec)).add(samples);
+        Ok(())
+    })?;
+
+    meter
+        .map(LoudnessMeter::finish)
+        .ok_or_else(|| "No audio could be decoded".to_string())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+/// The two K-weighting stages, a high shelf modelling the head followed by
+/// the RLB high-pass, with coefficients derived for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10_f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// BS.1770 channel weights, assuming the usual 5.1 order (L R C LFE Ls Rs).
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Polyphase interpolator used to estimate inter-sample peaks.
+struct TruePeak {
+    factor: usize,
+    /// `phases[p][t]` is tap `t` of phase `p`
+    phases: Vec<Vec<f64>>,
+    /// Most recent samples per channel, newest first
+    history: Vec<Vec<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        // Oversample to at least 192 kHz as BS.1770 recommends
+        let factor = match sample_rate {
+            0..=95_999 => 4,
+            96_000..=191_999 => 2,
+            _ => 1,
+        };
+
+        let length = factor * TRUE_PEAK_TAPS_PER_PHASE;
+        let center = (length - 1) as f64 / 2.0;
+        let mut phases = vec![vec![0.0; TRUE_PEAK_TAPS_PER_PHASE]; factor];
+        for n in 0..length {
+            // Windowed sinc low-pass at the original Nyquist frequency
+            let x = (n as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / length as f64).cos();
+            phases[n % factor][n / factor] = sinc * window;
+        }
+
+        TruePeak {
+            factor,
+            phases,
+            history: vec![vec![0.0; TRUE_PEAK_TAPS_PER_PHASE]; channels],
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, channel: usize, sample: f64) {
+        self.peak = self.peak.max(sample.abs());
+        if self.factor == 1 {
+            return;
+        }
+
+        let history = &mut self.history[channel];
+        history.rotate_right(1);
+        history[0] = sample;
+
+        for phase in &self.phases {
+            let value: f64 = phase.iter().zip(history.iter()).map(|(tap, s)| tap * s).sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+struct LoudnessMeter {
+    spec: PcmSpec,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    true_peak: TruePeak,
+    sub_block_len: usize,
+    sub_block_fill: usize,
+    /// Running sum of squares per channel for the current sub-block
+    sub_block_sums: Vec<f64>,
+    /// Channel weighted mean square of every completed sub-block
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    fn new(spec: PcmSpec) -> Self {
+        LoudnessMeter {
+            spec,
+            filters: vec![k_weighting(spec.sample_rate); spec.channels],
+            weights: (0..spec.channels).map(|c| channel_weight(c, spec.channels)).collect(),
+            true_peak: TruePeak::new(spec.sample_rate, spec.channels),
+            sub_block_len: (spec.sample_rate / SUB_BLOCKS_PER_SECOND).max(1) as usize,
+            sub_block_fill: 0,
+            sub_block_sums: vec![0.0; spec.channels],
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.spec.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64;
+                self.true_peak.process(channel, sample);
+
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample));
+                self.sub_block_sums[channel] += weighted * weighted;
+            }
+
+            self.sub_block_fill += 1;
+            if self.sub_block_fill == self.sub_block_len {
+                let energy = self
+                    .sub_block_sums
+                    .iter()
+                    .zip(&self.weights)
+                    .map(|(sum, weight)| weight * sum / self.sub_block_len as f64)
+                    .sum();
+                self.sub_blocks.push(energy);
+                self.sub_block_sums.iter_mut().for_each(|sum| *sum = 0.0);
+                self.sub_block_fill = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> LoudnessReport {
+        let momentary = windowed_energies(&self.sub_blocks, MOMENTARY_SUB_BLOCKS);
+        let integrated_lufs = gated_energy(&momentary, INTEGRATED_RELATIVE_GATE_LU).map(energy_to_lufs);
+
+        let loudness_range_lu = loudness_range(&windowed_energies(&self.sub_blocks, SHORT_TERM_SUB_BLOCKS));
+
+        let true_peak_dbtp = (self.true_peak.peak > 0.0).then(|| 20.0 * self.true_peak.peak.log10());
+
+        LoudnessReport {
+            integrated_lufs,
+            loudness_range_lu,
+            true_peak_dbtp,
+        }
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn lufs_to_energy(lufs: f64) -> f64 {
+    10_f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Mean energy of every window of `length` sub-blocks, advancing one sub-block at a time.
+fn windowed_energies(sub_blocks: &[f64], length: usize) -> Vec<f64> {
+    sub_blocks
+        .windows(length)
+        .map(|window| window.iter().sum::<f64>() / length as f64)
+        .collect()
+}
+
+/// Applies the absolute gate, then a gate `relative_gate_lu` below the loudness
+/// of what remains, and returns the mean energy of the blocks that pass both.
+fn gated_energy(blocks: &[f64], relative_gate_lu: f64) -> Option<f64> {
+    let absolute_gate = lufs_to_energy(ABSOLUTE_GATE_LUFS);
+    let above_absolute: Vec<f64> = blocks.iter().copied().filter(|e| *e > absolute_gate).collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+    let relative_gate = lufs_to_energy(energy_to_lufs(mean) + relative_gate_lu);
+    let gated: Vec<f64> = above_absolute.into_iter().filter(|e| *e > relative_gate).collect();
+    if gated.is_empty() {
+        return None;
+    }
+
+    Some(gated.iter().sum::<f64>() / gated.len() as f64)
+}
+
+/// Spread between the 10th and 95th percentile of gated short-term loudness.
+fn loudness_range(short_term: &[f64]) -> f64 {
+    let Some(mean) = gated_energy(short_term, f64::NEG_INFINITY) else {
+        return 0.0;
+    };
+
+    let relative_gate = lufs_to_energy(energy_to_lufs(mean) + RANGE_RELATIVE_GATE_LU);
+    let absolute_gate = lufs_to_energy(ABSOLUTE_GATE_LUFS);
+    let mut loudness: Vec<f64> = short_term
+        .iter()
+        .copied()
+        .filter(|e| *e > absolute_gate && *e > relative_gate)
+        .map(energy_to_lufs)
+        .collect();
+    if loudness.len() < 2 {
+        return 0.0;
+    }
+
+    loudness.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+
+    percentile(0.95) - percentile(0.10)
+}
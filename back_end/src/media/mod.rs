@@ -0,0 +1,224 @@
+pub mod archive;
+pub mod artwork;
+pub mod decode;
+pub mod encode;
+pub mod fingerprint;
+pub mod flac;
+pub mod gapless;
+pub mod hls;
+pub mod loudness;
+pub mod renditions;
+pub mod retag;
+pub mod tags;
+pub mod transcode;
+pub mod validate;
+pub mod waveform;
+
+use std::{fs::File, io::Read};
+
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::{Hint, ProbeResult},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp3,
+    Aac,
+    Flac,
+    Ogg,
+    Wav,
+    Mp4,
+}
+
+impl Container {
+    /// The container stored with a track, see `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mp3" => Some(Container::Mp3),
+            "aac" => Some(Container::Aac),
+            "flac" => Some(Container::Flac),
+            "ogg" => Some(Container::Ogg),
+            "wav" => Some(Container::Wav),
+            "mp4" => Some(Container::Mp4),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "mp3",
+            Container::Aac => "aac",
+            Container::Flac => "flac",
+            Container::Ogg => "ogg",
+            Container::Wav => "wav",
+            Container::Mp4 => "m4a",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "mp3",
+            Container::Aac => "aac",
+            Container::Flac => "flac",
+            Container::Ogg => "ogg",
+            Container::Wav => "wav",
+            Container::Mp4 => "mp4",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Container::Mp3 => "audio/mpeg",
+            Container::Aac => "audio/aac",
+            Container::Flac => "audio/flac",
+            Container::Ogg => "audio/ogg",
+            Container::Wav => "audio/wav",
+            Container::Mp4 => "audio/mp4",
+        }
+    }
+}
+
+/// How an uploaded file is encoded, stored with its track.
+#[derive(Debug, Clone)]
+pub struct AudioFormat {
+    pub container: Container,
+    pub codec: String,
+    pub sample_rate: u32,
+    /// Only known for lossless codecs
+    pub bit_depth: Option<u32>,
+    pub channels: usize,
+    /// Average over the whole file, tags and artwork included
+    pub bitrate_kbps: u32,
+    pub gapless: gapless::GaplessInfo,
+}
+
+/// Number of leading bytes `sniff_container` needs to recognise a file.
+pub const SNIFF_LEN: usize = 4096;
+
+/// Recognises the container from its magic bytes, ignoring the file name.
+pub fn sniff_container(bytes: &[u8]) -> Option<Container> {
+    if bytes.len() < 4 {
+        return None;
+    }
+
+    // An ID3v2 tag can precede MP3, AAC and occasionally FLAC streams
+    if bytes.starts_with(b"ID3") && bytes.len() >= 10 {
+        let tag_size = ((bytes[6] as usize & 0x7f) << 21)
+            | ((bytes[7] as usize & 0x7f) << 14)
+            | ((bytes[8] as usize & 0x7f) << 7)
+            | (bytes[9] as usize & 0x7f);
+        let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+        let stream_start = 10 + tag_size + footer;
+
+        return match bytes.get(stream_start..) {
+            Some(rest) if rest.len() >= 4 => sniff_container(rest),
+            // The stream starts beyond what we were given, MP3 is by far the most common case
+            _ => Some(Container::Mp3),
+        };
+    }
+
+    if bytes.starts_with(b"fLaC") {
+        return Some(Container::Flac);
+    }
+    if bytes.starts_with(b"OggS") {
+        return Some(Container::Ogg);
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WAVE") {
+        return Some(Container::Wav);
+    }
+    if bytes.get(4..8) == Some(b"ftyp") {
+        return Some(Container::Mp4);
+    }
+
+    // MPEG audio frame sync: 11 set bits, the layer field tells ADTS AAC (00) from MP3
+    if bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0 {
+        return match (bytes[1] >> 1) & 0x03 {
+            0 if bytes[1] & 0xf0 == 0xf0 => Some(Container::Aac),
+            1..=3 => Some(Container::Mp3),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+/// Reads the first bytes of a file and sniffs its container.
+pub fn sniff_file(file_path: &str) -> std::io::Result<Option<Container>> {
+    let mut file = File::open(file_path)?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+
+    Ok(sniff_container(&head))
+}
+
+/// Opens an audio file and probes its container with symphonia.
+pub fn probe_file(file_path: &str) -> Result<ProbeResult, String> {
+    // The hint comes from the file content, never from the client supplied name
+    let mut hint = Hint::new();
+    if let Some(container) = sniff_file(file_path).map_err(|e| e.to_string())? {
+        hint.with_extension(container.extension());
+    }
+
+    let file = File::open(file_path).map_err(|e| e.to_string())?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(head: &[u8]) -> Vec<u8> {
+        let mut bytes = head.to_vec();
+        bytes.resize(64, 0);
+        bytes
+    }
+
+    #[test]
+    fn recognises_magic_bytes() {
+        assert_eq!(sniff_container(&padded(b"fLaC")), Some(Container::Flac));
+        assert_eq!(sniff_container(&padded(b"OggS")), Some(Container::Ogg));
+        assert_eq!(sniff_container(&padded(b"RIFF\x24\x00\x00\x00WAVEfmt ")), Some(Container::Wav));
+        assert_eq!(sniff_container(&padded(b"\x00\x00\x00\x20ftypM4A ")), Some(Container::Mp4));
+    }
+
+    #[test]
+    fn tells_mp3_from_adts_frames() {
+        // MPEG-1 layer III
+        assert_eq!(sniff_container(&padded(&[0xff, 0xfb, 0x90, 0x64])), Some(Container::Mp3));
+        // ADTS, MPEG-4 AAC without CRC
+        assert_eq!(sniff_container(&padded(&[0xff, 0xf1, 0x50, 0x80])), Some(Container::Aac));
+        // Layer 00 without the full 12-bit sync isn't ADTS
+        assert_eq!(sniff_container(&padded(&[0xff, 0xe1, 0x50, 0x80])), None);
+    }
+
+    #[test]
+    fn looks_past_id3_tags() {
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x0a".to_vec();
+        tagged.extend_from_slice(&[0; 10]);
+        tagged.extend_from_slice(b"fLaC");
+        assert_eq!(sniff_container(&padded(&tagged)), Some(Container::Flac));
+
+        // With a footer, ten more bytes come before the stream
+        let mut footer = b"ID3\x04\x00\x10\x00\x00\x00\x00".to_vec();
+        footer.extend_from_slice(&[0; 10]);
+        footer.extend_from_slice(&[0xff, 0xf1, 0x50, 0x80]);
+        assert_eq!(sniff_container(&padded(&footer)), Some(Container::Aac));
+
+        // Tags larger than the sniffed bytes are taken for MP3
+        assert_eq!(sniff_container(&padded(b"ID3\x04\x00\x00\x00\x01\x00\x00")), Some(Container::Mp3));
+    }
+
+    #[test]
+    fn rejects_unknown_and_short_input() {
+        assert_eq!(sniff_container(b"fLa"), None);
+        assert_eq!(sniff_container(&padded(b"RIFF\x24\x00\x00\x00AVI ")), None);
+        assert_eq!(sniff_container(&padded(b"<html>")), None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+
+use crate::media::{
+    decode,
+    encode::{self, Codec, PcmEncoder},
+};
+
+pub struct RenditionSpec {
+    pub quality: &'static str,
+    pub codec: Codec,
+    pub bitrate_kbps: Option<u32>,
+}
+
+/// Renditions generated for every track, from best to worst quality.
+pub const RENDITIONS: [RenditionSpec; 4] = [
+    RenditionSpec { quality: "lossless", codec: Codec::Flac, bitrate_kbps: None },
+    RenditionSpec { quality: "high", codec: Codec::Mp3, bitrate_kbps: Some(320) },
+    RenditionSpec { quality: "medium", codec: Codec::Mp3, bitrate_kbps: Some(160) },
+    RenditionSpec { quality: "low", codec: Codec::Mp3, bitrate_kbps: Some(96) },
+];
+
+/// Quality name clients use to ask for the uploaded file itself.
+pub const ORIGINAL_QUALITY: &str = "original";
+
+pub fn find_spec(quality: &str) -> Option<&'static RenditionSpec> {
+    RENDITIONS.iter().find(|spec| spec.quality == quality)
+}
+
+#[derive(Debug, Clone)]
+pub struct RenditionOutput {
+    pub quality: &'static str,
+    pub codec: Codec,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub file_path: String,
+    pub file_size: u64,
+}
+
+/// Decodes `source_path` once and encodes every rendition into `output_dir`.
+///
+/// Lossy renditions need ffmpeg and are skipped when it isn't configured.
+pub fn generate_renditions(
+    source_path: &str,
+    output_dir: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<Vec<RenditionOutput>, String> {
+    fs::create_dir_all(output_dir).map_err(|e| e.to_string())?;
+
+    let specs: Vec<&RenditionSpec> = RENDITIONS
+        .iter()
+        .filter(|spec| !spec.codec.needs_ffmpeg() || ffmpeg_path.is_some())
+        .collect();
+
+    // Encoders are created once the first packet tells us the PCM layout
+    let mut encoders: Vec<(&RenditionSpec, String, Box<dyn PcmEncoder>)> = Vec::new();
+
+    let spec = decode::decode_file(source_path, |pcm_spec, samples| {
+        if encoders.is_empty() {
+            for spec in &specs {
+                let file_path = format!("{}/{}.{}", output_dir, spec.quality, spec.codec.extension());
+                let encoder = encode::create_encoder(spec.codec, spec.bitrate_kbps, *pcm_spec, &file_path, ffmpeg_path)?;
+                encoders.push((spec, file_path, encoder));
+            }
+        }
+
+        for (_, _, encoder) in encoders.iter_mut() {
+            encoder.write(samples)?;
+        }
+
+        Ok(())
+    })?;
+
+    let mut outputs = Vec::with_capacity(encoders.len());
+    for (rendition, file_path, encoder) in encoders {
+        encoder.finish()?;
+
+        let file_size = fs::metadata(&file_path).map_err(|e| e.to_string())?.len();
+        outputs.push(RenditionOutput {
+            quality: rendition.quality,
+            codec: rendition.codec,
+            bitrate_kbps: rendition.bitrate_kbps,
+            sample_rate: spec.sample_rate,
+            channels: encode::output_channels(rendition.codec, spec.channels),
+            file_path,
+            file_size,
+        });
+    }
+
+    Ok(outputs)
+}
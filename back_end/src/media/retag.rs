@@ -0,0 +1,861 @@
+//! Writing a track's current tags and cover into a copy of its file, for
+//! downloads.
+//!
+//! Whatever tags the upload carried are replaced, the audio itself is copied
+//! byte for byte. Each container gets the tag format players expect there:
+//! ID3v2.3 for MP3, AAC and WAV, Vorbis comments for FLAC and Ogg, and iTunes
+//! style atoms for MP4.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::media::{tags::EmbeddedTags, Container};
+
+/// Front cover written into downloads.
+#[derive(Debug, Clone)]
+pub struct Cover {
+    /// A square JPEG
+    pub data: Vec<u8>,
+    /// Edge length in pixels
+    pub size: u32,
+}
+
+/// Copies `source_path` into `output_path` with `tags` and `cover` in place
+/// of the tags it had.
+pub fn retag(
+    source_path: &str,
+    container: Container,
+    tags: &EmbeddedTags,
+    cover: Option<&Cover>,
+    output_path: &str,
+) -> Result<(), String> {
+    let mut source = File::open(source_path).map_err(|e| e.to_string())?;
+    let mut output = BufWriter::new(File::create(output_path).map_err(|e| e.to_string())?);
+
+    let written = match container {
+        Container::Mp3 | Container::Aac => write_id3_prefixed(&mut source, &mut output, tags, cover),
+        Container::Flac => write_flac(&mut source, &mut output, tags, cover),
+        Container::Wav => write_wav(&mut source, &mut output, tags, cover),
+        Container::Ogg => write_ogg(&mut source, &mut output, tags, cover),
+        Container::Mp4 => write_mp4(&mut source, &mut output, tags, cover),
+    };
+
+    written.and_then(|_| output.flush()).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    TrackNumber,
+    DiscNumber,
+    Year,
+    Genre,
+    Isrc,
+}
+
+/// The tags that have a value, in the order they are written.
+fn fields(tags: &EmbeddedTags) -> Vec<(Field, String)> {
+    let number = |value: Option<i32>| value.map(|value| value.to_string());
+
+    [
+        (Field::Title, tags.title.clone()),
+        (Field::Artist, tags.artist.clone()),
+        (Field::Album, tags.album.clone()),
+        (Field::AlbumArtist, tags.album_artist.clone()),
+        (Field::TrackNumber, number(tags.track_number)),
+        (Field::DiscNumber, number(tags.disc_number)),
+        (Field::Year, number(tags.release_year)),
+        (Field::Genre, tags.genre.clone()),
+        (Field::Isrc, tags.isrc.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Some((field, value?.trim().to_string())))
+    .filter(|(_, value)| !value.is_empty())
+    .collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Up to `len` bytes from `offset`, fewer at the end of the file.
+fn read_at(source: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    source.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    Read::take(&mut *source, len).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn copy_range(source: &mut File, start: u64, end: u64, output: &mut impl Write) -> io::Result<()> {
+    source.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut Read::take(&mut *source, end.saturating_sub(start)), output)?;
+    if copied < end.saturating_sub(start) {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(())
+}
+
+// ID3v2.3, written in front of MP3 and AAC streams and into WAV chunks
+
+fn write_id3_prefixed(
+    source: &mut File,
+    output: &mut impl Write,
+    tags: &EmbeddedTags,
+    cover: Option<&Cover>,
+) -> io::Result<()> {
+    let start = id3_end(source)?;
+    let end = trailing_tags_start(source)?.max(start);
+
+    output.write_all(&id3_tag(tags, cover))?;
+    copy_range(source, start, end, output)
+}
+
+/// Where the ID3v2 tags at the start of a file end, 0 without any.
+fn id3_end(source: &mut File) -> io::Result<u64> {
+    let mut offset = 0;
+    loop {
+        let header = read_at(source, offset, 10)?;
+        if header.len() < 10 || !header.starts_with(b"ID3") {
+            return Ok(offset);
+        }
+
+        let size = header[6..10].iter().fold(0u64, |size, byte| (size << 7) | (*byte as u64 & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        offset += 10 + size + footer;
+    }
+}
+
+/// Where the ID3v1 and APEv2 tags at the end of a file start, the file size without any.
+fn trailing_tags_start(source: &mut File) -> io::Result<u64> {
+    let mut end = source.metadata()?.len();
+
+    if end >= 128 && read_at(source, end - 128, 3)? == b"TAG" {
+        end -= 128;
+    }
+
+    if end >= 32 {
+        let footer = read_at(source, end - 32, 32)?;
+        if footer.starts_with(b"APETAGEX") {
+            // The size covers the items and the footer, the optional header comes on top
+            let size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]) as u64;
+            let header = if footer[23] & 0x80 != 0 { 32 } else { 0 };
+            end = end.saturating_sub(size + header);
+        }
+    }
+
+    Ok(end)
+}
+
+fn id3_tag(tags: &EmbeddedTags, cover: Option<&Cover>) -> Vec<u8> {
+    let mut frames = Vec::new();
+    for (field, value) in fields(tags) {
+        let id = match field {
+            Field::Title => b"TIT2",
+            Field::Artist => b"TPE1",
+            Field::Album => b"TALB",
+            Field::AlbumArtist => b"TPE2",
+            Field::TrackNumber => b"TRCK",
+            Field::DiscNumber => b"TPOS",
+            Field::Year => b"TYER",
+            Field::Genre => b"TCON",
+            Field::Isrc => b"TSRC",
+        };
+        id3_frame(&mut frames, id, &id3_text(&value));
+    }
+
+    if let Some(cover) = cover {
+        // Latin-1 MIME type, front cover, empty description
+        let mut body = vec![0];
+        body.extend_from_slice(b"image/jpeg\0");
+        body.extend_from_slice(&[3, 0]);
+        body.extend_from_slice(&cover.data);
+        id3_frame(&mut frames, b"APIC", &body);
+    }
+
+    let size = frames.len() as u32;
+    let mut tag = Vec::with_capacity(10 + frames.len());
+    tag.extend_from_slice(b"ID3\x03\x00\x00");
+    // Synchsafe, 7 bits per byte
+    tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+    tag.extend(frames);
+    tag
+}
+
+fn id3_frame(frames: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    frames.extend_from_slice(id);
+    frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frames.extend_from_slice(&[0, 0]);
+    frames.extend_from_slice(body);
+}
+
+/// Latin-1 when the text fits, UTF-16 with a byte order mark otherwise.
+fn id3_text(value: &str) -> Vec<u8> {
+    if value.chars().all(|c| (c as u32) < 0x100) {
+        let mut text = vec![0];
+        text.extend(value.chars().map(|c| c as u8));
+        text
+    } else {
+        let mut text = vec![1, 0xff, 0xfe];
+        text.extend(value.encode_utf16().flat_map(u16::to_le_bytes));
+        text
+    }
+}
+
+// FLAC, the comment and picture metadata blocks are replaced
+
+const FLAC_PADDING: u8 = 1;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+const FLAC_PICTURE: u8 = 6;
+
+fn write_flac(source: &mut File, output: &mut impl Write, tags: &EmbeddedTags, cover: Option<&Cover>) -> io::Result<()> {
+    // Some taggers put an ID3v2 tag in front of the stream
+    let start = id3_end(source)?;
+    if read_at(source, start, 4)? != b"fLaC" {
+        return Err(invalid("Not a FLAC stream"));
+    }
+
+    let mut offset = start + 4;
+    let mut blocks = Vec::new();
+    let mut vendor = None;
+    loop {
+        let header = read_at(source, offset, 4)?;
+        if header.len() < 4 {
+            return Err(invalid("Truncated FLAC metadata"));
+        }
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7f;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+
+        let body = read_at(source, offset + 4, length)?;
+        if (body.len() as u64) < length {
+            return Err(invalid("Truncated FLAC metadata"));
+        }
+
+        match kind {
+            FLAC_PADDING | FLAC_PICTURE => {}
+            FLAC_VORBIS_COMMENT => vendor = comment_vendor(&body),
+            _ => blocks.push((kind, body)),
+        }
+
+        offset += 4 + length;
+        if last {
+            break;
+        }
+    }
+
+    // STREAMINFO has to stay the first block
+    if blocks.first().map(|(kind, _)| *kind) != Some(0) {
+        return Err(invalid("FLAC stream without STREAMINFO"));
+    }
+
+    blocks.push((FLAC_VORBIS_COMMENT, vorbis_comment(vendor.as_deref(), tags, None)));
+    if let Some(cover) = cover {
+        blocks.push((FLAC_PICTURE, flac_picture(cover)));
+    }
+
+    output.write_all(b"fLaC")?;
+    for (i, (kind, body)) in blocks.iter().enumerate() {
+        if body.len() >= 1 << 24 {
+            return Err(invalid("FLAC metadata block too large"));
+        }
+        let last = if i == blocks.len() - 1 { 0x80 } else { 0 };
+        output.write_all(&[kind | last])?;
+        output.write_all(&(body.len() as u32).to_be_bytes()[1..])?;
+        output.write_all(body)?;
+    }
+
+    let end = source.metadata()?.len();
+    copy_range(source, offset, end, output)
+}
+
+/// The FLAC PICTURE block, also embedded in Ogg comments.
+fn flac_picture(cover: &Cover) -> Vec<u8> {
+    let mime_type = b"image/jpeg";
+
+    let mut picture = Vec::new();
+    // Front cover
+    picture.extend_from_slice(&3u32.to_be_bytes());
+    picture.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+    picture.extend_from_slice(mime_type);
+    // No description
+    picture.extend_from_slice(&0u32.to_be_bytes());
+    picture.extend_from_slice(&cover.size.to_be_bytes());
+    picture.extend_from_slice(&cover.size.to_be_bytes());
+    // 24 bits per pixel, not indexed
+    picture.extend_from_slice(&24u32.to_be_bytes());
+    picture.extend_from_slice(&0u32.to_be_bytes());
+    picture.extend_from_slice(&(cover.data.len() as u32).to_be_bytes());
+    picture.extend_from_slice(&cover.data);
+    picture
+}
+
+/// The vendor string a Vorbis comment starts with.
+fn comment_vendor(comment: &[u8]) -> Option<String> {
+    let length = u32::from_le_bytes(comment.get(..4)?.try_into().ok()?) as usize;
+    let vendor = comment.get(4..4 + length)?;
+    String::from_utf8(vendor.to_vec()).ok()
+}
+
+/// A Vorbis comment, the cover goes in as a base64 PICTURE block where
+/// the container has no place of its own for it.
+fn vorbis_comment(vendor: Option<&str>, tags: &EmbeddedTags, cover: Option<&Cover>) -> Vec<u8> {
+    let mut comments: Vec<String> = fields(tags)
+        .into_iter()
+        .map(|(field, value)| {
+            let key = match field {
+                Field::Title => "TITLE",
+                Field::Artist => "ARTIST",
+                Field::Album => "ALBUM",
+                Field::AlbumArtist => "ALBUMARTIST",
+                Field::TrackNumber => "TRACKNUMBER",
+                Field::DiscNumber => "DISCNUMBER",
+                Field::Year => "DATE",
+                Field::Genre => "GENRE",
+                Field::Isrc => "ISRC",
+            };
+            format!("{}={}", key, value)
+        })
+        .collect();
+
+    if let Some(cover) = cover {
+        comments.push(format!("METADATA_BLOCK_PICTURE={}", STANDARD.encode(flac_picture(cover))));
+    }
+
+    let vendor = vendor.unwrap_or("music_platform");
+    let mut comment = Vec::new();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(vendor.as_bytes());
+    comment.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for entry in comments {
+        comment.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        comment.extend_from_slice(entry.as_bytes());
+    }
+    comment
+}
+
+// WAV, new LIST/INFO and "id3 " chunks replace the old ones. INFO is what
+// Windows and our own ingest read, it has no place for artwork
+
+fn write_wav(source: &mut File, output: &mut impl Write, tags: &EmbeddedTags, cover: Option<&Cover>) -> io::Result<()> {
+    let header = read_at(source, 0, 12)?;
+    if header.len() < 12 || !header.starts_with(b"RIFF") || &header[8..12] != b"WAVE" {
+        return Err(invalid("Not a RIFF WAVE file"));
+    }
+
+    let file_size = source.metadata()?.len();
+    let mut offset = 12;
+    let mut chunks = Vec::new();
+    while offset + 8 <= file_size {
+        let chunk_header = read_at(source, offset, 12)?;
+        let id = &chunk_header[..4];
+        let size = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]) as u64;
+        // Chunks are word aligned, a missing pad byte at the very end is tolerated
+        let end = (offset + 8 + size + (size & 1)).min(file_size);
+
+        let is_tag = id == b"id3 " || id == b"ID3 " || (id == b"LIST" && chunk_header.get(8..12) == Some(b"INFO"));
+        if !is_tag {
+            chunks.push((offset, end, id == b"data"));
+        }
+
+        offset = end;
+    }
+
+    let mut tag_chunks = riff_chunk(b"LIST", &wav_info(tags));
+    tag_chunks.extend(riff_chunk(b"id3 ", &id3_tag(tags, cover)));
+
+    let padded_length = |(start, end, _): &(u64, u64, bool)| (end - start + 1) & !1;
+    let riff_size = 4 + chunks.iter().map(padded_length).sum::<u64>() + tag_chunks.len() as u64;
+    let riff_size = u32::try_from(riff_size).map_err(|_| invalid("WAV file too large"))?;
+
+    output.write_all(b"RIFF")?;
+    output.write_all(&riff_size.to_le_bytes())?;
+    output.write_all(b"WAVE")?;
+
+    // Many readers stop at the audio, so the tags go in front of it
+    let mut tag_chunks = Some(tag_chunks);
+    for (start, end, is_data) in &chunks {
+        if *is_data {
+            if let Some(tag_chunks) = tag_chunks.take() {
+                output.write_all(&tag_chunks)?;
+            }
+        }
+        copy_range(source, *start, *end, output)?;
+        if (end - start) % 2 == 1 {
+            output.write_all(&[0])?;
+        }
+    }
+    match tag_chunks {
+        Some(tag_chunks) => output.write_all(&tag_chunks),
+        None => Ok(()),
+    }
+}
+
+fn wav_info(tags: &EmbeddedTags) -> Vec<u8> {
+    let mut info = b"INFO".to_vec();
+    for (field, value) in fields(tags) {
+        let id = match field {
+            Field::Title => b"INAM",
+            Field::Artist => b"IART",
+            Field::Album => b"IPRD",
+            Field::TrackNumber => b"IPRT",
+            Field::Year => b"ICRD",
+            Field::Genre => b"IGNR",
+            Field::AlbumArtist | Field::DiscNumber | Field::Isrc => continue,
+        };
+        // NUL terminated, UTF-8 is what current tools write
+        info.extend(riff_chunk(id, &[value.as_bytes(), &[0]].concat()));
+    }
+    info
+}
+
+/// A RIFF chunk, padded to an even length.
+fn riff_chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(body.len() + 9);
+    chunk.extend_from_slice(id);
+    chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+// Ogg Vorbis and Opus, the comment header packet is replaced and the
+// pages after it are renumbered
+
+const OGG_CONTINUED: u8 = 0x01;
+const OGG_FIRST_PAGE: u8 = 0x02;
+/// Granule position of pages on which no packet ends.
+const OGG_NO_GRANULE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OggCodec {
+    Vorbis,
+    Opus,
+}
+
+impl OggCodec {
+    fn comment_prefix(&self) -> &'static [u8] {
+        match self {
+            OggCodec::Vorbis => b"\x03vorbis",
+            OggCodec::Opus => b"OpusTags",
+        }
+    }
+
+    /// Header packets after the identification header: comment (and setup)
+    fn header_packets(&self) -> usize {
+        match self {
+            OggCodec::Vorbis => 2,
+            OggCodec::Opus => 1,
+        }
+    }
+}
+
+struct OggPage {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    body: Vec<u8>,
+}
+
+fn write_ogg(source: &mut File, output: &mut impl Write, tags: &EmbeddedTags, cover: Option<&Cover>) -> io::Result<()> {
+    source.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(source);
+
+    let first = read_ogg_page(&mut reader)?.ok_or_else(|| invalid("Empty Ogg file"))?;
+    if first.header_type & OGG_FIRST_PAGE == 0 {
+        return Err(invalid("Ogg file doesn't start with a stream"));
+    }
+    let codec = if first.body.starts_with(b"\x01vorbis") {
+        OggCodec::Vorbis
+    } else if first.body.starts_with(b"OpusHead") {
+        OggCodec::Opus
+    } else {
+        return Err(invalid("Only Vorbis and Opus can be tagged in Ogg"));
+    };
+
+    // Both codecs start the audio on a fresh page after the headers
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    let mut next_sequence = first.sequence + 1;
+    while packets.len() < codec.header_packets() {
+        let page = read_ogg_page(&mut reader)?.ok_or_else(|| invalid("Truncated Ogg headers"))?;
+        if page.serial != first.serial {
+            return Err(invalid("Multiplexed Ogg streams can't be tagged"));
+        }
+        next_sequence = page.sequence + 1;
+
+        let mut position = 0;
+        for lace in &page.segments {
+            packet.extend_from_slice(&page.body[position..position + *lace as usize]);
+            position += *lace as usize;
+            if *lace < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+
+        if packets.len() > codec.header_packets() || (packets.len() == codec.header_packets() && !packet.is_empty()) {
+            return Err(invalid("Ogg audio shares a page with the headers"));
+        }
+    }
+
+    let prefix = codec.comment_prefix();
+    let vendor = packets[0].strip_prefix(prefix).and_then(comment_vendor);
+    let mut comment = prefix.to_vec();
+    comment.extend(vorbis_comment(vendor.as_deref(), tags, cover));
+    if codec == OggCodec::Vorbis {
+        // Framing bit
+        comment.push(1);
+    }
+    packets[0] = comment;
+
+    write_ogg_page(&mut *output, &first)?;
+    let header_pages = paginate_ogg(&packets, first.serial, first.sequence + 1);
+    for page in &header_pages {
+        write_ogg_page(&mut *output, page)?;
+    }
+
+    let shift = (first.sequence + 1 + header_pages.len() as u32).wrapping_sub(next_sequence);
+    while let Some(mut page) = read_ogg_page(&mut reader)? {
+        if page.serial == first.serial {
+            page.sequence = page.sequence.wrapping_add(shift);
+        }
+        write_ogg_page(&mut *output, &page)?;
+    }
+
+    Ok(())
+}
+
+fn read_ogg_page(reader: &mut impl Read) -> io::Result<Option<OggPage>> {
+    let mut header = [0; 27];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if &header[..4] != b"OggS" {
+        return Err(invalid("Lost Ogg page sync"));
+    }
+
+    let mut segments = vec![0; header[26] as usize];
+    reader.read_exact(&mut segments)?;
+    let mut body = vec![0; segments.iter().map(|lace| *lace as usize).sum()];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(OggPage {
+        header_type: header[5],
+        granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+        serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+        sequence: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+        segments,
+        body,
+    }))
+}
+
+fn write_ogg_page(output: &mut impl Write, page: &OggPage) -> io::Result<()> {
+    let mut data = Vec::with_capacity(27 + page.segments.len() + page.body.len());
+    data.extend_from_slice(b"OggS");
+    data.push(0);
+    data.push(page.header_type);
+    data.extend_from_slice(&page.granule.to_le_bytes());
+    data.extend_from_slice(&page.serial.to_le_bytes());
+    data.extend_from_slice(&page.sequence.to_le_bytes());
+    // Checksum, computed with this field zeroed
+    data.extend_from_slice(&[0; 4]);
+    data.push(page.segments.len() as u8);
+    data.extend_from_slice(&page.segments);
+    data.extend_from_slice(&page.body);
+
+    let crc = ogg_crc(&data);
+    data[22..26].copy_from_slice(&crc.to_le_bytes());
+    output.write_all(&data)
+}
+
+/// Lays header packets out on as few pages as the 255 segment limit allows.
+fn paginate_ogg(packets: &[Vec<u8>], serial: u32, first_sequence: u32) -> Vec<OggPage> {
+    let mut pages = Vec::new();
+    let mut page = OggPage {
+        header_type: 0,
+        granule: OGG_NO_GRANULE,
+        serial,
+        sequence: first_sequence,
+        segments: Vec::new(),
+        body: Vec::new(),
+    };
+
+    for packet in packets {
+        // A packet is split into 255 byte segments plus a shorter (maybe empty) last one
+        let full_segments = packet.len() / 255;
+        for i in 0..=full_segments {
+            if page.segments.len() == 255 {
+                let next = OggPage {
+                    header_type: if i > 0 { OGG_CONTINUED } else { 0 },
+                    granule: OGG_NO_GRANULE,
+                    serial,
+                    sequence: page.sequence + 1,
+                    segments: Vec::new(),
+                    body: Vec::new(),
+                };
+                pages.push(std::mem::replace(&mut page, next));
+            }
+
+            let start = i * 255;
+            let end = (start + 255).min(packet.len());
+            page.segments.push((end - start) as u8);
+            page.body.extend_from_slice(&packet[start..end]);
+            if i == full_segments {
+                // Header pages are at granule 0 once a packet ends on them
+                page.granule = 0;
+            }
+        }
+    }
+
+    pages.push(page);
+    pages
+}
+
+const OGG_CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0, |crc, byte| (crc << 8) ^ OGG_CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize])
+}
+
+// MP4, the metadata in moov/udta/meta is replaced. When moov comes before
+// the media data, the chunk offsets are moved by the change in size
+
+fn write_mp4(source: &mut File, output: &mut impl Write, tags: &EmbeddedTags, cover: Option<&Cover>) -> io::Result<()> {
+    let file_size = source.metadata()?.len();
+
+    let mut offset = 0;
+    let mut moov = None;
+    while offset + 8 <= file_size {
+        let header = read_at(source, offset, 16)?;
+        let (kind, _, size) = mp4_box_header(&header, file_size - offset)?;
+        match &kind {
+            b"moov" => moov = Some((offset, offset + size)),
+            b"moof" => return Err(invalid("Fragmented MP4 files can't be tagged")),
+            _ => {}
+        }
+        offset += size;
+    }
+
+    let (moov_start, moov_end) = moov.ok_or_else(|| invalid("MP4 file without a moov box"))?;
+    let old_moov = read_at(source, moov_start, moov_end - moov_start)?;
+    let (_, header_length, _) = mp4_box_header(&old_moov, old_moov.len() as u64)?;
+
+    let mut body = Vec::new();
+    let mut has_udta = false;
+    let moov_body = &old_moov[header_length..];
+    for child in mp4_children(moov_body)? {
+        if &child.kind == b"udta" {
+            // Other user data, like chapters, stays
+            let udta_body = &moov_body[child.body_start..child.end];
+            let mut udta = Vec::new();
+            for item in mp4_children(udta_body)? {
+                if &item.kind != b"meta" {
+                    udta.extend_from_slice(&udta_body[item.start..item.end]);
+                }
+            }
+            udta.extend(mp4_meta(tags, cover));
+            body.extend(mp4_box(b"udta", &udta));
+            has_udta = true;
+        } else {
+            body.extend_from_slice(&moov_body[child.start..child.end]);
+        }
+    }
+    if !has_udta {
+        body.extend(mp4_box(b"udta", &mp4_meta(tags, cover)));
+    }
+
+    // Only media data stored after moov moves
+    let mut new_moov = mp4_box(b"moov", &body);
+    let shift = new_moov.len() as i64 - old_moov.len() as i64;
+    shift_chunk_offsets(&mut new_moov[8..], moov_end, shift)?;
+
+    copy_range(source, 0, moov_start, output)?;
+    output.write_all(&new_moov)?;
+    copy_range(source, moov_end, file_size, output)
+}
+
+/// Type, header length and total size of the box `data` starts with.
+fn mp4_box_header(data: &[u8], available: u64) -> io::Result<([u8; 4], usize, u64)> {
+    if data.len() < 8 {
+        return Err(invalid("Truncated MP4 box"));
+    }
+    let kind: [u8; 4] = data[4..8].try_into().unwrap();
+
+    let (header_length, size) = match u32::from_be_bytes(data[..4].try_into().unwrap()) {
+        // Extends to the end of the file
+        0 => (8, available),
+        1 => {
+            let size = data.get(8..16).ok_or_else(|| invalid("Truncated MP4 box"))?;
+            (16, u64::from_be_bytes(size.try_into().unwrap()))
+        }
+        size => (8, size as u64),
+    };
+
+    if size < header_length as u64 || size > available {
+        return Err(invalid("Invalid MP4 box size"));
+    }
+    Ok((kind, header_length, size))
+}
+
+/// A box inside another, positioned within the parent's body.
+struct Mp4Child {
+    kind: [u8; 4],
+    start: usize,
+    body_start: usize,
+    end: usize,
+}
+
+fn mp4_children(data: &[u8]) -> io::Result<Vec<Mp4Child>> {
+    let mut children = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let (kind, header_length, size) = mp4_box_header(&data[offset..], (data.len() - offset) as u64)?;
+        children.push(Mp4Child {
+            kind,
+            start: offset,
+            body_start: offset + header_length,
+            end: offset + size as usize,
+        });
+        offset += size as usize;
+    }
+    Ok(children)
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + body.len());
+    data.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    data
+}
+
+fn mp4_data(data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + value.len());
+    data.extend_from_slice(&data_type.to_be_bytes());
+    // Locale
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(value);
+    mp4_box(b"data", &data)
+}
+
+/// An iTunes metadata item holding one value.
+fn mp4_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    mp4_box(kind, &mp4_data(data_type, value))
+}
+
+fn mp4_meta(tags: &EmbeddedTags, cover: Option<&Cover>) -> Vec<u8> {
+    const UTF8: u32 = 1;
+    const NUMBERS: u32 = 0;
+    const JPEG: u32 = 13;
+
+    let mut items = Vec::new();
+    for (field, value) in fields(tags) {
+        let kind = match field {
+            Field::Title => b"\xa9nam",
+            Field::Artist => b"\xa9ART",
+            Field::Album => b"\xa9alb",
+            Field::AlbumArtist => b"aART",
+            Field::Year => b"\xa9day",
+            Field::Genre => b"\xa9gen",
+            // Written below, they aren't text
+            Field::TrackNumber | Field::DiscNumber | Field::Isrc => continue,
+        };
+        items.extend(mp4_item(kind, UTF8, value.as_bytes()));
+    }
+
+    // Position and total count, the total isn't known
+    if let Some(number) = tags.track_number.and_then(|number| u16::try_from(number).ok()) {
+        let [high, low] = number.to_be_bytes();
+        items.extend(mp4_item(b"trkn", NUMBERS, &[0, 0, high, low, 0, 0, 0, 0]));
+    }
+    if let Some(number) = tags.disc_number.and_then(|number| u16::try_from(number).ok()) {
+        let [high, low] = number.to_be_bytes();
+        items.extend(mp4_item(b"disk", NUMBERS, &[0, 0, high, low, 0, 0]));
+    }
+
+    if let Some(isrc) = tags.isrc.as_deref().filter(|isrc| !isrc.is_empty()) {
+        let mut freeform = Vec::new();
+        freeform.extend(mp4_box(b"mean", &[&[0; 4][..], b"com.apple.iTunes"].concat()));
+        freeform.extend(mp4_box(b"name", &[&[0; 4][..], b"ISRC"].concat()));
+        freeform.extend(mp4_data(UTF8, isrc.as_bytes()));
+        items.extend(mp4_box(b"----", &freeform));
+    }
+
+    if let Some(cover) = cover {
+        items.extend(mp4_item(b"covr", JPEG, &cover.data));
+    }
+
+    // meta is a full box, its handler marks the items as iTunes metadata
+    let mut hdlr = vec![0; 8];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+
+    let mut meta = vec![0; 4];
+    meta.extend(mp4_box(b"hdlr", &hdlr));
+    meta.extend(mp4_box(b"ilst", &items));
+    mp4_box(b"meta", &meta)
+}
+
+/// Moves the chunk offsets of every track that point past the old moov box.
+fn shift_chunk_offsets(data: &mut [u8], moov_end: u64, shift: i64) -> io::Result<()> {
+    for child in mp4_children(data)? {
+        let kind = child.kind;
+        let body = &mut data[child.body_start..child.end];
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(body, moov_end, shift)?,
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                let count = body.get(4..8).map_or(0, |count| u32::from_be_bytes(count.try_into().unwrap()) as usize);
+                let entries = body.get_mut(8..8 + count * width).ok_or_else(|| invalid("Truncated chunk offsets"))?;
+
+                for entry in entries.chunks_exact_mut(width) {
+                    let offset = if width == 4 {
+                        u32::from_be_bytes(entry.try_into().unwrap()) as u64
+                    } else {
+                        u64::from_be_bytes(entry.try_into().unwrap())
+                    };
+                    if offset < moov_end {
+                        continue;
+                    }
+
+                    let shifted = offset.checked_add_signed(shift).ok_or_else(|| invalid("Invalid chunk offset"))?;
+                    if width == 4 {
+                        let shifted = u32::try_from(shifted).map_err(|_| invalid("Chunk offset out of range"))?;
+                        entry.copy_from_slice(&shifted.to_be_bytes());
+                    } else {
+                        entry.copy_from_slice(&shifted.to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use symphonia::core::{
+    meta::{MetadataRevision, StandardTagKey, StandardVisualKey, Value},
+    probe::ProbeResult,
+};
+
+/// Tags read from ID3v2, Vorbis comments, MP4 atoms or FLAC metadata blocks.
+#[derive(Debug, Default, Clone)]
+pub struct EmbeddedTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub release_year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoverArt {
+    pub data: Vec<u8>,
+}
+
+/// Collects the embedded tags and front cover of a probed file.
+///
+/// Metadata inside the container (Vorbis comments, FLAC blocks, MP4 atoms) is
+/// preferred, metadata found while probing (an ID3v2 header) fills the gaps.
+pub fn read_tags(probed: &mut ProbeResult) -> (EmbeddedTags, Option<CoverArt>) {
+    let mut tags = EmbeddedTags::default();
+    let mut cover = None;
+
+    let mut format_metadata = probed.format.metadata();
+    if let Some(revision) = format_metadata.skip_to_latest() {
+        apply_revision(revision, &mut tags, &mut cover);
+    }
+
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            apply_revision(revision, &mut tags, &mut cover);
+        }
+    }
+
+    (tags, cover)
+}
+
+fn apply_revision(revision: &MetadataRevision, tags: &mut EmbeddedTags, cover: &mut Option<CoverArt>) {
+    for tag in revision.tags() {
+        let Some(key) = tag.std_key else { continue };
+        let Some(value) = value_to_string(&tag.value) else { continue };
+
+        let (slot, parsed) = match key {
+            StandardTagKey::TrackTitle => (&mut tags.title, Some(value)),
+            StandardTagKey::Artist => (&mut tags.artist, Some(value)),
+            StandardTagKey::Album => (&mut tags.album, Some(value)),
+            StandardTagKey::AlbumArtist => (&mut tags.album_artist, Some(value)),
+            StandardTagKey::Genre => (&mut tags.genre, Some(value)),
+            StandardTagKey::IdentIsrc => (&mut tags.isrc, parse_isrc(&value)),
+            StandardTagKey::TrackNumber => {
+                tags.track_number = tags.track_number.or(parse_position(&value));
+                continue;
+            }
+            StandardTagKey::DiscNumber => {
+                tags.disc_number = tags.disc_number.or(parse_position(&value));
+                continue;
+            }
+            StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate => {
+                tags.release_year = tags.release_year.or(parse_year(&value));
+                continue;
+            }
+            _ => continue,
+        };
+
+        if slot.is_none() {
+            *slot = parsed;
+        }
+    }
+
+    if cover.is_none() {
+        let visuals = revision.visuals();
+        let front = visuals
+            .iter()
+            .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+            .or_else(|| visuals.first());
+
+        if let Some(visual) = front {
+            if !visual.data.is_empty() {
+                *cover = Some(CoverArt {
+                    data: visual.data.to_vec(),
+                });
+            }
+        }
+    }
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(text) => text.trim().trim_matches('\0').to_string(),
+        Value::UnsignedInt(number) => number.to_string(),
+        Value::SignedInt(number) => number.to_string(),
+        Value::Float(number) => number.to_string(),
+        _ => return None,
+    };
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Parses "3" or "3/12" into 3.
+fn parse_position(value: &str) -> Option<i32> {
+    value
+        .split('/')
+        .next()
+        .and_then(|number| number.trim().parse::<i32>().ok())
+        .filter(|number| *number > 0)
+}
+
+/// Parses "2019", "2019-05-01" or "2019-05-01T00:00:00" into 2019.
+fn parse_year(value: &str) -> Option<i32> {
+    value
+        .get(..4)
+        .and_then(|year| year.parse::<i32>().ok())
+        .filter(|year| (1000..=9999).contains(year))
+}
+
+/// ISRCs are 12 alphanumeric characters, often written with dashes.
+fn parse_isrc(value: &str) -> Option<String> {
+    let isrc = value.replace('-', "").to_uppercase();
+    if isrc.len() == 12 && isrc.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(isrc)
+    } else {
+        None
+    }
+}
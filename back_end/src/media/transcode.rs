@@ -0,0 +1,204 @@
+//! Transcoding the uploaded file into another format while it is streamed,
+//! for clients that can't play what was uploaded.
+
+use crate::media::{
+    decode,
+    encode::{self, Codec, PcmEncoder},
+};
+
+/// What `X-Rendition-Quality` says for transcoded responses.
+pub const TRANSCODED_QUALITY: &str = "transcoded";
+
+/// MP3 bitrate used when the client doesn't ask for one.
+pub const DEFAULT_BITRATE_KBPS: u32 = 192;
+
+/// Bitrates a client may ask for, in kbps.
+const BITRATE_RANGE: std::ops::RangeInclusive<u32> = 32..=320;
+
+/// Format and bitrate a track is transcoded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranscodeTarget {
+    pub codec: Codec,
+    /// Only set for lossy codecs
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl TranscodeTarget {
+    /// Target named by the `format` and `bitrate` query parameters.
+    pub fn parse(format: Option<&str>, bitrate_kbps: Option<u32>, ffmpeg_available: bool) -> Result<Self, String> {
+        let codec = match format.unwrap_or("mp3") {
+            "mp3" => Codec::Mp3,
+            "flac" => Codec::Flac,
+            other => return Err(format!("Unsupported format '{}', expected mp3 or flac", other)),
+        };
+
+        if codec.needs_ffmpeg() && !ffmpeg_available {
+            return Err(format!("Transcoding to {} is not available", codec.name()));
+        }
+
+        let bitrate_kbps = match (codec, bitrate_kbps) {
+            (Codec::Flac, Some(_)) => return Err("A bitrate only applies to mp3".to_string()),
+            (Codec::Flac, None) => None,
+            (_, Some(bitrate)) if !BITRATE_RANGE.contains(&bitrate) => {
+                return Err(format!(
+                    "Bitrate must be between {} and {} kbps",
+                    BITRATE_RANGE.start(),
+                    BITRATE_RANGE.end()
+                ));
+            }
+            (_, bitrate) => Some(bitrate.unwrap_or(DEFAULT_BITRATE_KBPS)),
+        };
+
+        Ok(TranscodeTarget { codec, bitrate_kbps })
+    }
+
+    /// File name of the transcoded track in the cache.
+    pub fn file_name(&self) -> String {
+        match self.bitrate_kbps {
+            Some(bitrate) => format!("{}-{}.{}", self.codec.name(), bitrate, self.codec.extension()),
+            None => format!("{}.{}", self.codec.name(), self.codec.extension()),
+        }
+    }
+}
+
+/// Picks what to send for an `Accept` header: `Ok(None)` when the original
+/// is acceptable, otherwise the format the client prefers among those we can
+/// produce. `Err` when there is nothing the client accepts.
+pub fn negotiate(accept: Option<&str>, original_mime_type: &str, ffmpeg_available: bool) -> Result<Option<TranscodeTarget>, ()> {
+    if accept_quality(accept, original_mime_type) > 0.0 {
+        return Ok(None);
+    }
+
+    // Ties go to MP3, it plays nearly everywhere
+    let candidates = [Codec::Mp3, Codec::Flac]
+        .into_iter()
+        .filter(|codec| !codec.needs_ffmpeg() || ffmpeg_available);
+
+    let mut best: Option<(Codec, f32)> = None;
+    for codec in candidates {
+        let quality = accept_quality(accept, codec.mime_type());
+        if quality > best.map_or(0.0, |(_, best_quality)| best_quality) {
+            best = Some((codec, quality));
+        }
+    }
+
+    best.map(|(codec, _)| {
+        Some(TranscodeTarget {
+            codec,
+            bitrate_kbps: codec.needs_ffmpeg().then_some(DEFAULT_BITRATE_KBPS),
+        })
+    })
+    .ok_or(())
+}
+
+/// The q-value an `Accept` header gives `mime_type`, the most specific
+/// matching range wins. Everything is acceptable without the header.
+fn accept_quality(accept: Option<&str>, mime_type: &str) -> f32 {
+    let Some(accept) = accept else {
+        return 1.0;
+    };
+    let kind = mime_type.split('/').next().unwrap_or_default();
+
+    let mut best: Option<(u8, f32)> = None;
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';').map(str::trim);
+        let range = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let specificity = if range == mime_type {
+            3
+        } else if range.strip_suffix("/*") == Some(kind) {
+            2
+        } else if range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, quality));
+        }
+    }
+
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// Decodes `source_path` with symphonia and encodes it into `output_path`.
+/// The output grows as it is encoded, so it can be sent before it's done.
+pub fn transcode(
+    source_path: &str,
+    target: TranscodeTarget,
+    output_path: &str,
+    ffmpeg_path: Option<&str>,
+) -> Result<(), String> {
+    let mut encoder: Option<Box<dyn PcmEncoder>> = None;
+
+    decode::decode_file(source_path, |pcm_spec, samples| {
+        let encoder = match &mut encoder {
+            Some(encoder) => encoder,
+            None => encoder.insert(encode::create_encoder(
+                target.codec,
+                target.bitrate_kbps,
+                *pcm_spec,
+                output_path,
+                ffmpeg_path,
+            )?),
+        };
+
+        encoder.write(samples)
+    })?;
+
+    encoder.ok_or("The file contains no audio")?.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codec(result: Result<Option<TranscodeTarget>, ()>) -> Result<Option<Codec>, ()> {
+        result.map(|target| target.map(|target| target.codec))
+    }
+
+    #[test]
+    fn keeps_the_original_when_accepted() {
+        assert_eq!(codec(negotiate(None, "audio/flac", true)), Ok(None));
+        assert_eq!(codec(negotiate(Some("*/*"), "audio/flac", true)), Ok(None));
+        assert_eq!(codec(negotiate(Some("audio/*"), "audio/flac", true)), Ok(None));
+        assert_eq!(codec(negotiate(Some("audio/mpeg, audio/flac;q=0.1"), "audio/flac", true)), Ok(None));
+    }
+
+    #[test]
+    fn transcodes_when_the_original_is_refused() {
+        assert_eq!(codec(negotiate(Some("audio/mpeg"), "audio/flac", true)), Ok(Some(Codec::Mp3)));
+        assert_eq!(codec(negotiate(Some("audio/flac"), "audio/wav", true)), Ok(Some(Codec::Flac)));
+        // A specific range beats a wildcard, even with a lower q-value
+        assert_eq!(codec(negotiate(Some("audio/*, audio/wav;q=0"), "audio/wav", true)), Ok(Some(Codec::Mp3)));
+        assert_eq!(
+            codec(negotiate(Some("audio/mpeg;q=0.5, audio/flac"), "audio/wav", true)),
+            Ok(Some(Codec::Flac))
+        );
+    }
+
+    #[test]
+    fn prefers_mp3_on_ties() {
+        let target = negotiate(Some("audio/mpeg, audio/flac"), "audio/wav", true).unwrap().unwrap();
+
+        assert_eq!(target.codec, Codec::Mp3);
+        assert_eq!(target.bitrate_kbps, Some(DEFAULT_BITRATE_KBPS));
+    }
+
+    #[test]
+    fn only_offers_mp3_with_ffmpeg() {
+        assert_eq!(codec(negotiate(Some("audio/mpeg, audio/flac"), "audio/wav", false)), Ok(Some(Codec::Flac)));
+        assert_eq!(codec(negotiate(Some("audio/mpeg"), "audio/wav", false)), Err(()));
+    }
+
+    #[test]
+    fn fails_when_nothing_is_acceptable() {
+        assert_eq!(codec(negotiate(Some("video/mp4"), "audio/wav", true)), Err(()));
+        assert_eq!(codec(negotiate(Some("audio/*;q=0"), "audio/wav", true)), Err(()));
+    }
+}
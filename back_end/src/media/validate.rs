@@ -0,0 +1,253 @@
+use std::fmt;
+
+use symphonia::core::{
+    codecs::{
+        CodecType, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3,
+        CODEC_TYPE_NULL, CODEC_TYPE_PCM_F32LE, CODEC_TYPE_PCM_S16LE, CODEC_TYPE_PCM_S24LE,
+        CODEC_TYPE_PCM_S32LE, CODEC_TYPE_PCM_U8, CODEC_TYPE_VORBIS,
+    },
+    errors::Error,
+};
+
+use crate::{config::Config, media::{self, Container}};
+
+/// Codecs we accept and can decode for streaming, analysis and renditions.
+const ALLOWED_CODECS: [CodecType; 9] = [
+    CODEC_TYPE_MP3,
+    CODEC_TYPE_AAC,
+    CODEC_TYPE_FLAC,
+    CODEC_TYPE_VORBIS,
+    CODEC_TYPE_PCM_U8,
+    CODEC_TYPE_PCM_S16LE,
+    CODEC_TYPE_PCM_S24LE,
+    CODEC_TYPE_PCM_S32LE,
+    CODEC_TYPE_PCM_F32LE,
+];
+
+const MIN_SAMPLE_RATE: u32 = 8_000;
+
+/// Tolerated share of packets that fail to decode, e.g. a damaged first MP3 frame.
+const MAX_DECODE_ERROR_RATIO: f64 = 0.01;
+
+#[derive(Debug, Clone)]
+pub struct ValidationLimits {
+    pub min_seconds: u64,
+    pub max_seconds: u64,
+    pub max_sample_rate: u32,
+    pub max_channels: usize,
+}
+
+impl ValidationLimits {
+    pub fn from_config(config: &Config) -> Self {
+        ValidationLimits {
+            min_seconds: config.min_track_seconds,
+            max_seconds: config.max_track_seconds,
+            max_sample_rate: config.max_sample_rate,
+            max_channels: config.max_channels,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub container: Container,
+    pub codec: CodecType,
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Sample size of lossless codecs
+    pub bits_per_sample: Option<u32>,
+    pub frames: u64,
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    Io(String),
+    UnrecognizedContainer,
+    NoAudioTrack,
+    UnsupportedCodec(String),
+    Corrupted(String),
+    Truncated { expected_frames: u64, decoded_frames: u64 },
+    TooShort { seconds: f64, min_seconds: u64 },
+    TooLong { seconds: f64, max_seconds: u64 },
+    UnsupportedSampleRate(u32),
+    TooManyChannels { channels: usize, max_channels: usize },
+}
+
+impl ValidationError {
+    /// Everything except I/O trouble on our side is a property of the upload itself.
+    pub fn is_permanent(&self) -> bool {
+        !matches!(self, ValidationError::Io(_))
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Io(message) => write!(f, "Could not read the upload: {}", message),
+            ValidationError::UnrecognizedContainer => write!(f, "Unrecognized file type, expected MP3, AAC, M4A, FLAC, OGG or WAV"),
+            ValidationError::NoAudioTrack => write!(f, "The file does not contain an audio track"),
+            ValidationError::UnsupportedCodec(codec) => write!(f, "Unsupported audio codec: {}", codec),
+            ValidationError::Corrupted(message) => write!(f, "The audio stream is corrupted: {}", message),
+            ValidationError::Truncated { expected_frames, decoded_frames } => write!(
+                f,
+                "The audio stream is truncated: decoded {} of {} frames",
+                decoded_frames, expected_frames
+            ),
+            ValidationError::TooShort { seconds, min_seconds } => write!(
+                f,
+                "Track is too short ({:.1}s), the minimum is {}s",
+                seconds, min_seconds
+            ),
+            ValidationError::TooLong { seconds, max_seconds } => write!(
+                f,
+                "Track is too long ({:.0}s), the maximum is {}s",
+                seconds, max_seconds
+            ),
+            ValidationError::UnsupportedSampleRate(rate) => write!(f, "Unsupported sample rate: {} Hz", rate),
+            ValidationError::TooManyChannels { channels, max_channels } => write!(
+                f,
+                "Too many channels ({}), the maximum is {}",
+                channels, max_channels
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+pub fn codec_name(codec: CodecType) -> String {
+    symphonia::default::get_codecs()
+        .get_codec(codec)
+        .map(|descriptor| descriptor.short_name.to_string())
+        .unwrap_or_else(|| codec.to_string())
+}
+
+/// Fully decodes an upload and checks it against the codec allowlist and limits.
+pub fn validate_file(file_path: &str, limits: &ValidationLimits) -> Result<ValidationReport, ValidationError> {
+    let container = media::sniff_file(file_path)
+        .map_err(|e| ValidationError::Io(e.to_string()))?
+        .ok_or(ValidationError::UnrecognizedContainer)?;
+
+    let mut probed = media::probe_file(file_path).map_err(|_| ValidationError::UnrecognizedContainer)?;
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(ValidationError::NoAudioTrack)?;
+
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    if !ALLOWED_CODECS.contains(&params.codec) {
+        return Err(ValidationError::UnsupportedCodec(codec_name(params.codec)));
+    }
+
+    let mut sample_rate = params.sample_rate.unwrap_or(0);
+    let mut channels = params.channels.map(|c| c.count()).unwrap_or(0);
+    check_format(sample_rate, channels, limits)?;
+
+    // Reject obviously oversized files before spending time decoding them
+    if let (Some(n_frames), true) = (params.n_frames, sample_rate > 0) {
+        let seconds = n_frames as f64 / sample_rate as f64;
+        if seconds > limits.max_seconds as f64 {
+            return Err(ValidationError::TooLong { seconds, max_seconds: limits.max_seconds });
+        }
+    }
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions { verify: true })
+        .map_err(|_| ValidationError::UnsupportedCodec(codec_name(params.codec)))?;
+
+    let mut frames: u64 = 0;
+    let mut packets: u64 = 0;
+    let mut decode_errors: u64 = 0;
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(Error::IoError(e)) => return Err(ValidationError::Io(e.to_string())),
+            Err(e) => return Err(ValidationError::Corrupted(e.to_string())),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+        packets += 1;
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = decoded.spec();
+                if sample_rate == 0 {
+                    sample_rate = spec.rate;
+                }
+                if channels == 0 {
+                    channels = spec.channels.count();
+                }
+                frames += decoded.frames() as u64;
+            }
+            Err(Error::DecodeError(_)) => decode_errors += 1,
+            Err(e) => return Err(ValidationError::Corrupted(e.to_string())),
+        }
+
+        if sample_rate > 0 && frames / sample_rate as u64 > limits.max_seconds {
+            return Err(ValidationError::TooLong {
+                seconds: frames as f64 / sample_rate as f64,
+                max_seconds: limits.max_seconds,
+            });
+        }
+    }
+
+    if packets == 0 || frames == 0 {
+        return Err(ValidationError::Corrupted("no decodable audio".to_string()));
+    }
+
+    if decode_errors as f64 > packets as f64 * MAX_DECODE_ERROR_RATIO {
+        return Err(ValidationError::Corrupted(format!(
+            "{} of {} packets failed to decode",
+            decode_errors, packets
+        )));
+    }
+
+    if decoder.finalize().verify_ok == Some(false) {
+        return Err(ValidationError::Corrupted("checksum mismatch".to_string()));
+    }
+
+    // Container headers announce the length, noticeably fewer decoded frames means a cut-off file
+    if let Some(expected_frames) = params.n_frames {
+        let tolerance = (expected_frames / 100).max(sample_rate as u64 / 10);
+        if frames + tolerance < expected_frames {
+            return Err(ValidationError::Truncated { expected_frames, decoded_frames: frames });
+        }
+    }
+
+    check_format(sample_rate, channels, limits)?;
+
+    let seconds = frames as f64 / sample_rate as f64;
+    if seconds < limits.min_seconds as f64 {
+        return Err(ValidationError::TooShort { seconds, min_seconds: limits.min_seconds });
+    }
+
+    Ok(ValidationReport {
+        container,
+        codec: params.codec,
+        sample_rate,
+        channels,
+        bits_per_sample: params.bits_per_sample,
+        frames,
+    })
+}
+
+fn check_format(sample_rate: u32, channels: usize, limits: &ValidationLimits) -> Result<(), ValidationError> {
+    if sample_rate != 0 && !(MIN_SAMPLE_RATE..=limits.max_sample_rate).contains(&sample_rate) {
+        return Err(ValidationError::UnsupportedSampleRate(sample_rate));
+    }
+
+    if channels > limits.max_channels {
+        return Err(ValidationError::TooManyChannels { channels, max_channels: limits.max_channels });
+    }
+
+    Ok(())
+}
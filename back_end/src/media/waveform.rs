@@ -0,0 +1,133 @@
+//! Min/max peak data in the audiowaveform format (version 2, 8-bit, mono).
+
+use crate::media::decode;
+
+/// Finest resolution we compute, the audiowaveform default.
+const BASE_SAMPLES_PER_PIXEL: usize = 256;
+
+const DAT_VERSION: i32 = 2;
+const DAT_FLAG_8_BIT: u32 = 1;
+const DAT_HEADER_LEN: usize = 24;
+
+/// Named resolutions and the maximum number of points each one has.
+pub const RESOLUTIONS: [(&str, usize); 3] = [("low", 256), ("medium", 1024), ("high", 4096)];
+
+pub const DEFAULT_RESOLUTION: &str = "medium";
+
+pub fn is_resolution(name: &str) -> bool {
+    RESOLUTIONS.iter().any(|(resolution, _)| *resolution == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub samples_per_pixel: usize,
+    /// Interleaved min/max pairs
+    pub data: Vec<i8>,
+}
+
+impl Waveform {
+    pub fn point_count(&self) -> usize {
+        self.data.len() / 2
+    }
+
+    /// Merges every `factor` points into one.
+    fn downsample(&self, factor: usize) -> Waveform {
+        let data = self
+            .data
+            .chunks(factor * 2)
+            .flat_map(|points| {
+                let min = points.iter().step_by(2).copied().min().unwrap_or(0);
+                let max = points.iter().skip(1).step_by(2).copied().max().unwrap_or(0);
+                [min, max]
+            })
+            .collect();
+
+        Waveform {
+            sample_rate: self.sample_rate,
+            samples_per_pixel: self.samples_per_pixel * factor,
+            data,
+        }
+    }
+
+    /// Serializes to an audiowaveform `.dat` file.
+    pub fn to_dat(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DAT_HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&DAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&DAT_FLAG_8_BIT.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate as i32).to_le_bytes());
+        bytes.extend_from_slice(&(self.samples_per_pixel as i32).to_le_bytes());
+        bytes.extend_from_slice(&(self.point_count() as u32).to_le_bytes());
+        // Channels, peaks are always mixed down to mono
+        bytes.extend_from_slice(&1_i32.to_le_bytes());
+        bytes.extend(self.data.iter().map(|value| *value as u8));
+        bytes
+    }
+
+    pub fn from_dat(bytes: &[u8]) -> Result<Waveform, String> {
+        let header = |index: usize| -> Result<[u8; 4], String> {
+            bytes
+                .get(index * 4..index * 4 + 4)
+                .and_then(|field| field.try_into().ok())
+                .ok_or_else(|| "Waveform header is truncated".to_string())
+        };
+
+        if i32::from_le_bytes(header(0)?) != DAT_VERSION || u32::from_le_bytes(header(1)?) != DAT_FLAG_8_BIT {
+            return Err("Unsupported waveform data".to_string());
+        }
+
+        let sample_rate = i32::from_le_bytes(header(2)?) as u32;
+        let samples_per_pixel = i32::from_le_bytes(header(3)?) as usize;
+        let length = u32::from_le_bytes(header(4)?) as usize;
+        let data = bytes
+            .get(DAT_HEADER_LEN..DAT_HEADER_LEN + length * 2)
+            .ok_or("Waveform data is truncated")?
+            .iter()
+            .map(|value| *value as i8)
+            .collect();
+
+        Ok(Waveform { sample_rate, samples_per_pixel, data })
+    }
+}
+
+/// Decodes a file once and returns its peaks at every named resolution.
+pub fn generate_waveforms(file_path: &str) -> Result<Vec<(&'static str, Waveform)>, String> {
+    let mut data: Vec<i8> = Vec::new();
+    let mut fill = 0;
+    let (mut min, mut max) = (f32::MAX, f32::MIN);
+
+    let spec = decode::decode_file(file_path, |spec, samples| {
+        for frame in samples.chunks_exact(spec.channels) {
+            let mono = frame.iter().sum::<f32>() / spec.channels as f32;
+            min = min.min(mono);
+            max = max.max(mono);
+
+            fill += 1;
+            if fill == BASE_SAMPLES_PER_PIXEL {
+                data.extend([to_i8(min), to_i8(max)]);
+                fill = 0;
+                (min, max) = (f32::MAX, f32::MIN);
+            }
+        }
+        Ok(())
+    })?;
+
+    if fill > 0 {
+        data.extend([to_i8(min), to_i8(max)]);
+    }
+
+    let base = Waveform {
+        sample_rate: spec.sample_rate,
+        samples_per_pixel: BASE_SAMPLES_PER_PIXEL,
+        data,
+    };
+
+    Ok(RESOLUTIONS
+        .iter()
+        .map(|(resolution, max_points)| (*resolution, base.downsample(base.point_count().div_ceil(*max_points).max(1))))
+        .collect())
+}
+
+fn to_i8(sample: f32) -> i8 {
+    (sample * 127.0).round().clamp(-128.0, 127.0) as i8
+}
@@ -9,7 +9,7 @@ use sqlx::postgres::types::PgInterval;
 pub struct Duration {
     pub months: i32,
     pub days: i32,
-    pub microseconds: i32,
+    pub microseconds: i64,
 }
 
 impl Duration {
@@ -27,7 +27,7 @@ impl From<PgInterval> for Duration {
         Self {
             months: interval.months,
             days: interval.days,
-            microseconds: interval.microseconds as i32,
+            microseconds: interval.microseconds,
         }
     }
 }
@@ -47,6 +47,8 @@ pub struct User{
     pub username: String,
     pub email: String,
     pub password_hash: String,
+    pub role: String,
+    pub tier: String,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>, 
 }
@@ -106,3 +108,52 @@ pub struct UserFavorite{
 }
 
 
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub track_id: Option<Uuid>,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TrackRendition {
+    pub id: Uuid,
+    pub track_id: Uuid,
+    pub quality: String,
+    pub codec: String,
+    pub mime_type: String,
+    pub bitrate_kbps: Option<i32>,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub file_path: String,
+    pub file_size: i64,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// What goes into a downloaded copy of a track.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TrackDownload {
+    pub file_name: String,
+    pub container: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub release_year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    /// Largest JPEG variant of the artwork
+    pub artwork_file_name: Option<String>,
+    pub artwork_size: Option<i32>,
+    /// The owner, an admin, or a license that allows it
+    pub can_download: bool,
+}
@@ -1,9 +1,9 @@
 use std::sync::Arc;
 
 use axum::{extract::DefaultBodyLimit, middleware, Extension, Router};
-use tower_http::{trace::TraceLayer, services::ServeDir};
+use tower_http::trace::TraceLayer;
 
-use crate::{auth::auth, handler::{auth::auth_handler, favorites::favorites_handler, getfile::get_file_handler, history::history_handler, playlists::playlist_hanlder, upload::upload_handler, users::users_handler}, AppState};
+use crate::{auth::{admin, auth, stream_auth}, handler::{admin::admin_handler, assets::assets_handler, auth::auth_handler, favorites::favorites_handler, getfile::{get_file_handler, stream_handler}, history::history_handler, hls::hls_handler, playlists::playlist_hanlder, search::search_handler, tracks::tracks_handler, upload::upload_handler, users::users_handler}, AppState};
 
 const MAX_FILE_SIZE: usize = 6 * 1024 * 1024; // 5 MB in bytes
 
@@ -24,7 +24,8 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
     .nest(
         "/get", 
         get_file_handler()
-            .layer(middleware::from_fn(auth))        
+            .layer(middleware::from_fn(auth))
+            .merge(stream_handler().layer(middleware::from_fn(stream_auth)))
     )
     .nest(
         "/favorite", 
@@ -36,12 +37,36 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
         playlist_hanlder()
             .layer(middleware::from_fn(auth))
     )
+    .nest(
+        "/tracks",
+        tracks_handler()
+            .layer(middleware::from_fn(auth))
+    )
+    .nest(
+        "/search",
+        search_handler()
+            .layer(middleware::from_fn(auth))
+    )
     .nest(
         "/history",
         history_handler()
             .layer(middleware::from_fn(auth))
     )
-    .nest_service("/assets", ServeDir::new("assets"))
+    .nest(
+        "/hls",
+        hls_handler()
+            .layer(middleware::from_fn(stream_auth))
+    )
+    .nest(
+        "/admin",
+        admin_handler()
+            .layer(middleware::from_fn(admin))
+            .layer(middleware::from_fn(auth))
+    )
+    .nest_service(
+        "/assets",
+        assets_handler(app_state.storage.as_ref())
+    )
     .layer(TraceLayer::new_for_http())
     .layer(Extension(app_state));
 
@@ -0,0 +1,195 @@
+//! Files derived on demand, such as transcodes, kept on local disk under a
+//! size budget. The least recently used go first when it is exceeded.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use axum::body::Bytes;
+use futures::StreamExt;
+use tokio::{fs, io::AsyncReadExt, sync::watch};
+use uuid::Uuid;
+
+use crate::storage::{local::LocalStorage, ByteStream, Storage, StorageError};
+
+/// Ending of entries still being written, never served from the cache.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Largest piece read at once while following a partial file.
+const FOLLOW_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How long to wait for a partial file to grow before looking again.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Outcome of writing a partial file, `None` while it is still being written.
+pub type WriteStatus = Option<Result<(), String>>;
+
+#[derive(Debug)]
+pub struct DiskCache {
+    storage: LocalStorage,
+    budget_bytes: u64,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    /// Bumped on every use, orders the entries from least to most recently used
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    last_used: u64,
+}
+
+impl DiskCache {
+    /// Picks up the entries `prefix` below `root` already holds, oldest
+    /// first, and drops partial files left behind by a previous run.
+    pub async fn open(root: &str, prefix: &str, budget_bytes: u64) -> Result<Self, StorageError> {
+        let storage = LocalStorage::new(root);
+
+        let mut objects = storage.list(prefix).await?;
+        objects.sort_by_key(|object| object.last_modified);
+
+        let mut state = CacheState::default();
+        for object in objects {
+            if object.key.ends_with(PARTIAL_SUFFIX) {
+                storage.delete(&object.key).await?;
+                continue;
+            }
+
+            state.clock += 1;
+            state.entries.insert(object.key, CacheEntry { size: object.size, last_used: state.clock });
+        }
+
+        let cache = DiskCache { storage, budget_bytes, state: Mutex::new(state) };
+        cache.evict(None).await;
+
+        Ok(cache)
+    }
+
+    /// The cached files, with the usual range and metadata support.
+    pub fn storage(&self) -> &dyn Storage {
+        &self.storage
+    }
+
+    /// Marks `key` as just used, after a hit.
+    pub fn touch(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(entry) = state.entries.get_mut(key) {
+            entry.last_used = clock;
+        }
+    }
+
+    /// A new file to write the entry for `key` into, handed to `insert` once complete.
+    pub fn partial_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| StorageError::Backend(e.to_string()))?;
+        }
+
+        Ok(PathBuf::from(format!("{}.{}{}", path.display(), Uuid::new_v4(), PARTIAL_SUFFIX)))
+    }
+
+    /// Moves a complete partial file in place and makes room for it.
+    pub async fn insert(&self, key: &str, partial: &Path) -> Result<(), StorageError> {
+        let path = self.path(key);
+        let size = fs::metadata(partial).await.map_err(|e| StorageError::Backend(e.to_string()))?.len();
+        fs::rename(partial, &path).await.map_err(|e| StorageError::Backend(e.to_string()))?;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let last_used = state.clock;
+            state.entries.insert(key.to_string(), CacheEntry { size, last_used });
+        }
+
+        self.evict(Some(key)).await;
+
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.storage.local_path(key).expect("local storage always has paths")
+    }
+
+    /// Removes the least recently used entries until the cache fits its budget again.
+    async fn evict(&self, keep: Option<&str>) {
+        let victims = {
+            let mut state = self.state.lock().unwrap();
+            let mut total: u64 = state.entries.values().map(|entry| entry.size).sum();
+
+            let mut by_age: Vec<(u64, String)> = state
+                .entries
+                .iter()
+                .filter(|(key, _)| Some(key.as_str()) != keep)
+                .map(|(key, entry)| (entry.last_used, key.clone()))
+                .collect();
+            by_age.sort();
+
+            let mut victims = Vec::new();
+            for (_, key) in by_age {
+                if total <= self.budget_bytes {
+                    break;
+                }
+                if let Some(entry) = state.entries.remove(&key) {
+                    total -= entry.size;
+                    victims.push(key);
+                }
+            }
+            victims
+        };
+
+        // Responses still reading an evicted file keep their open handle
+        for key in victims {
+            if let Err(e) = self.storage.delete(&key).await {
+                eprintln!("Failed to evict {} from the cache: {}", key, e);
+            }
+        }
+    }
+}
+
+/// Streams a file while it is still being written, until `status` reports
+/// that the writer is done. A failed write ends the stream with an error.
+pub fn follow_partial(file: fs::File, status: watch::Receiver<WriteStatus>) -> ByteStream {
+    futures::stream::unfold(Some((file, status)), |state| async move {
+        let (mut file, mut status) = state?;
+
+        loop {
+            // Looked at before reading, so nothing written in between is missed
+            let finished = status.borrow().clone();
+
+            let mut buffer = vec![0; FOLLOW_BUFFER_SIZE];
+            let read = match file.read(&mut buffer).await {
+                Ok(read) => read,
+                Err(e) => return Some((Err(StorageError::Backend(e.to_string())), None)),
+            };
+
+            if read > 0 {
+                buffer.truncate(read);
+                return Some((Ok(Bytes::from(buffer)), Some((file, status))));
+            }
+
+            match finished {
+                Some(Ok(())) => return None,
+                Some(Err(e)) => return Some((Err(StorageError::Backend(e)), None)),
+                None => {
+                    // A writer that went away without reporting counts as failed
+                    if let Ok(Err(_)) = tokio::time::timeout(FOLLOW_POLL_INTERVAL, status.changed()).await {
+                        if status.borrow().is_none() {
+                            return Some((Err(StorageError::Backend("The writer stopped".to_string())), None));
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .boxed()
+}
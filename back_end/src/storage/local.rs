@@ -0,0 +1,198 @@
+use std::{
+    io::{self, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::storage::{ByteStream, Storage, StorageError, StoredObject};
+
+/// Largest piece read from disk at once while streaming.
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files below a directory on this machine, the layout the API always had.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> Self {
+        LocalStorage { root: PathBuf::from(root) }
+    }
+
+    /// Keys come from our own code, but `..` still never leaves the root.
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key.split('/').filter(|part| !part.is_empty() && *part != "..").collect::<PathBuf>())
+    }
+
+    /// Written aside first so a half written file is never read
+    fn temp_path(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.tmp", path.display()))
+    }
+
+    fn error(key: &str, err: io::Error) -> StorageError {
+        match err.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+            _ => StorageError::Backend(format!("{}: {}", key, err)),
+        }
+    }
+
+    async fn create_parent(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        match path.parent() {
+            Some(parent) => fs::create_dir_all(parent).await.map_err(|e| Self::error(key, e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Directories emptied by a delete go too, up to the root.
+    async fn remove_empty_parents(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if current == self.root || fs::remove_dir(current).await.is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+
+    async fn metadata(&self, key: String, path: &Path) -> io::Result<StoredObject> {
+        let metadata = fs::metadata(path).await?;
+
+        Ok(StoredObject {
+            key,
+            size: metadata.len(),
+            last_modified: metadata.modified()?.into(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        self.create_parent(key, &path).await?;
+
+        let temp_path = Self::temp_path(&path);
+        fs::write(&temp_path, data).await.map_err(|e| Self::error(key, e))?;
+        fs::rename(&temp_path, &path).await.map_err(|e| Self::error(key, e))
+    }
+
+    async fn put_file(&self, key: &str, source: &Path) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if path == source {
+            return Ok(());
+        }
+        self.create_parent(key, &path).await?;
+
+        let temp_path = Self::temp_path(&path);
+        fs::copy(source, &temp_path).await.map_err(|e| Self::error(key, e))?;
+        fs::rename(&temp_path, &path).await.map_err(|e| Self::error(key, e))
+    }
+
+    async fn get_range(&self, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>, StorageError> {
+        let path = self.path(key);
+        let Some(range) = range else {
+            return fs::read(&path).await.map_err(|e| Self::error(key, e));
+        };
+
+        let mut file = fs::File::open(&path).await.map_err(|e| Self::error(key, e))?;
+        file.seek(SeekFrom::Start(range.start)).await.map_err(|e| Self::error(key, e))?;
+
+        let mut data = Vec::new();
+        file.take(range.end.saturating_sub(range.start))
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| Self::error(key, e))?;
+
+        Ok(data)
+    }
+
+    async fn get_stream(&self, key: &str, range: Range<u64>) -> Result<ByteStream, StorageError> {
+        let mut file = fs::File::open(self.path(key)).await.map_err(|e| Self::error(key, e))?;
+        file.seek(SeekFrom::Start(range.start)).await.map_err(|e| Self::error(key, e))?;
+
+        let key = key.to_string();
+        let reader = file.take(range.end.saturating_sub(range.start));
+
+        Ok(ReaderStream::with_capacity(reader, STREAM_BUFFER_SIZE)
+            .map_err(move |e| Self::error(&key, e))
+            .boxed())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if path == destination {
+            return Ok(());
+        }
+
+        fs::copy(&path, destination).await.map(|_| ()).map_err(|e| Self::error(key, e))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<StoredObject>, StorageError> {
+        match self.metadata(key.to_string(), &self.path(key)).await {
+            Ok(object) => Ok(Some(object)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Self::error(key, e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path(key);
+        match fs::remove_file(&path).await {
+            Ok(()) => {
+                self.remove_empty_parents(&path).await;
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Self::error(key, e)),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        let prefix = prefix.trim_end_matches('/');
+        let mut objects = Vec::new();
+        let mut pending = vec![prefix.to_string()];
+
+        while let Some(dir_key) = pending.pop() {
+            let mut entries = match fs::read_dir(self.path(&dir_key)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Self::error(&dir_key, e)),
+            };
+
+            while let Some(entry) = entries.next_entry().await.map_err(|e| Self::error(&dir_key, e))? {
+                let key = format!("{}/{}", dir_key, entry.file_name().to_string_lossy());
+                let file_type = entry.file_type().await.map_err(|e| Self::error(&key, e))?;
+                if file_type.is_dir() {
+                    pending.push(key);
+                } else if file_type.is_file() {
+                    match self.metadata(key.clone(), &entry.path()).await {
+                        Ok(object) => objects.push(object),
+                        // Deleted while we were looking
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(Self::error(&key, e)),
+                    }
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn presign(&self, _key: &str, _expires_in: Duration) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
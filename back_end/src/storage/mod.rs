@@ -0,0 +1,164 @@
+//! Where uploads, renditions and artwork are kept.
+//!
+//! Keys are relative paths such as `uploads/{file_name}`, the local backend
+//! stores them as files below its root, the S3 backend as objects in a bucket.
+
+pub mod cache;
+pub mod local;
+pub mod s3;
+
+use std::{
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+
+use crate::{config::StorageConfig, AppState};
+
+/// Bytes of an object as they arrive, in pieces of bounded size.
+pub type ByteStream = BoxStream<'static, Result<Bytes, StorageError>>;
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound(String),
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::NotFound(key) => write!(f, "{} not found in storage", key),
+            StorageError::Backend(message) => write!(f, "Storage error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[async_trait]
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Stores `data` under `key`, replacing what was there.
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError>;
+
+    /// Stores a local file under `key` without reading it into memory.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError>;
+
+    /// Reads `range` of the object, or all of it.
+    async fn get_range(&self, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>, StorageError>;
+
+    /// Streams the non-empty `range` of the object without holding it in memory.
+    async fn get_stream(&self, key: &str, range: Range<u64>) -> Result<ByteStream, StorageError>;
+
+    /// Copies the object into a local file.
+    async fn get_file(&self, key: &str, path: &Path) -> Result<(), StorageError>;
+
+    async fn head(&self, key: &str) -> Result<Option<StoredObject>, StorageError>;
+
+    /// Removes the object, keys that don't exist are not an error.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// Every object below the `prefix` directory, at any depth.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError>;
+
+    /// A URL clients can fetch the object from directly, `None` when the
+    /// backend can't hand out such URLs and the API has to serve the bytes.
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<Option<String>, StorageError>;
+
+    /// Where `key` lives on this machine, for backends that keep files locally.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub fn from_config(storage: &StorageConfig) -> Result<Arc<dyn Storage>, StorageError> {
+    match storage {
+        StorageConfig::Local { root } => Ok(Arc::new(local::LocalStorage::new(root))),
+        StorageConfig::S3 { .. } => Ok(Arc::new(s3::S3Storage::new(storage)?)),
+    }
+}
+
+/// Removes every object below `prefix`.
+pub async fn delete_prefix(storage: &dyn Storage, prefix: &str) -> Result<(), StorageError> {
+    for object in storage.list(prefix).await? {
+        storage.delete(&object.key).await?;
+    }
+
+    Ok(())
+}
+
+/// A local file standing in for a stored object, for work that needs a
+/// path such as decoding.
+///
+/// With local storage it is the stored file itself. Otherwise it is a
+/// scratch copy under `STORAGE_WORK_DIR` that is removed when dropped.
+#[derive(Debug)]
+pub struct WorkingFile {
+    path: PathBuf,
+    scratch: bool,
+}
+
+impl WorkingFile {
+    /// Reserves the local path for `key` without fetching anything, for
+    /// files that are produced here and stored afterwards.
+    pub fn for_key(app_state: &AppState, key: &str) -> Result<Self, StorageError> {
+        let (path, scratch) = match app_state.storage.local_path(key) {
+            Some(path) => (path, false),
+            None => (Path::new(&app_state.env.storage_work_dir).join(key), true),
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| StorageError::Backend(e.to_string()))?;
+        }
+
+        Ok(WorkingFile { path, scratch })
+    }
+
+    /// The object stored under `key`, copied here if it isn't local already.
+    pub async fn fetch(app_state: &AppState, key: &str) -> Result<Self, StorageError> {
+        let working_file = Self::for_key(app_state, key)?;
+        if working_file.scratch {
+            app_state.storage.get_file(key, &working_file.path).await?;
+        }
+
+        Ok(working_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    /// Stores the file under `key`, a no-op when it already is the stored file.
+    pub async fn persist(&self, app_state: &AppState, key: &str) -> Result<(), StorageError> {
+        if !self.scratch {
+            return Ok(());
+        }
+
+        app_state.storage.put_file(key, &self.path).await
+    }
+}
+
+impl Drop for WorkingFile {
+    fn drop(&mut self) {
+        if self.scratch {
+            let _ = if self.path.is_dir() { fs::remove_dir_all(&self.path) } else { fs::remove_file(&self.path) };
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{ops::Range, path::Path as LocalPath, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::http::Method;
+use futures::{StreamExt, TryStreamExt};
+use object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    buffered::BufWriter,
+    path::Path,
+    signer::Signer,
+    Attribute, Attributes, GetOptions, ObjectMeta, ObjectStore, PutPayload,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config::StorageConfig,
+    storage::{ByteStream, Storage, StorageError, StoredObject},
+};
+
+/// Objects in an S3 compatible bucket, AWS itself or e.g. MinIO.
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    store: Arc<AmazonS3>,
+}
+
+impl S3Storage {
+    pub fn new(config: &StorageConfig) -> Result<Self, StorageError> {
+        let StorageConfig::S3 { bucket, region, endpoint, access_key_id, secret_access_key } = config else {
+            return Err(StorageError::Backend("Not an S3 storage configuration".to_string()));
+        };
+
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(bucket)
+            .with_region(region);
+
+        // MinIO and friends are addressed by path and usually served over plain HTTP
+        if let Some(endpoint) = endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(access_key_id) = access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+
+        let store = builder.build().map_err(|e| StorageError::Backend(e.to_string()))?;
+
+        Ok(S3Storage { store: Arc::new(store) })
+    }
+
+    fn error(key: &str, err: object_store::Error) -> StorageError {
+        match err {
+            object_store::Error::NotFound { .. } => StorageError::NotFound(key.to_string()),
+            err => StorageError::Backend(format!("{}: {}", key, err)),
+        }
+    }
+
+    /// Objects are served to browsers through signed URLs, so they need a type.
+    fn attributes(key: &str) -> Attributes {
+        let mut attributes = Attributes::new();
+        if let Some(mime) = mime_guess::from_path(key).first() {
+            attributes.insert(Attribute::ContentType, mime.to_string().into());
+        }
+        attributes
+    }
+
+    fn object(meta: ObjectMeta) -> StoredObject {
+        StoredObject {
+            key: meta.location.to_string(),
+            size: meta.size as u64,
+            last_modified: meta.last_modified,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        self.store
+            .put_opts(&Path::from(key), PutPayload::from(data), Self::attributes(key).into())
+            .await
+            .map(|_| ())
+            .map_err(|e| Self::error(key, e))
+    }
+
+    async fn put_file(&self, key: &str, path: &LocalPath) -> Result<(), StorageError> {
+        let backend = |e: std::io::Error| StorageError::Backend(format!("{}: {}", key, e));
+
+        let mut file = tokio::fs::File::open(path).await.map_err(backend)?;
+        // Large files go up as a multipart upload
+        let mut writer = BufWriter::new(self.store.clone(), Path::from(key)).with_attributes(Self::attributes(key));
+        tokio::io::copy(&mut file, &mut writer).await.map_err(backend)?;
+        writer.shutdown().await.map_err(backend)
+    }
+
+    async fn get_range(&self, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>, StorageError> {
+        let location = Path::from(key);
+        let data = match range {
+            Some(range) => self.store.get_range(&location, range.start as usize..range.end as usize).await,
+            None => match self.store.get(&location).await {
+                Ok(result) => result.bytes().await,
+                Err(e) => Err(e),
+            },
+        };
+
+        data.map(|data| data.to_vec()).map_err(|e| Self::error(key, e))
+    }
+
+    async fn get_stream(&self, key: &str, range: Range<u64>) -> Result<ByteStream, StorageError> {
+        let options = GetOptions {
+            range: Some((range.start as usize..range.end as usize).into()),
+            ..Default::default()
+        };
+
+        let stream = self.store
+            .get_opts(&Path::from(key), options)
+            .await
+            .map_err(|e| Self::error(key, e))?
+            .into_stream();
+
+        let key = key.to_string();
+        Ok(stream.map_err(move |e| Self::error(&key, e)).boxed())
+    }
+
+    async fn get_file(&self, key: &str, path: &LocalPath) -> Result<(), StorageError> {
+        let backend = |e: std::io::Error| StorageError::Backend(format!("{}: {}", key, e));
+
+        let mut stream = self.store
+            .get(&Path::from(key))
+            .await
+            .map_err(|e| Self::error(key, e))?
+            .into_stream();
+
+        let mut file = tokio::fs::File::create(path).await.map_err(backend)?;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| Self::error(key, e))?;
+            file.write_all(&chunk).await.map_err(backend)?;
+        }
+        file.flush().await.map_err(backend)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<StoredObject>, StorageError> {
+        match self.store.head(&Path::from(key)).await {
+            Ok(meta) => Ok(Some(Self::object(meta))),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(Self::error(key, e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self.store.delete(&Path::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(Self::error(key, e)),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        self.store
+            .list(Some(&Path::from(prefix)))
+            .map_ok(Self::object)
+            .try_collect()
+            .await
+            .map_err(|e| Self::error(prefix, e))
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<Option<String>, StorageError> {
+        self.store
+            .signed_url(Method::GET, &Path::from(key), expires_in)
+            .await
+            .map(|url| Some(url.to_string()))
+            .map_err(|e| Self::error(key, e))
+    }
+}
@@ -1,2 +1,6 @@
 pub mod password; 
-pub mod token;
\ No newline at end of file
+pub mod token;
+pub mod range;
+pub mod signed_url;
+pub mod stream_limits;
+pub mod stream_sessions;
\ No newline at end of file
@@ -0,0 +1,153 @@
+//! HTTP Range and conditional request handling for streamed files.
+
+use std::ops::Range;
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Utc};
+
+use crate::storage::StoredObject;
+
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// No usable Range header, the whole file is sent
+    Full,
+    /// A single satisfiable byte range, end exclusive
+    Partial(Range<u64>),
+    /// The range lies outside the file, answered with 416
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header against a file of `size` bytes.
+///
+/// Supports `bytes=start-end`, `bytes=start-` and suffix ranges such as
+/// `bytes=-500`. Headers that are malformed, use another unit or ask for
+/// several ranges are ignored, as RFC 9110 allows.
+pub fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    match (start.trim(), end.trim()) {
+        ("", "") => RangeRequest::Full,
+        // The last `length` bytes
+        ("", length) => match length.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+            Ok(length) => RangeRequest::Partial(size.saturating_sub(length)..size),
+            Err(_) => RangeRequest::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => None,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => Some(end),
+                    _ => return RangeRequest::Full,
+                },
+            };
+
+            if start >= size {
+                return RangeRequest::Unsatisfiable;
+            }
+            // An end past the file is cut to the file
+            let end = end.map_or(size, |end| end.saturating_add(1).min(size));
+            RangeRequest::Partial(start..end)
+        }
+    }
+}
+
+/// Strong validator built from the size and modification time.
+pub fn etag(object: &StoredObject) -> String {
+    format!("\"{:x}-{:x}\"", object.size, object.last_modified.timestamp_millis())
+}
+
+pub fn http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim()).ok().map(|date| date.with_timezone(&Utc))
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Whether the client's copy is still current, answered with 304.
+///
+/// `If-None-Match` wins over `If-Modified-Since` and compares weakly.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: &DateTime<Utc>) -> bool {
+    if let Some(value) = header_str(headers, header::IF_NONE_MATCH) {
+        return value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    match header_str(headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date) {
+        Some(since) => last_modified.timestamp() <= since.timestamp(),
+        None => false,
+    }
+}
+
+/// Whether a Range header may be honoured. With `If-Range` it only is when
+/// the file hasn't changed since, otherwise the whole file is sent.
+pub fn is_range_allowed(headers: &HeaderMap, etag: &str, last_modified: &DateTime<Utc>) -> bool {
+    let Some(value) = header_str(headers, header::IF_RANGE).map(str::trim) else {
+        return true;
+    };
+
+    // Entity tags are compared strongly here, weak ones never match
+    if value.starts_with('"') || value.starts_with("W/") {
+        return value == etag;
+    }
+
+    parse_http_date(value).is_some_and(|date| date.timestamp() == last_modified.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-499", 1000), RangeRequest::Partial(0..500));
+        assert_eq!(parse_range("bytes=500-", 1000), RangeRequest::Partial(500..1000));
+        assert_eq!(parse_range(" bytes= 10 - 19 ", 1000), RangeRequest::Partial(10..20));
+        assert_eq!(parse_range("bytes=-200", 1000), RangeRequest::Partial(800..1000));
+    }
+
+    #[test]
+    fn cuts_ranges_to_the_file() {
+        assert_eq!(parse_range("bytes=900-5000", 1000), RangeRequest::Partial(900..1000));
+        assert_eq!(parse_range("bytes=-5000", 1000), RangeRequest::Partial(0..1000));
+        assert_eq!(parse_range("bytes=0-18446744073709551615", 1000), RangeRequest::Partial(0..1000));
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_file() {
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_headers_it_does_not_support() {
+        assert_eq!(parse_range("items=0-10", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=-", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=10", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=20-10", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-10", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=-x", 1000), RangeRequest::Full);
+    }
+}
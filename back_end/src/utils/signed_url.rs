@@ -0,0 +1,166 @@
+//! Short-lived URLs for clients that can't send an `Authorization` header,
+//! such as `<audio>` elements and native media players.
+//!
+//! The query carries the path prefix it grants, the user, the login session
+//! it was minted from and the expiry, signed with HMAC-SHA256 so they can be checked without the database.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Characters escaped in path segments and query values.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+#[derive(Debug, Deserialize)]
+pub struct SignedUrlParams {
+    pub scope: Option<String>,
+    pub uid: Option<Uuid>,
+    pub sid: Option<Uuid>,
+    pub exp: Option<i64>,
+    pub sig: Option<String>,
+}
+
+impl SignedUrlParams {
+    pub fn is_signed(&self) -> bool {
+        self.sig.is_some()
+    }
+}
+
+fn encode_component(value: &str) -> String {
+    utf8_percent_encode(value, COMPONENT).to_string()
+}
+
+fn signature(key: &[u8], scope: &str, user_id: Uuid, session_id: Uuid, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}\n{}\n{}", scope, user_id, session_id, expires).as_bytes());
+    mac
+}
+
+/// Query string granting `user_id` access to the path `scope` until `expires`
+/// (a Unix timestamp), on behalf of the login `session_id`. A scope ending in
+/// `/` covers everything below it.
+pub fn signed_query(key: &[u8], scope: &str, user_id: Uuid, session_id: Uuid, expires: i64) -> String {
+    let sig = hex::encode(signature(key, scope, user_id, session_id, expires).finalize().into_bytes());
+
+    format!(
+        "scope={}&uid={}&sid={}&exp={}&sig={}",
+        encode_component(scope),
+        user_id,
+        session_id,
+        expires,
+        sig
+    )
+}
+
+/// The user and login session a signed URL was minted for, if it is valid
+/// for `path` right now.
+pub fn verify(key: &[u8], params: &SignedUrlParams, path: &str) -> Option<(Uuid, Uuid)> {
+    let (Some(scope), Some(user_id), Some(session_id), Some(expires), Some(sig)) =
+        (&params.scope, params.uid, params.sid, params.exp, &params.sig)
+    else {
+        return None;
+    };
+
+    let in_scope = path == scope || (scope.ends_with('/') && path.starts_with(scope.as_str()));
+    if expires < Utc::now().timestamp() || !in_scope {
+        return None;
+    }
+
+    let sig = hex::decode(sig).ok()?;
+    // Compared in constant time
+    signature(key, scope, user_id, session_id, expires).verify_slice(&sig).ok()?;
+
+    Some((user_id, session_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"test key";
+
+    fn params(query: &str) -> SignedUrlParams {
+        let mut params = SignedUrlParams { scope: None, uid: None, sid: None, exp: None, sig: None };
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            let value = percent_encoding::percent_decode_str(value).decode_utf8().unwrap().to_string();
+            match name {
+                "scope" => params.scope = Some(value),
+                "uid" => params.uid = value.parse().ok(),
+                "sid" => params.sid = value.parse().ok(),
+                "exp" => params.exp = value.parse().ok(),
+                "sig" => params.sig = Some(value),
+                _ => {}
+            }
+        }
+        params
+    }
+
+    #[test]
+    fn verifies_its_own_signature() {
+        let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let expires = Utc::now().timestamp() + 60;
+        let query = signed_query(KEY, "/api/get/play/1", user_id, session_id, expires);
+
+        assert_eq!(verify(KEY, &params(&query), "/api/get/play/1"), Some((user_id, session_id)));
+    }
+
+    #[test]
+    fn prefix_scopes_cover_paths_below_them() {
+        let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let expires = Utc::now().timestamp() + 60;
+        let prefix = params(&signed_query(KEY, "/api/hls/1/", user_id, session_id, expires));
+
+        assert!(verify(KEY, &prefix, "/api/hls/1/high/0.mp3").is_some());
+        assert!(verify(KEY, &prefix, "/api/hls/2/high/0.mp3").is_none());
+
+        let exact = params(&signed_query(KEY, "/api/get/play/1", user_id, session_id, expires));
+        assert!(verify(KEY, &exact, "/api/get/play/12").is_none());
+    }
+
+    #[test]
+    fn rejects_expired_urls() {
+        let query = signed_query(KEY, "/a", Uuid::new_v4(), Uuid::new_v4(), Utc::now().timestamp() - 1);
+
+        assert!(verify(KEY, &params(&query), "/a").is_none());
+    }
+
+    #[test]
+    fn rejects_tampered_urls() {
+        let expires = Utc::now().timestamp() + 60;
+        let query = signed_query(KEY, "/a", Uuid::new_v4(), Uuid::new_v4(), expires);
+
+        let mut other_user = params(&query);
+        other_user.uid = Some(Uuid::new_v4());
+        assert!(verify(KEY, &other_user, "/a").is_none());
+
+        let mut other_session = params(&query);
+        other_session.sid = Some(Uuid::new_v4());
+        assert!(verify(KEY, &other_session, "/a").is_none());
+
+        let mut later = params(&query);
+        later.exp = Some(expires + 3600);
+        assert!(verify(KEY, &later, "/a").is_none());
+
+        let mut wider = params(&query);
+        wider.scope = Some("/".to_string());
+        assert!(verify(KEY, &wider, "/a").is_none());
+
+        assert!(verify(b"other key", &params(&query), "/a").is_none());
+    }
+
+    #[test]
+    fn rejects_incomplete_queries() {
+        let query = signed_query(KEY, "/a", Uuid::new_v4(), Uuid::new_v4(), Utc::now().timestamp() + 60);
+
+        let mut unsigned = params(&query);
+        unsigned.sig = Some("not hex".to_string());
+        assert!(verify(KEY, &unsigned, "/a").is_none());
+
+        let mut without_session = params(&query);
+        without_session.sid = None;
+        assert!(verify(KEY, &without_session, "/a").is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Concurrent stream leases and bandwidth caps of the plan tiers.
+//!
+//! Every device that streams holds a lease that its requests keep renewing.
+//! A device is told apart by the login session in its token or signed URL,
+//! which the server issues and signs, so the range requests of one player
+//! share a lease and clients can't pick their own.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::http::StatusCode;
+use futures::StreamExt;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{
+    databases::{streams::StreamLeaseExt, users::UserExt},
+    errors::{ErrorMessage, HttpError},
+    storage::ByteStream,
+    AppState,
+};
+
+/// Seconds of audio sent at full speed before the cap applies, so playback starts quickly.
+const BURST_SECONDS: u64 = 5;
+
+/// Takes a lease for the device of login `session_id` streaming `track_id`,
+/// returns its key and the bandwidth cap of the user's tier.
+pub async fn acquire_lease(
+    app_state: &AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    track_id: Uuid,
+) -> Result<(String, u64), HttpError> {
+    let user = app_state
+        .db_client
+        .get_user(Some(user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string()))?;
+    let limits = app_state.env.stream_limits(&user.tier);
+
+    let session_key = session_id.to_string();
+    let acquired = app_state
+        .db_client
+        .acquire_stream_lease(user_id, &session_key, track_id, app_state.env.stream_lease_seconds, limits.max_streams)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !acquired {
+        return Err(HttpError::new(
+            format!(
+                "Your {} plan allows streaming on {} device(s) at a time, stop playback on another device first",
+                user.tier, limits.max_streams
+            ),
+            StatusCode::TOO_MANY_REQUESTS,
+        ));
+    }
+
+    Ok((session_key, limits.bandwidth_kbps))
+}
+
+/// Renews the lease while a long response is still being sent.
+pub fn keep_lease_alive(stream: ByteStream, app_state: Arc<AppState>, user_id: Uuid, session_key: String) -> ByteStream {
+    let lease_seconds = app_state.env.stream_lease_seconds;
+    let interval = Duration::from_secs((lease_seconds / 2).max(1));
+    let mut last_renewed = Instant::now();
+
+    stream
+        .then(move |chunk| {
+            let renew = last_renewed.elapsed() >= interval;
+            if renew {
+                last_renewed = Instant::now();
+            }
+            let app_state = app_state.clone();
+            let session_key = session_key.clone();
+
+            async move {
+                if renew {
+                    if let Err(e) = app_state.db_client.renew_stream_lease(user_id, &session_key, lease_seconds).await {
+                        eprintln!("Failed to renew stream lease of user {}: {}", user_id, e);
+                    }
+                }
+                chunk
+            }
+        })
+        .boxed()
+}
+
+/// Holds chunks back so the stream averages at most `kbps` after the initial burst.
+pub fn throttle(stream: ByteStream, kbps: u64) -> ByteStream {
+    if kbps == 0 {
+        return stream;
+    }
+
+    let bytes_per_second = kbps * 1000 / 8;
+    let burst = bytes_per_second * BURST_SECONDS;
+    let started = Instant::now();
+    let mut sent = 0u64;
+
+    stream
+        .then(move |chunk| {
+            let due = chunk.as_ref().ok().map(|bytes| {
+                sent += bytes.len() as u64;
+                started + Duration::from_secs_f64(sent.saturating_sub(burst) as f64 / bytes_per_second as f64)
+            });
+
+            async move {
+                if let Some(due) = due {
+                    tokio::time::sleep_until(due).await;
+                }
+                chunk
+            }
+        })
+        .boxed()
+}
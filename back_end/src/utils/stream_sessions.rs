@@ -0,0 +1,115 @@
+//! Stream sessions, from which plays are counted on the server.
+//!
+//! The range requests one device sends for a track are merged into a session
+//! as long as they follow each other within the stream lease. A session
+//! counts as a play once it delivered `PLAY_MIN_SECONDS` of audio or
+//! `PLAY_MIN_PERCENT` of the file, whichever comes first.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{
+    databases::streams::{StreamRequest, StreamSessionExt},
+    errors::HttpError,
+    storage::ByteStream,
+    AppState,
+};
+
+/// How often the bytes sent so far are written down while a response is sent.
+const RECORD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Session of the device holding `session_key` that `request` belongs to.
+pub async fn open_session(
+    app_state: &AppState,
+    user_id: Uuid,
+    track_id: Uuid,
+    session_key: &str,
+    request: &StreamRequest<'_>,
+) -> Result<Uuid, HttpError> {
+    app_state
+        .db_client
+        .open_stream_session(user_id, track_id, session_key, request, app_state.env.stream_lease_seconds)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Records what of `stream` reaches the client, `offset` being the position
+/// of its first byte in the file.
+pub fn record_delivery(stream: ByteStream, app_state: Arc<AppState>, session_id: Uuid, offset: u64) -> ByteStream {
+    RecordedStream {
+        inner: stream,
+        app_state,
+        session_id,
+        recorded: offset,
+        position: offset,
+        last_recorded: Instant::now(),
+    }
+    .boxed()
+}
+
+struct RecordedStream {
+    inner: ByteStream,
+    app_state: Arc<AppState>,
+    session_id: Uuid,
+    /// Bytes before this one are written down
+    recorded: u64,
+    position: u64,
+    last_recorded: Instant,
+}
+
+impl RecordedStream {
+    fn record(&mut self) {
+        if self.position == self.recorded {
+            return;
+        }
+
+        let (start, end) = (self.recorded, self.position);
+        self.recorded = self.position;
+        self.last_recorded = Instant::now();
+
+        let app_state = self.app_state.clone();
+        let session_id = self.session_id;
+        tokio::spawn(async move {
+            let env = &app_state.env;
+            if let Err(e) = app_state
+                .db_client
+                .record_stream_delivery(session_id, start, end, env.play_min_seconds, env.play_min_percent)
+                .await
+            {
+                eprintln!("Failed to record delivery of stream session {}: {}", session_id, e);
+            }
+        });
+    }
+}
+
+impl Stream for RecordedStream {
+    type Item = <ByteStream as Stream>::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+
+        if let Poll::Ready(Some(Ok(bytes))) = &poll {
+            self.position += bytes.len() as u64;
+            if self.last_recorded.elapsed() >= RECORD_INTERVAL {
+                self.record();
+            }
+        }
+
+        poll
+    }
+}
+
+// Runs when the response is done as well as when the client goes away mid-stream
+impl Drop for RecordedStream {
+    fn drop(&mut self) {
+        self.record();
+    }
+}
@@ -2,12 +2,18 @@ use axum::http::StatusCode;
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
 use crate::errors::{ErrorMessage, HttpError};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String,
+    /// Session of the login the token was issued for, tells devices apart.
+    /// Missing from tokens issued before sessions were recorded.
+    #[serde(default)]
+    pub sid: Option<Uuid>,
     pub iat: usize,
     pub exp: usize,
 }
@@ -22,6 +28,7 @@ pub fn create_token (user_id: &str, secret: &[u8], expires_in_seconds: i64) -> R
     let exp = (now + Duration::seconds(expires_in_seconds)).timestamp() as usize;
     let claims = TokenClaims {
         sub: user_id.to_string(),
+        sid: Some(Uuid::new_v4()),
         iat,
         exp,
     };
@@ -33,11 +40,46 @@ pub fn create_token (user_id: &str, secret: &[u8], expires_in_seconds: i64) -> R
     )
 }
 
-pub fn decode_token<T: Into<String>>(token: T, secret: &[u8]) -> Result<String, HttpError> {
+pub fn decode_token<T: Into<String>>(token: T, secret: &[u8]) -> Result<TokenClaims, HttpError> {
     let decoded = decode::<TokenClaims>(&token.into(), &DecodingKey::from_secret(secret), &Validation::new(Algorithm::HS256));
 
     match decoded {
-        Ok(token_data) => Ok(token_data.claims.sub),
+        Ok(token_data) => Ok(token_data.claims),
         Err(_) => Err(HttpError::new(ErrorMessage::InvalidToken.to_string(), StatusCode::UNAUTHORIZED)),
     }
+}
+
+/// Stands in for the session of a token without one, derived from the token
+/// so every request carrying it counts as the same device.
+pub fn fallback_session_id(token: &str) -> Uuid {
+    let digest = Sha256::digest(token.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Uuid::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+
+    #[test]
+    fn tokens_without_session_still_decode() {
+        let now = Utc::now().timestamp() as usize;
+        let claims = serde_json::json!({ "sub": Uuid::new_v4().to_string(), "iat": now, "exp": now + 60 });
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+
+        assert!(decode_token(token.as_str(), SECRET).unwrap().sid.is_none());
+        assert_eq!(fallback_session_id(&token), fallback_session_id(&token));
+    }
+
+    #[test]
+    fn fallback_session_differs_per_token() {
+        let first = create_token("user", SECRET, 60).unwrap();
+        let second = create_token("user", SECRET, 60).unwrap();
+
+        assert!(decode_token(first.as_str(), SECRET).unwrap().sid.is_some());
+        assert_ne!(fallback_session_id(&first), fallback_session_id(&second));
+    }
 }
\ No newline at end of file